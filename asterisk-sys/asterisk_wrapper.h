@@ -2,11 +2,17 @@
 
 #include "asterisk.h"
 #include "asterisk/astobj2.h"
+#include "asterisk/causes.h"
 #include "asterisk/channel.h"
+#include "asterisk/cli.h"
 #include "asterisk/frame.h"
 #include "asterisk/format_cache.h"
+#include "asterisk/indications.h"
 #include "asterisk/logger.h"
+#include "asterisk/manager.h"
 #include "asterisk/module.h"
+#include "asterisk/musiconhold.h"
+#include "asterisk/pbx.h"
 #include "asterisk/rtp_engine.h"
 #include "asterisk/stasis_channels.h"
 #include "jitterbuf.h"
\ No newline at end of file
@@ -15,6 +15,9 @@ fn main() {
         .allowlist_item("__ast_.*")
         .allowlist_item("__ao2_.*")
         .allowlist_item("AST_.*")
+        .allowlist_item("astman_.*")
+        .allowlist_item("pbx_.*")
+        .allowlist_item("EVENT_FLAG_.*")
         .allowlist_item("jb.*")
         .default_macro_constant_type(bindgen::MacroTypeVariation::Signed)
         .parse_callbacks(Box::new(bindgen::CargoCallbacks::new()))
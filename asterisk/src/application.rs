@@ -0,0 +1,31 @@
+use std::ffi::{c_char, c_int, CStr};
+
+use asterisk_sys::bindings::{
+    ast_channel, ast_module, ast_register_application2, ast_unregister_application,
+};
+
+use crate::{asterisk_call, AsteriskError};
+
+pub type ApplicationHandler = unsafe extern "C" fn(*mut ast_channel, *const c_char) -> c_int;
+
+/// Registers a dialplan application [name], which runs [handler] with the channel executing it
+/// and its arguments.
+pub unsafe fn register(
+    name: &'static CStr,
+    handler: ApplicationHandler,
+    synopsis: &'static CStr,
+    description: &'static CStr,
+    module: *mut ast_module,
+) -> Result<(), AsteriskError> {
+    asterisk_call(ast_register_application2(
+        name.as_ptr(),
+        Some(handler),
+        synopsis.as_ptr(),
+        description.as_ptr(),
+        module.cast(),
+    ))
+}
+
+pub unsafe fn unregister(name: &'static CStr) {
+    ast_unregister_application(name.as_ptr());
+}
@@ -1,10 +1,21 @@
-use std::{os::raw::c_void, ptr};
+use std::{
+    ffi::{c_char, c_int, CStr, CString},
+    mem::MaybeUninit,
+    os::raw::c_void,
+    ptr,
+};
 
 use asterisk_sys::bindings::{
-    ast_channel, ast_channel_nativeformats, ast_channel_nativeformats_set,
-    ast_channel_set_readformat, ast_channel_set_writeformat, ast_channel_stage_snapshot,
-    ast_channel_stage_snapshot_done, ast_channel_tech_pvt, ast_channel_tech_pvt_set,
-    ast_control_frame_type, ast_frame, ast_queue_control, ast_queue_frame, ast_queue_hangup,
+    ast_channel, ast_channel_bridge_peer, ast_channel_connected, ast_channel_get_by_name,
+    ast_channel_name, ast_channel_nativeformats, ast_channel_nativeformats_set,
+    ast_channel_queue_connected_line_update, ast_channel_set_readformat,
+    ast_channel_set_writeformat, ast_channel_stage_snapshot, ast_channel_stage_snapshot_done,
+    ast_channel_tech, ast_channel_tech_pvt, ast_channel_tech_pvt_set, ast_channel_uniqueid,
+    ast_control_frame_type, ast_frame, ast_moh_start, ast_moh_stop, ast_party_connected_line,
+    ast_party_connected_line_init, ast_party_name, ast_party_number, ast_playtones_stop,
+    ast_queue_control, ast_queue_frame, ast_queue_hangup, ast_queue_hangup_with_cause,
+    ast_set_callerid, ast_set_party_connected_line, pbx_builtin_getvar_helper,
+    pbx_builtin_setvar_helper,
 };
 
 use crate::{
@@ -14,9 +25,81 @@ use crate::{
 
 #[repr(transparent)]
 pub struct Channel(pub ast_channel);
+
+/// The name and number of a party, as far as they are valid.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PartyId {
+    pub name: Option<String>,
+    pub number: Option<String>,
+}
 unsafe impl AsteriskWrapper<ast_channel> for Channel {}
 
 impl Channel {
+    /// Looks up a channel by its name, returning a new reference to it.
+    pub fn get_by_name(name: &CStr) -> Option<Ao2<Channel>> {
+        let raw = unsafe { ast_channel_get_by_name(name.as_ptr()) };
+        Some(Channel::from_obj(unsafe { Ao2::try_from_raw(raw)? }))
+    }
+
+    pub fn name(&self) -> &str {
+        let name = unsafe { CStr::from_ptr(ast_channel_name(ptr::addr_of!(self.0))) };
+        name.to_str().unwrap_or_default()
+    }
+
+    pub fn unique_id(&self) -> &str {
+        let id = unsafe { CStr::from_ptr(ast_channel_uniqueid(ptr::addr_of!(self.0))) };
+        id.to_str().unwrap_or_default()
+    }
+
+    /// The party this channel is connected to. For outgoing channels, `Dial` sets it to the caller
+    /// id of the calling channel before placing the call. The channel needs to be locked while
+    /// calling this.
+    pub fn connected_line(&self) -> PartyId {
+        let connected = unsafe { &*ast_channel_connected(ptr::addr_of!(self.0).cast_mut()) };
+        PartyId {
+            name: party_name(&connected.id.name),
+            number: party_number(&connected.id.number),
+        }
+    }
+
+    /// Reads a channel variable. The channel needs to be locked while calling this.
+    pub fn get_variable(&self, name: &CStr) -> Option<String> {
+        let value =
+            unsafe { pbx_builtin_getvar_helper(ptr::addr_of!(self.0).cast_mut(), name.as_ptr()) };
+        if value.is_null() {
+            return None;
+        }
+
+        let value = unsafe { CStr::from_ptr(value) };
+        Some(value.to_string_lossy().into_owned())
+    }
+
+    /// Sets a channel variable. This locks the channel, unless the calling thread has already
+    /// locked it.
+    pub fn set_variable(&self, name: &CStr, value: &str) {
+        let Ok(value) = CString::new(value) else {
+            return;
+        };
+
+        unsafe {
+            pbx_builtin_setvar_helper(
+                ptr::addr_of!(self.0).cast_mut(),
+                name.as_ptr(),
+                value.as_ptr(),
+            )
+        };
+    }
+
+    pub fn tech(&self) -> *const ast_channel_tech {
+        unsafe { ast_channel_tech(ptr::addr_of!(self.0)) }
+    }
+
+    /// Returns the channel this channel is talking to, if it's in a two-party bridge.
+    pub fn bridge_peer(&self) -> Option<Ao2<Channel>> {
+        let raw = unsafe { ast_channel_bridge_peer(ptr::addr_of!(self.0).cast_mut()) };
+        Some(Channel::from_obj(unsafe { Ao2::try_from_raw(raw)? }))
+    }
+
     pub fn set_readformat(&mut self, format: &Format) {
         unsafe {
             ast_channel_set_readformat(
@@ -62,6 +145,11 @@ impl Channel {
         unsafe { ast_queue_hangup(ptr::addr_of!(self.0).cast_mut()) };
     }
 
+    /// Queues a hangup with one of the `AST_CAUSE_*` causes.
+    pub fn queue_hangup_with_cause(&self, cause: c_int) {
+        unsafe { ast_queue_hangup_with_cause(ptr::addr_of!(self.0).cast_mut(), cause) };
+    }
+
     pub fn queue_control(&self, control: ast_control_frame_type) {
         unsafe { ast_queue_control(ptr::addr_of!(self.0).cast_mut(), control) };
     }
@@ -70,12 +158,104 @@ impl Channel {
         unsafe { ast_queue_frame(ptr::addr_of!(self.0).cast_mut(), std::ptr::from_mut(frame)) };
     }
 
+    /// Queues an update of the name of the connected line, which is passed on to the channel
+    /// bridged with this one, e.g. to show it on the display of a phone.
+    pub fn queue_connected_line_name(&self, name: &str) {
+        let Ok(name) = CString::new(name) else {
+            return;
+        };
+
+        let mut connected = MaybeUninit::<ast_party_connected_line>::uninit();
+        let mut connected = unsafe {
+            ast_party_connected_line_init(connected.as_mut_ptr());
+            connected.assume_init()
+        };
+        // Only borrowed, the party is never freed.
+        connected.id.name.str_ = name.as_ptr().cast_mut();
+        connected.id.name.valid = 1;
+
+        let mut update: ast_set_party_connected_line = unsafe { std::mem::zeroed() };
+        update.id.name = 1;
+
+        unsafe {
+            ast_channel_queue_connected_line_update(
+                ptr::addr_of!(self.0).cast_mut(),
+                &connected,
+                &update,
+            )
+        };
+    }
+
+    /// Sets the caller id name of this channel, keeping its number.
+    pub fn set_caller_name(&mut self, name: &str) {
+        let Ok(name) = CString::new(name) else {
+            return;
+        };
+
+        unsafe {
+            ast_set_callerid(
+                ptr::addr_of_mut!(self.0),
+                ptr::null(),
+                name.as_ptr(),
+                ptr::null(),
+            )
+        };
+    }
+
+    /// Starts playing music on hold into this channel, from [class] or, if that class doesn't
+    /// exist, from [fallback_class]. Without either, the default class is used.
+    pub fn start_music_on_hold(&mut self, class: Option<&CStr>, fallback_class: Option<&CStr>) {
+        unsafe {
+            ast_moh_start(
+                ptr::addr_of_mut!(self.0),
+                class.map_or(ptr::null(), CStr::as_ptr),
+                fallback_class.map_or(ptr::null(), CStr::as_ptr),
+            )
+        };
+    }
+
+    pub fn stop_music_on_hold(&mut self) {
+        unsafe { ast_moh_stop(ptr::addr_of_mut!(self.0)) }
+    }
+
+    /// Stops tones that Asterisk generates into this channel, like the ring tone.
+    pub fn stop_tones(&mut self) {
+        unsafe { ast_playtones_stop(ptr::addr_of_mut!(self.0)) }
+    }
+
     pub fn stage_snapshot<'a>(&'a mut self) -> StagedSnapshot<'a> {
         unsafe { ast_channel_stage_snapshot(ptr::addr_of_mut!(*self.to_asterisk_mut())) }
         StagedSnapshot { channel: self }
     }
 }
 
+impl PartyId {
+    /// `Name <number>`, or whichever of the two is known.
+    pub fn caller_id(&self) -> Option<String> {
+        match (&self.name, &self.number) {
+            (Some(name), Some(number)) => Some(format!("{name} <{number}>")),
+            (name, number) => name.clone().or_else(|| number.clone()),
+        }
+    }
+}
+
+fn party_name(name: &ast_party_name) -> Option<String> {
+    party_string(name.valid != 0, name.str_)
+}
+
+fn party_number(number: &ast_party_number) -> Option<String> {
+    party_string(number.valid != 0, number.str_)
+}
+
+fn party_string(valid: bool, str: *const c_char) -> Option<String> {
+    if !valid || str.is_null() {
+        return None;
+    }
+
+    let str = unsafe { CStr::from_ptr(str) }.to_string_lossy();
+    Some(str.into_owned()).filter(|str| !str.is_empty())
+}
+
 pub struct StagedSnapshot<'a> {
     pub channel: &'a mut Channel,
 }
@@ -0,0 +1,131 @@
+use std::{
+    ffi::{c_char, c_int, CStr},
+    ptr::null_mut,
+};
+
+use asterisk_sys::bindings::{
+    __ast_cli_register, ast_cli, ast_cli_args, ast_cli_command_CLI_GENERATE,
+    ast_cli_command_CLI_INIT, ast_cli_entry, ast_cli_unregister, ast_module,
+};
+
+use crate::{asterisk_call, AsteriskError};
+
+/// A CLI command implemented in Rust.
+///
+/// Asterisk passes the entry back to the handler, so we embed the `ast_cli_entry` as the first
+/// field and recover the Rust handler from it.
+#[repr(C)]
+pub struct CliEntry {
+    entry: ast_cli_entry,
+    command: &'static CStr,
+    usage: &'static CStr,
+    handler: fn(&CliArgs) -> CliResult,
+}
+
+#[repr(transparent)]
+pub struct CliArgs(ast_cli_args);
+
+pub enum CliResult {
+    Success,
+    ShowUsage,
+    Failure,
+}
+
+impl CliEntry {
+    pub const fn new(
+        command: &'static CStr,
+        summary: &'static CStr,
+        usage: &'static CStr,
+        handler: fn(&CliArgs) -> CliResult,
+    ) -> Self {
+        let mut entry = unsafe { std::mem::zeroed::<ast_cli_entry>() };
+        entry.summary = summary.as_ptr();
+        entry.handler = Some(Self::handle);
+
+        Self {
+            entry,
+            command,
+            usage,
+            handler,
+        }
+    }
+
+    /// Registers all [entries] with Asterisk. They need to stay alive until they're unregistered.
+    ///
+    /// Each entry is registered on its own: Asterisk would walk an array of them in steps of
+    /// `ast_cli_entry`, which our entries are larger than.
+    pub unsafe fn register_all(
+        entries: &'static mut [CliEntry],
+        module: *mut ast_module,
+    ) -> Result<(), AsteriskError> {
+        for registered in 0..entries.len() {
+            let res = asterisk_call(__ast_cli_register(&mut entries[registered].entry, module));
+            if res.is_err() {
+                Self::unregister_all(&mut entries[..registered]);
+                return res;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub unsafe fn unregister_all(entries: &mut [CliEntry]) {
+        for entry in entries {
+            ast_cli_unregister(&mut entry.entry);
+        }
+    }
+
+    unsafe extern "C" fn handle(
+        e: *mut ast_cli_entry,
+        cmd: c_int,
+        a: *mut ast_cli_args,
+    ) -> *mut c_char {
+        let entry = e.cast::<CliEntry>().as_mut().unwrap();
+
+        #[allow(non_upper_case_globals)]
+        match cmd {
+            ast_cli_command_CLI_INIT => {
+                entry.entry.command = entry.command.as_ptr().cast_mut();
+                entry.entry.usage = entry.usage.as_ptr();
+                null_mut()
+            }
+            ast_cli_command_CLI_GENERATE => null_mut(),
+            _ => {
+                let args = CliArgs::from_raw(a.as_ref().unwrap());
+                // CLI_SUCCESS, CLI_SHOWUSAGE and CLI_FAILURE are integers disguised as pointers.
+                match (entry.handler)(args) {
+                    CliResult::Success => null_mut(),
+                    CliResult::ShowUsage => 1 as *mut c_char,
+                    CliResult::Failure => 2 as *mut c_char,
+                }
+            }
+        }
+    }
+}
+
+impl CliArgs {
+    fn from_raw(raw: &ast_cli_args) -> &Self {
+        unsafe { std::mem::transmute::<&ast_cli_args, &Self>(raw) }
+    }
+
+    /// All words of the command line, including the words of the command itself.
+    pub fn argv(&self) -> Vec<&str> {
+        let argv = unsafe { std::slice::from_raw_parts(self.0.argv, self.0.argc as usize) };
+        argv.iter()
+            .map(|arg| unsafe { CStr::from_ptr(*arg) }.to_str().unwrap_or_default())
+            .collect()
+    }
+
+    /// Prints a line of output to the console that issued the command.
+    pub fn print(&self, line: &str) {
+        let line = format!("{line}\n");
+        unsafe {
+            ast_cli(
+                self.0.fd,
+                c"%.*s".as_ptr(),
+                line.len() as c_int,
+                line.as_ptr(),
+            )
+        };
+    }
+}
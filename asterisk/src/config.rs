@@ -1,12 +1,12 @@
 use std::{
-    ffi::CStr,
+    ffi::{c_char, CStr},
     marker::PhantomData,
     ptr::{null, NonNull},
 };
 
 use asterisk_sys::bindings::{
-    ast_category, ast_category_first, ast_category_get, ast_config, ast_config_load2, ast_flags,
-    ast_module_info, ast_variable,
+    ast_category, ast_category_browse, ast_category_first, ast_category_get, ast_config,
+    ast_config_load2, ast_flags, ast_module_info, ast_variable,
 };
 
 pub struct AsteriskConfig {
@@ -49,6 +49,24 @@ impl AsteriskConfig {
             config: PhantomData,
         })
     }
+
+    /// The names of all categories, in the order they appear in the file.
+    pub fn category_names(&self) -> Vec<&CStr> {
+        let mut names = vec![];
+        let mut previous: *const c_char = null();
+
+        loop {
+            let name = unsafe { ast_category_browse(self.raw, previous) };
+            if name.is_null() {
+                break;
+            }
+
+            names.push(unsafe { CStr::from_ptr(name) });
+            previous = name;
+        }
+
+        names
+    }
 }
 
 impl<'a> ConfigVariable<'a> {
@@ -55,11 +55,12 @@ pub enum JitterBufferErr<T> {
 }
 
 impl<T> JitterBuffer<T> {
-    pub fn new(config: &mut jb_conf) -> Self {
+    /// Creates a jitter buffer, measuring time relative to [reference_time].
+    pub fn new(config: &mut jb_conf, reference_time: Instant) -> Self {
         let mut buf = Self {
             buf: unsafe { jb_new() },
             entries: PhantomData,
-            reference_time: Instant::now(),
+            reference_time,
         };
         buf.setconf(config);
 
@@ -75,13 +76,14 @@ impl<T> JitterBuffer<T> {
     pub fn get(
         &mut self,
         expected_frame_length: Duration,
+        now: Instant,
     ) -> Result<JitterFrame<T>, JitterBufferErr<T>> {
         let mut frame = MaybeUninit::uninit();
         let code = unsafe {
             jb_get(
                 self.buf,
                 frame.as_mut_ptr(),
-                self.receiver_timestamp(Instant::now()),
+                self.receiver_timestamp(now),
                 expected_frame_length.as_millis() as i64,
             )
         };
@@ -94,10 +96,11 @@ impl<T> JitterBuffer<T> {
         frame_type: JitterFrameType,
         length: Duration,
         ts: i64,
+        now: Instant,
     ) -> Result<(), JitterBufferErr<T>> {
         let raw_data = Box::into_raw(data);
         let ms = length.as_millis() as i64;
-        let now = self.receiver_timestamp(Instant::now());
+        let now = self.receiver_timestamp(now);
         let frame_type = frame_type.into();
 
         let code = unsafe {
@@ -141,7 +144,8 @@ impl<T> JitterBuffer<T> {
     }
 
     fn receiver_timestamp(&self, time: Instant) -> i64 {
-        time.duration_since(self.reference_time).as_millis() as i64
+        time.saturating_duration_since(self.reference_time)
+            .as_millis() as i64
     }
 
     fn interpret_frame(frame: jb_frame) -> JitterFrame<T> {
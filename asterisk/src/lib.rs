@@ -5,12 +5,15 @@ use asterisk_sys::bindings::{__IncompleteArrayField, ast_str, ast_threadstorage}
 #[macro_use]
 pub mod macros;
 
+pub mod application;
 pub mod astobj2;
 pub mod channel;
+pub mod cli;
 pub mod config;
 pub mod formats;
 pub mod jitterbuffer;
 pub mod logger;
+pub mod manager;
 
 pub enum AsteriskError {
     GenericFailure,
@@ -0,0 +1,83 @@
+use std::ffi::{c_int, CStr, CString};
+
+use asterisk_sys::bindings::{
+    __ast_manager_event_multichan, ast_manager_register2, ast_manager_unregister, ast_module,
+    astman_get_header, astman_send_ack, astman_send_error, mansession, message,
+};
+
+use crate::{asterisk_call, AsteriskError};
+
+pub type ManagerActionHandler = unsafe extern "C" fn(*mut mansession, *const message) -> c_int;
+
+/// Sends an AMI event to the sessions subscribed to [category]. The [body] consists of
+/// `Header: value\r\n` lines.
+pub fn send_event(category: c_int, event: &CStr, body: &str) {
+    unsafe {
+        __ast_manager_event_multichan(
+            category,
+            event.as_ptr(),
+            0,
+            std::ptr::null_mut(),
+            c"chan_discord".as_ptr(),
+            0,
+            c"send_event".as_ptr(),
+            c"%.*s".as_ptr(),
+            body.len() as c_int,
+            body.as_ptr(),
+        );
+    }
+}
+
+/// An incoming AMI action, with the session used to respond to it.
+pub struct ManagerAction {
+    session: *mut mansession,
+    message: *const message,
+}
+
+impl ManagerAction {
+    pub unsafe fn from_raw(session: *mut mansession, message: *const message) -> Self {
+        Self { session, message }
+    }
+
+    pub unsafe fn register(
+        name: &'static CStr,
+        authority: c_int,
+        handler: ManagerActionHandler,
+        module: *mut ast_module,
+    ) -> Result<(), AsteriskError> {
+        asterisk_call(ast_manager_register2(
+            name.as_ptr(),
+            authority,
+            Some(handler),
+            module,
+            std::ptr::null(),
+            std::ptr::null(),
+        ))
+    }
+
+    pub unsafe fn unregister(name: &'static CStr) {
+        ast_manager_unregister(name.as_ptr());
+    }
+
+    /// Returns the value of a header, or `None` if it's missing or empty.
+    pub fn header(&self, name: &CStr) -> Option<&str> {
+        let value = unsafe { CStr::from_ptr(astman_get_header(self.message, name.as_ptr().cast_mut())) };
+        let value = value.to_str().ok()?;
+
+        if value.is_empty() {
+            None
+        } else {
+            Some(value)
+        }
+    }
+
+    pub fn ack(&self, text: &str) {
+        let text = CString::new(text).unwrap_or_default();
+        unsafe { astman_send_ack(self.session, self.message, text.as_ptr()) };
+    }
+
+    pub fn error(&self, text: &str) {
+        let text = CString::new(text).unwrap_or_default();
+        unsafe { astman_send_error(self.session, self.message, text.as_ptr()) };
+    }
+}
@@ -0,0 +1,48 @@
+use std::ffi::{c_char, c_int, CStr};
+
+use asterisk::channel::Channel;
+use asterisk_sys::bindings::ast_channel;
+use chan_discord_common::dial::Destination;
+use log::warn;
+
+use crate::channel_tech::{discord_channel_for, move_call};
+
+/// `DiscordMove(<guild>/<channel>)`: Moves the Discord call to another voice channel, either on a
+/// Discord channel or on a channel bridged with one. The Asterisk channels stay up.
+///
+/// Sets `DISCORD_MOVE_STATUS` to `SUCCESS` or `FAILURE`.
+pub unsafe extern "C" fn discord_move(chan: *mut ast_channel, data: *const c_char) -> c_int {
+    let Some(chan) = chan.as_ref() else {
+        return -1;
+    };
+    let chan = Channel::from_asterisk(chan);
+
+    let data = CStr::from_ptr(data).to_string_lossy();
+    let Some((guild, voice_channel)) = Destination::parse_address(&data) else {
+        warn!("DiscordMove requires a destination formatted as <server>/<channel>, got {data:?}");
+        chan.set_variable(c"DISCORD_MOVE_STATUS", "FAILURE");
+        return 0;
+    };
+
+    let res = match discord_channel_for(chan) {
+        Some(discord) => move_call(&discord, guild, voice_channel),
+        None => None,
+    };
+    let status = match res {
+        Some(Ok(())) => "SUCCESS",
+        Some(Err(e)) => {
+            warn!(
+                "Could not move {} to {guild}/{voice_channel}: {e}",
+                chan.name()
+            );
+            "FAILURE"
+        }
+        None => {
+            warn!("{} is not connected to a Discord channel", chan.name());
+            "FAILURE"
+        }
+    };
+
+    chan.set_variable(c"DISCORD_MOVE_STATUS", status);
+    0
+}
@@ -1,56 +1,217 @@
-use std::ffi::CStr;
+use std::{
+    collections::HashMap,
+    ffi::{c_int, CStr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::anyhow;
 use chan_discord_common::{
-    constants::{MAX_OPUS_PAYLOAD_SIZE, NUM_SAMPLES, SAMPLE_RATE},
-    discord::voice_task::{OutgoingVoicePacket, VoiceEvent, VoiceTaskHandle},
+    audio::{
+        archive::{CallArchive, OpusTrack},
+        mixer::Volume,
+        recording::{CallRecording, Track},
+        resegment::Resegmenter,
+        speaker::{ActiveSpeakerDetector, ParticipantLevel},
+        vad::VoiceActivityDetector,
+    },
+    constants::{
+        MAX_OPUS_PAYLOAD_SIZE, OPUS_SILENCE_FRAME, SAMPLE_RATE, SILENCE_FRAMES_BEFORE_PAUSE,
+    },
+    dial::Destination,
+    discord::{
+        cache::DiscordCache,
+        call_log::{CallLogEntry, CallLogEvent},
+        presence::{CallPlace, Presence, PresenceCall},
+        rest::RestClient,
+        voice_task::{OutgoingVoicePacket, VoiceEvent, VoiceStateFlags, VoiceTaskHandle},
+        Discord,
+    },
     error::{ChanRes, DiscordError},
+    stats::{CallStats, SendCounters},
+    timeouts::{CallTimers, HangupReason},
     utils::{request_channel, RequestReceiver, RequestSender},
 };
-use discortp::wrap::Wrap32;
-use log::{trace, warn};
+use log::{debug, trace, warn};
 use rand::{thread_rng, Rng};
 use tokio::{
     sync::{mpsc, oneshot},
-    time::{sleep_until, Instant},
+    task::JoinHandle,
+    time::{interval, sleep_until, Instant, Interval, MissedTickBehavior},
 };
 use twilight_gateway::{Event, MessageSender};
-use twilight_model::id::{
-    marker::{ChannelMarker, GuildMarker, UserMarker},
-    Id,
+use twilight_model::{
+    guild::Permissions,
+    id::{
+        marker::{ChannelMarker, GuildMarker, UserMarker},
+        Id,
+    },
 };
 
-use asterisk::{astobj2::Ao2, channel::Channel};
-use asterisk_sys::bindings::{ast_control_frame_type_AST_CONTROL_ANSWER, ast_frame};
+use asterisk::{astobj2::Ao2, channel::Channel, manager};
+use asterisk_sys::bindings::{
+    ast_control_frame_type, ast_control_frame_type_AST_CONTROL_ANSWER,
+    ast_control_frame_type_AST_CONTROL_HOLD, ast_control_frame_type_AST_CONTROL_PROGRESS,
+    ast_control_frame_type_AST_CONTROL_UNHOLD, ast_frame, AST_CAUSE_NORMAL_CLEARING,
+    AST_CAUSE_RECOVERY_ON_TIMER_EXPIRE, EVENT_FLAG_CALL,
+};
 
 use crate::{
+    config::{AnswerPolicy, CallOptions},
     queue_thread::{ChannelWriteKind, QueueThread},
     rtp_receiver::{FetchPacketResult, RtpReceiver},
 };
 
 pub struct CallHandle {
+    info: CallInfo,
+    options: CallOptions,
     requests: RequestSender<CallRequest, ChanRes<CallResponse>>,
     encoder: opus::Encoder,
-    timestamp: Wrap32,
+    resegmenter: Resegmenter,
+    vad: VoiceActivityDetector,
+    transmission: Transmission,
+    recording: Option<Track>,
+    archive: Option<OpusTrack>,
+    // While the call is on hold, nothing is sent to Discord.
+    muted: bool,
+    listen_only: bool,
+    // The profile of discord.conf the call was dialed with.
+    profile: Option<String>,
+}
+
+/// Where a call goes in Discord. The names are looked up when the call is prepared and are missing
+/// if the guild or channel wasn't known to us yet.
+#[derive(Debug, Clone)]
+pub struct CallInfo {
+    pub guild: Id<GuildMarker>,
+    pub channel: Id<ChannelMarker>,
+    pub guild_name: Option<String>,
+    pub channel_name: Option<String>,
+}
+
+/// Optional captures of the call audio, enabled through channel variables.
+#[derive(Debug, Default)]
+pub struct CallCapture {
+    pub recording: Option<CallRecording>,
+    pub archive: Option<CallArchive>,
+    pub pcap: Option<PathBuf>,
+}
+
+/// Whether we're currently sending audio to Discord. We stop transmitting while the Asterisk side
+/// is silent, which also clears the speaking indicator in Discord.
+enum Transmission {
+    Speaking,
+    TrailingSilence { remaining_frames: usize },
+    Paused,
 }
 
 #[derive(Debug)]
 pub enum CallRequest {
-    JoinChannel,
+    JoinChannel {
+        capture: CallCapture,
+        caller: Option<String>,
+    },
     HangUp,
-    WriteFrame(OutgoingVoicePacket),
-    FixUp { new_channel: Ao2<Channel> },
+    WriteFrame {
+        packet: OutgoingVoicePacket,
+        // Whether the frame was speech rather than trailing silence.
+        is_speech: bool,
+    },
+    SetSpeaking(bool),
+    SetSelfMute(bool),
+    PostNotice(String),
+    SetCapture(Option<PathBuf>),
+    FixUp {
+        new_channel: Ao2<Channel>,
+    },
+    SetUserVolume {
+        user: Id<UserMarker>,
+        volume: Volume,
+    },
+    GetUserVolume {
+        user: Id<UserMarker>,
+    },
+    GetStats,
+    GetParticipants,
+    MoveTo {
+        guild: Id<GuildMarker>,
+        channel: Id<ChannelMarker>,
+    },
 }
 
 #[derive(Debug)]
-pub struct CallResponse {}
+pub enum CallResponse {
+    Empty,
+    Volume(Volume),
+    Stats(CallStats),
+    Participants(Vec<Id<UserMarker>>),
+    Moved(CallInfo),
+}
 
 pub struct CallWorker {
     asterisk_channel: Ao2<Channel>,
     voice: VoiceTaskState,
     requests: RequestReceiver<CallRequest, ChanRes<CallResponse>>,
     rtp: RtpReceiver,
+    send_counters: Arc<SendCounters>,
     queue_thread: QueueThread,
+    speaker: ActiveSpeakerDetector,
+    speaker_updates: Interval,
+    info: CallInfo,
+    discord: Discord,
+    cache: DiscordCache,
+    rest: RestClient,
+    // The last notice posted into the chat of the voice channel, to not repeat it.
+    last_notice: Option<String>,
+    // The users in the voice channel as last published on the connected line.
+    participants: Option<Vec<Id<UserMarker>>>,
+    answer_policy: AnswerPolicy,
+    answer: AnswerState,
+    timers: CallTimers,
+    hangup_reason: Option<HangupReason>,
+    voice_flags: VoiceStateFlags,
+    // Listen-only calls never send audio to Discord.
+    listen_only: bool,
+    // Whether Asterisk is sending audio, for voice tasks started after it changed.
+    speaking: bool,
+    // The nickname to use during the call, and the change to it along with the nickname to
+    // restore afterwards.
+    nickname: Option<String>,
+    nickname_change: Option<(JoinHandle<ChanRes<()>>, Option<String>)>,
+    // Passes silence to Asterisk while the call moves to another voice channel.
+    move_silence: Option<Interval>,
+    // Where packets are captured to, for the voice task of another guild to go on with it.
+    pcap: Option<PathBuf>,
+    log_channels: HashMap<Id<GuildMarker>, Id<ChannelMarker>>,
+    // The caller id of the calling side, for the log channel.
+    caller: Option<String>,
+    // Everyone who has been in the voice channel during the call, in the order they joined.
+    participants_seen: Vec<Id<UserMarker>>,
+    // Whether the call was logged as started, calls that never got there aren't logged as ended.
+    log_started: bool,
+    presence: Presence,
+    // Counts the call in the presence of the bot while it's connected.
+    presence_call: Option<PresenceCall>,
+    stage: StageState,
+    // Whether we already asked to speak on the stage of the current voice channel.
+    speak_requested: bool,
+}
+
+/// Whether the Asterisk channel has been answered, see [AnswerPolicy].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AnswerState {
+    Connecting,
+    WaitingForPolicy,
+    Answered,
+}
+
+/// Whether Discord can hear the bot. On stage channels, it joins the audience first, which is
+/// passed on to Asterisk as progress before the call is answered and as hold afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StageState {
+    Speaker,
+    Suppressed { held: bool },
 }
 
 enum VoiceTaskState {
@@ -69,22 +230,38 @@ enum VoiceTaskState {
     },
 }
 
-impl CallHandle {
-    pub fn parse_destination_addr(str: &CStr) -> Option<(Id<GuildMarker>, Id<ChannelMarker>)> {
-        let str = str.to_str().ok()?;
+impl CallInfo {
+    pub fn new(cache: &DiscordCache, guild: Id<GuildMarker>, channel: Id<ChannelMarker>) -> Self {
+        Self {
+            guild,
+            channel,
+            guild_name: cache.guild_name(guild),
+            channel_name: cache.channel_name(channel),
+        }
+    }
 
-        let mut split = str.split('/');
-        let guild = Id::new(split.next()?.parse::<u64>().ok()?);
-        let channel = Id::new(split.next()?.parse::<u64>().ok()?);
+    /// `<guild name> / <channel name>`, with ids in place of unknown names.
+    pub fn caller_name(&self) -> String {
+        let place = self.place();
+        format!("{} / {}", place.guild, place.channel)
+    }
 
-        if split.next().is_some() {
-            // We only want two elements
-            return None;
+    /// The names of the guild and channel, with ids in place of unknown names.
+    pub fn place(&self) -> CallPlace {
+        CallPlace {
+            guild: self
+                .guild_name
+                .clone()
+                .unwrap_or_else(|| self.guild.to_string()),
+            channel: self
+                .channel_name
+                .clone()
+                .unwrap_or_else(|| self.channel.to_string()),
         }
-
-        Some((guild, channel))
     }
+}
 
+impl CallHandle {
     fn request(&self, request: CallRequest) -> ChanRes<CallResponse> {
         let res = self
             .requests
@@ -93,13 +270,30 @@ impl CallHandle {
         Ok(res)
     }
 
-    pub fn hangup(&self) -> ChanRes<()> {
-        self.request(CallRequest::HangUp)?;
-        Ok(())
+    /// Leaves the Discord call, returning the final statistics of the call.
+    pub fn hangup(&mut self) -> ChanRes<CallStats> {
+        if let Some(track) = self.recording.take() {
+            track.finish();
+        }
+        if let Some(track) = self.archive.take() {
+            track.finish();
+        }
+
+        match self.request(CallRequest::HangUp)? {
+            CallResponse::Stats(stats) => Ok(stats),
+            _ => panic!("Expected stats response"),
+        }
     }
 
-    pub fn start_joining(&self) -> ChanRes<()> {
-        self.request(CallRequest::JoinChannel)?;
+    /// Starts joining the voice channel, for a call from [caller] if the caller id is known.
+    pub fn start_joining(
+        &mut self,
+        mut capture: CallCapture,
+        caller: Option<String>,
+    ) -> ChanRes<()> {
+        self.recording = capture.recording.as_mut().map(CallRecording::sent_track);
+        self.archive = capture.archive.as_ref().map(CallArchive::sent_track);
+        self.request(CallRequest::JoinChannel { capture, caller })?;
         Ok(())
     }
 
@@ -108,44 +302,202 @@ impl CallHandle {
         Ok(())
     }
 
-    pub fn write_frame(&mut self, frame: &ast_frame) -> ChanRes<()> {
-        let timestamp = self.timestamp;
-        self.timestamp += NUM_SAMPLES;
+    /// Starts or stops writing the packets of this call into a pcap file.
+    pub fn set_capture(&self, path: Option<PathBuf>) -> ChanRes<()> {
+        self.request(CallRequest::SetCapture(path))?;
+        Ok(())
+    }
+
+    pub fn set_user_volume(&self, user: Id<UserMarker>, volume: Volume) -> ChanRes<()> {
+        self.request(CallRequest::SetUserVolume { user, volume })?;
+        Ok(())
+    }
+
+    pub fn user_volume(&self, user: Id<UserMarker>) -> ChanRes<Volume> {
+        match self.request(CallRequest::GetUserVolume { user })? {
+            CallResponse::Volume(volume) => Ok(volume),
+            _ => panic!("Expected volume response"),
+        }
+    }
+
+    pub fn stats(&self) -> ChanRes<CallStats> {
+        match self.request(CallRequest::GetStats)? {
+            CallResponse::Stats(stats) => Ok(stats),
+            _ => panic!("Expected stats response"),
+        }
+    }
+
+    pub fn info(&self) -> &CallInfo {
+        &self.info
+    }
+
+    pub fn options(&self) -> &CallOptions {
+        &self.options
+    }
+
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// Stops or resumes sending audio to Discord, showing the bot as muted in the meantime.
+    /// Listen-only calls stay muted.
+    pub fn set_muted(&mut self, muted: bool) -> ChanRes<()> {
+        self.muted = muted;
+        self.request(CallRequest::SetSelfMute(muted || self.listen_only))?;
+        Ok(())
+    }
+
+    /// Posts [notice] into the chat of the voice channel, unless it was the last notice posted.
+    pub fn post_notice(&self, notice: &str) -> ChanRes<()> {
+        self.request(CallRequest::PostNotice(notice.to_string()))?;
+        Ok(())
+    }
+
+    /// The users currently in the Discord voice channel, without our bot.
+    pub fn participants(&self) -> ChanRes<Vec<Id<UserMarker>>> {
+        match self.request(CallRequest::GetParticipants)? {
+            CallResponse::Participants(participants) => Ok(participants),
+            _ => panic!("Expected participants response"),
+        }
+    }
+
+    /// Prepares moving the call to the voice [channel] in [guild], keeping the Asterisk channel
+    /// up. The move is waited for with [PendingMove::wait], which doesn't need the channel lock.
+    pub fn start_move(&self, guild: Id<GuildMarker>, channel: Id<ChannelMarker>) -> PendingMove {
+        PendingMove {
+            requests: self.requests.clone(),
+            guild,
+            channel,
+        }
+    }
+
+    /// Takes on the call information of the voice channel the call was moved to.
+    pub fn moved(&mut self, info: CallInfo) {
+        self.info = info;
+    }
 
+    pub fn write_frame(&mut self, frame: &ast_frame) -> ChanRes<()> {
         let raw_data = unsafe {
             std::slice::from_raw_parts(frame.data.ptr.cast::<i16>(), (frame.datalen / 2) as usize)
         };
 
-        let res = self
-            .encoder
-            .encode_vec(raw_data, MAX_OPUS_PAYLOAD_SIZE)
-            .map_err(|_| DiscordError::EncodeError)?;
-        let res = self.request(CallRequest::WriteFrame(OutgoingVoicePacket {
-            opus_payload: res,
-            timestamp: timestamp.into(),
-        }));
-        res?;
+        let now = std::time::Instant::now();
+        for mut frame in self.resegmenter.push(raw_data, now) {
+            if self.muted || self.listen_only {
+                // Wind down the transmission as if Asterisk sent silence, after which nothing is
+                // encoded or sent anymore.
+                frame.samples.fill(0);
+            }
+
+            if let Some(track) = &mut self.recording {
+                track.write(frame.timestamp, now, &frame.samples);
+            }
+
+            let is_speech = self.vad.process(&frame.samples);
+
+            let payload = match self.transmission {
+                _ if is_speech => {
+                    if matches!(self.transmission, Transmission::Paused) {
+                        self.request(CallRequest::SetSpeaking(true))?;
+                    }
+                    self.transmission = Transmission::Speaking;
+
+                    self.encoder
+                        .encode_vec(&frame.samples, MAX_OPUS_PAYLOAD_SIZE)
+                        .map_err(|_| DiscordError::EncodeError)?
+                }
+                Transmission::Speaking => {
+                    self.transmission = Transmission::TrailingSilence {
+                        remaining_frames: SILENCE_FRAMES_BEFORE_PAUSE - 1,
+                    };
+                    OPUS_SILENCE_FRAME.to_vec()
+                }
+                Transmission::TrailingSilence { remaining_frames } => {
+                    if remaining_frames == 0 {
+                        self.request(CallRequest::SetSpeaking(false))?;
+                        self.transmission = Transmission::Paused;
+                        continue;
+                    }
+
+                    self.transmission = Transmission::TrailingSilence {
+                        remaining_frames: remaining_frames - 1,
+                    };
+                    OPUS_SILENCE_FRAME.to_vec()
+                }
+                Transmission::Paused => continue,
+            };
+
+            if let Some(track) = &mut self.archive {
+                track.write(frame.timestamp, now, &payload);
+            }
+
+            self.request(CallRequest::WriteFrame {
+                packet: OutgoingVoicePacket {
+                    opus_payload: payload,
+                    timestamp: frame.timestamp,
+                },
+                is_speech,
+            })?;
+        }
+
         Ok(())
     }
 }
 
+/// A move of a call to another voice channel, see [CallHandle::start_move].
+pub struct PendingMove {
+    requests: RequestSender<CallRequest, ChanRes<CallResponse>>,
+    guild: Id<GuildMarker>,
+    channel: Id<ChannelMarker>,
+}
+
+impl PendingMove {
+    /// Moves the call, returning the new call information once the bot is joining the new voice
+    /// channel.
+    pub fn wait(self) -> ChanRes<CallInfo> {
+        let request = CallRequest::MoveTo {
+            guild: self.guild,
+            channel: self.channel,
+        };
+        let res = self
+            .requests
+            .request_blocking(request)
+            .map_err(|e| DiscordError::InternalError { source: e.into() })??;
+
+        match res {
+            CallResponse::Moved(info) => Ok(info),
+            _ => panic!("Expected moved response"),
+        }
+    }
+}
+
 enum WorkerEvent {
     ClientRequest(Option<(CallRequest, oneshot::Sender<ChanRes<CallResponse>>)>),
     CallEvent(Option<VoiceEvent>),
     MixedPacket((Vec<i16>, ast_frame)),
+    MoveSilence,
+    UpdateSpeaker,
+    Timeout(HangupReason),
 }
 
 unsafe impl Send for WorkerEvent {}
 
 impl CallWorker {
+    const SPEAKER_UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+    // With more participants, the connected line only shows how many there are.
+    const MAX_NAMED_PARTICIPANTS: usize = 3;
+    // The length of the silent frames passed to Asterisk while moving.
+    const MOVE_SILENCE_INTERVAL: Duration = Duration::from_millis(20);
+
     pub fn new(
         asterisk_channel: Ao2<Channel>,
-        server: Id<GuildMarker>,
-        channel: Id<ChannelMarker>,
-        user: Id<UserMarker>,
-        sender: MessageSender,
+        destination: &Destination,
+        discord: &Discord,
         events: mpsc::Receiver<Event>,
+        options: CallOptions,
+        presence: Presence,
     ) -> ChanRes<(Self, CallHandle)> {
+        let (server, channel) = (destination.guild, destination.channel);
         let rng = &mut thread_rng();
         let initial_timestamp = rng.gen::<u32>();
 
@@ -157,27 +509,77 @@ impl CallWorker {
 
         let (send, recv) = request_channel();
 
+        let mut speaker_updates = interval(Self::SPEAKER_UPDATE_INTERVAL);
+        speaker_updates.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let cache = discord.cache();
+        let info = CallInfo::new(&cache, server, channel);
         let worker = Self {
             asterisk_channel,
             voice: VoiceTaskState::Prepare {
                 server,
                 channel,
-                user,
-                sender,
+                user: discord.bot_user(),
+                sender: discord.message_sender(),
                 events,
             },
             requests: recv,
-            rtp: RtpReceiver::new(),
+            rtp: RtpReceiver::new(options.jitter_buffer.factory()),
+            send_counters: Arc::new(SendCounters::default()),
             queue_thread: super::queue_thread(),
+            speaker: ActiveSpeakerDetector::new(),
+            speaker_updates,
+            info: info.clone(),
+            discord: discord.clone(),
+            cache,
+            rest: discord.rest(),
+            last_notice: None,
+            participants: None,
+            answer_policy: options.answer,
+            answer: AnswerState::Connecting,
+            timers: CallTimers::new(
+                options
+                    .timeouts
+                    .with_overrides(&destination.options.timeouts),
+            ),
+            hangup_reason: None,
+            voice_flags: VoiceStateFlags {
+                self_mute: destination.options.listen_only,
+                self_deaf: destination.options.speak_only,
+            },
+            listen_only: destination.options.listen_only,
+            nickname: destination.options.nickname.clone(),
+            nickname_change: None,
+            move_silence: None,
+            log_channels: options.log_channels.clone(),
+            caller: None,
+            participants_seen: Vec::new(),
+            log_started: false,
+            presence,
+            presence_call: None,
+            stage: StageState::Speaker,
+            speak_requested: false,
+            speaking: true,
+            pcap: None,
         };
 
         Ok((
             worker,
             CallHandle {
+                info,
+                options,
                 requests: send,
                 encoder,
-
-                timestamp: initial_timestamp.into(),
+                resegmenter: Resegmenter::new(initial_timestamp),
+                vad: VoiceActivityDetector::new(),
+                // The speaking flag is set when the voice session starts, so we need to send
+                // silence and clear it if the call starts without audio.
+                transmission: Transmission::Speaking,
+                recording: None,
+                archive: None,
+                muted: false,
+                listen_only: destination.options.listen_only,
+                profile: destination.options.profile.clone(),
             },
         ))
     }
@@ -206,7 +608,27 @@ impl CallWorker {
         }
     }
 
+    async fn move_silence(interval: &mut Option<Interval>) {
+        match interval {
+            Some(interval) => {
+                interval.tick().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    async fn timeout(timeout: Option<(std::time::Instant, HangupReason)>) -> HangupReason {
+        match timeout {
+            Some((deadline, reason)) => {
+                sleep_until(Instant::from_std(deadline)).await;
+                reason
+            }
+            None => std::future::pending().await,
+        }
+    }
+
     async fn next_event(&mut self) -> WorkerEvent {
+        let timeout = self.timers.next_timeout();
         tokio::select! {
             request = self.requests.request() => {
                 WorkerEvent::ClientRequest(request)
@@ -217,6 +639,15 @@ impl CallWorker {
             packet = Self::mixed_packet(&mut self.rtp) => {
                 WorkerEvent::MixedPacket(packet)
             }
+            _ = Self::move_silence(&mut self.move_silence) => {
+                WorkerEvent::MoveSilence
+            }
+            _ = self.speaker_updates.tick() => {
+                WorkerEvent::UpdateSpeaker
+            }
+            reason = Self::timeout(timeout) => {
+                WorkerEvent::Timeout(reason)
+            }
         }
     }
 
@@ -226,7 +657,8 @@ impl CallWorker {
         response: oneshot::Sender<ChanRes<CallResponse>>,
     ) -> ChanRes<()> {
         match request {
-            CallRequest::JoinChannel => {
+            CallRequest::JoinChannel { capture, caller } => {
+                self.caller = caller;
                 let voice = std::mem::replace(
                     &mut self.voice,
                     VoiceTaskState::ShuttingDown {
@@ -248,10 +680,24 @@ impl CallWorker {
                             user,
                             server,
                             channel,
+                            self.send_counters.clone(),
+                            self.voice_flags,
                         )
                         .await;
+                        self.apply_speaking(&handle).await;
+                        self.change_nickname(server);
+                        if let Some(path) = capture.pcap.clone() {
+                            match handle.set_capture(Some(path.clone())).await {
+                                Ok(()) => self.pcap = Some(path),
+                                Err(e) => warn!("Could not capture packets: {e}"),
+                            }
+                        }
+
                         self.voice = VoiceTaskState::VoiceStarted { handle: handle };
-                        Ok(CallResponse {})
+                        self.rtp.set_capture(capture);
+                        self.post_log(CallLogEvent::Started);
+                        self.log_started = true;
+                        Ok(CallResponse::Empty)
                     }
                     _ => {
                         self.voice = voice;
@@ -263,17 +709,18 @@ impl CallWorker {
 
                 let _ = response.send(res);
             }
-            CallRequest::WriteFrame(packet) => {
+            CallRequest::WriteFrame { packet, is_speech } => {
+                self.timers.note_audio(is_speech, std::time::Instant::now());
                 let res = match &self.voice {
                     VoiceTaskState::VoiceStarted { handle } => handle.write(packet).await,
-                    _ => Err(DiscordError::InternalError {
-                        source: anyhow!("Call not connected yet"),
-                    }),
+                    // Audio written before the call is placed goes nowhere.
+                    _ => Ok(()),
                 }
-                .map(|_| CallResponse {});
+                .map(|_| CallResponse::Empty);
                 let _ = response.send(res);
             }
             CallRequest::HangUp => {
+                let stats = self.stats();
                 let voice = std::mem::replace(
                     &mut self.voice,
                     VoiceTaskState::ShuttingDown {
@@ -284,31 +731,124 @@ impl CallWorker {
                     trace!("Stopping discord voice task");
                     handle.leave_and_close().await;
                 };
-                let _ = response.send(Ok(CallResponse {}));
+                let _ = response.send(Ok(CallResponse::Stats(stats)));
+            }
+            CallRequest::SetSpeaking(speaking) => {
+                self.speaking = speaking;
+                let res = match &self.voice {
+                    VoiceTaskState::VoiceStarted { handle } => handle.set_speaking(speaking).await,
+                    // Applied once the voice task is started.
+                    _ => Ok(()),
+                }
+                .map(|_| CallResponse::Empty);
+                let _ = response.send(res);
+            }
+            CallRequest::SetSelfMute(muted) => {
+                let res = match &self.voice {
+                    VoiceTaskState::VoiceStarted { handle } => handle.set_self_mute(muted).await,
+                    _ => Err(DiscordError::InternalError {
+                        source: anyhow!("Call not connected yet"),
+                    }),
+                }
+                .map(|_| CallResponse::Empty);
+                let _ = response.send(res);
+            }
+            CallRequest::PostNotice(notice) => {
+                if self.last_notice.as_ref() != Some(&notice) {
+                    let rest = self.rest.clone();
+                    let channel = self.info.channel;
+                    self.last_notice = Some(notice.clone());
+                    // Don't hold up the call while Discord processes the message.
+                    tokio::spawn(async move {
+                        if let Err(e) = rest.post_message(channel, &notice).await {
+                            warn!("Could not post notice into {channel}: {e}");
+                        }
+                    });
+                }
+                let _ = response.send(Ok(CallResponse::Empty));
+            }
+            CallRequest::SetCapture(path) => {
+                let res = match &self.voice {
+                    VoiceTaskState::VoiceStarted { handle } => {
+                        handle.set_capture(path.clone()).await
+                    }
+                    _ => Err(DiscordError::InternalError {
+                        source: anyhow!("Call not connected yet"),
+                    }),
+                };
+                if res.is_ok() {
+                    self.pcap = path;
+                }
+                let res = res.map(|_| CallResponse::Empty);
+                let _ = response.send(res);
             }
             CallRequest::FixUp { new_channel } => {
                 self.asterisk_channel = new_channel;
             }
+            CallRequest::SetUserVolume { user, volume } => {
+                self.rtp.set_user_volume(user, volume);
+                let _ = response.send(Ok(CallResponse::Empty));
+            }
+            CallRequest::GetUserVolume { user } => {
+                let volume = self.rtp.user_volume(user);
+                let _ = response.send(Ok(CallResponse::Volume(volume)));
+            }
+            CallRequest::GetStats => {
+                let _ = response.send(Ok(CallResponse::Stats(self.stats())));
+            }
+            CallRequest::GetParticipants => {
+                let participants = self.cache.voice_channel_users(self.info.channel);
+                let _ = response.send(Ok(CallResponse::Participants(participants)));
+            }
+            CallRequest::MoveTo { guild, channel } => {
+                let res = self
+                    .move_to(guild, channel)
+                    .await
+                    .map(|_| CallResponse::Moved(self.info.clone()));
+                let _ = response.send(res);
+            }
         }
 
         Ok(())
     }
 
+    fn stats(&self) -> CallStats {
+        CallStats {
+            sent: self.send_counters.snapshot(),
+            participants: self.rtp.stats(),
+        }
+    }
+
     async fn handle_call_event(&mut self, event: VoiceEvent) -> ChanRes<()> {
         match event {
             VoiceEvent::Packet(packet) => {
-                self.rtp.handle_packet(packet);
+                // Speak-only calls don't pass on what they hear.
+                if !self.voice_flags.self_deaf {
+                    self.rtp.handle_packet(packet);
+                }
             }
-            VoiceEvent::UserJoined { ssrc, user } => {
-                trace!("User {user} joined with {ssrc}");
-                if let Err(e) = self.rtp.map_user_id(user, ssrc) {
-                    warn!("Could not add discord user to mixer: {e}");
+            VoiceEvent::UserJoined {
+                user,
+                ssrc,
+                video_ssrcs,
+            } => {
+                trace!("User {user} joined with {ssrc:?}, video: {video_ssrcs:?}");
+                for video_ssrc in video_ssrcs {
+                    self.rtp.ignore_ssrc(video_ssrc);
+                }
+
+                if let Some(ssrc) = ssrc {
+                    if let Err(e) = self.rtp.map_user_id(user, ssrc) {
+                        warn!("Could not add discord user to mixer: {e}");
+                    }
                 }
+                self.update_participants()?;
             }
             VoiceEvent::UserLeft { user } => {
                 trace!("User left: {user}");
 
                 self.rtp.unmap_user_id(user);
+                self.update_participants()?;
             }
             VoiceEvent::Speaking { user, ssrc } => {
                 trace!("User speaking: {user}, ssrc: {ssrc}");
@@ -316,9 +856,30 @@ impl CallWorker {
                     warn!("Could not add discord user to mixer: {e}");
                 }
             }
+            VoiceEvent::VoiceStatesChanged => {
+                self.update_participants()?;
+                self.update_stage()?;
+            }
+            VoiceEvent::FullyConnected if self.answer != AnswerState::Connecting => {
+                // Connected again after moving, the call has already made progress.
+                trace!("Connected to voice channel {}", self.info.channel);
+                self.move_silence = None;
+                self.update_participants()?;
+                self.update_stage()?;
+            }
             VoiceEvent::FullyConnected => {
-                self.asterisk_channel
-                    .queue_control(ast_control_frame_type_AST_CONTROL_ANSWER);
+                self.answer = AnswerState::WaitingForPolicy;
+                self.timers.connected(std::time::Instant::now());
+                if self.answer_policy != AnswerPolicy::Connect {
+                    // Callers hear the Discord channel as early media until we answer.
+                    self.queue_control(ast_control_frame_type_AST_CONTROL_PROGRESS)?;
+                }
+                self.update_participants()?;
+                self.update_empty_since();
+                self.check_answer()?;
+                self.update_stage()?;
+                self.post_log(CallLogEvent::Connected);
+                self.presence_call = Some(self.presence.add_call(self.info.place()));
             }
             VoiceEvent::Closed => {
                 self.voice = VoiceTaskState::ShuttingDown {
@@ -330,6 +891,366 @@ impl CallWorker {
         Ok(())
     }
 
+    /// Publishes changes of the dominant speaker in Discord to Asterisk: in a channel variable,
+    /// an AMI event and as the name of the connected line.
+    fn update_active_speaker(&mut self) -> ChanRes<()> {
+        let levels = self.rtp.levels();
+        let now = std::time::Instant::now();
+        // Packets from Discord only keep the call from being idle if someone is audible.
+        self.timers
+            .note_audio(levels.iter().any(ParticipantLevel::is_active), now);
+        if !self.speaker.update(&levels, now) {
+            return Ok(());
+        }
+        self.check_answer()?;
+
+        let speaker = self.speaker.current();
+        let user = speaker.map(|user| user.to_string()).unwrap_or_default();
+        let name = speaker
+            .map(|user| self.display_name(user))
+            .unwrap_or_default();
+        trace!("Active speaker is now {user} ({name})");
+
+        let channel = &self.asterisk_channel;
+        manager::send_event(
+            EVENT_FLAG_CALL,
+            c"DiscordActiveSpeaker",
+            &format!(
+                "Channel: {}\r\nUniqueid: {}\r\nUser: {user}\r\nName: {name}\r\n",
+                channel.name(),
+                channel.unique_id()
+            ),
+        );
+
+        self.queue_thread.request(
+            channel.clone(),
+            ChannelWriteKind::Variables(vec![(c"DISCORD_ACTIVE_SPEAKER", user)]),
+        )?;
+        // While nobody talks, the connected line shows who is in the call instead.
+        let connected_line = match speaker {
+            Some(_) => name,
+            None => self.participants_summary(),
+        };
+        self.queue_thread.request(
+            channel.clone(),
+            ChannelWriteKind::ConnectedLineName(connected_line),
+        )?;
+
+        Ok(())
+    }
+
+    /// Publishes the participants of the voice channel as the name of the connected line when
+    /// they change, unless someone is talking.
+    fn update_participants(&mut self) -> ChanRes<()> {
+        let participants = self.cache.voice_channel_users(self.info.channel);
+        if self.participants.as_ref() == Some(&participants) {
+            return Ok(());
+        }
+
+        trace!("Participants are now {participants:?}");
+        for user in &participants {
+            if !self.participants_seen.contains(user) {
+                self.participants_seen.push(*user);
+            }
+        }
+        self.participants = Some(participants);
+        self.update_empty_since();
+        self.check_answer()?;
+        if self.speaker.current().is_some() {
+            return Ok(());
+        }
+
+        let summary = self.participants_summary();
+        self.queue_thread.request(
+            self.asterisk_channel.clone(),
+            ChannelWriteKind::ConnectedLineName(summary),
+        )
+    }
+
+    /// Answers the Asterisk channel once the voice session is up and the [AnswerPolicy] is met.
+    fn check_answer(&mut self) -> ChanRes<()> {
+        if self.answer != AnswerState::WaitingForPolicy {
+            return Ok(());
+        }
+
+        let ready = match self.answer_policy {
+            AnswerPolicy::Connect => true,
+            AnswerPolicy::Participant => self.has_humans(),
+            AnswerPolicy::Speech => self.speaker.current().is_some(),
+        };
+        if !ready {
+            return Ok(());
+        }
+
+        trace!("Answering call ({:?})", self.answer_policy);
+        self.answer = AnswerState::Answered;
+        self.timers.answered(std::time::Instant::now());
+        self.queue_control(ast_control_frame_type_AST_CONTROL_ANSWER)?;
+        // Still in the audience of a stage, that's a hold now.
+        self.update_stage()
+    }
+
+    /// Follows whether the bot is suppressed on a stage channel, asking to speak once and putting
+    /// the Asterisk side on hold (or making progress if not answered yet) until it's a speaker.
+    fn update_stage(&mut self) -> ChanRes<()> {
+        if self.answer == AnswerState::Connecting {
+            return Ok(());
+        }
+
+        let (guild, channel) = (self.info.guild, self.info.channel);
+        let suppressed = match self.cache.is_suppressed(guild, channel) {
+            // Discord only suppresses members on stages, but we may not know the channel yet.
+            Some(suppressed) => suppressed && self.cache.is_stage_channel(channel),
+            None => return Ok(()),
+        };
+        if suppressed && !self.speak_requested {
+            self.speak_requested = true;
+            self.request_to_speak();
+        }
+
+        let answered = self.answer == AnswerState::Answered;
+        match (self.stage, suppressed) {
+            (StageState::Speaker, true) => {
+                debug!("Bot is in the audience of stage {channel}");
+                self.stage = StageState::Suppressed { held: answered };
+                self.queue_control(if answered {
+                    ast_control_frame_type_AST_CONTROL_HOLD
+                } else {
+                    ast_control_frame_type_AST_CONTROL_PROGRESS
+                })
+            }
+            (StageState::Suppressed { held: false }, true) if answered => {
+                self.stage = StageState::Suppressed { held: true };
+                self.queue_control(ast_control_frame_type_AST_CONTROL_HOLD)
+            }
+            (StageState::Suppressed { held }, false) => {
+                debug!("Bot is a speaker in {channel} now");
+                self.stage = StageState::Speaker;
+                if held {
+                    self.queue_control(ast_control_frame_type_AST_CONTROL_UNHOLD)?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Becomes a speaker on the stage right away if the bot may mute members, and raises its hand
+    /// otherwise.
+    fn request_to_speak(&self) {
+        let (guild, channel) = (self.info.guild, self.info.channel);
+        let permissions = self.cache.permissions(channel);
+        let rest = self.rest.clone();
+
+        tokio::spawn(async move {
+            let res = match permissions {
+                Some(permissions) if permissions.contains(Permissions::MUTE_MEMBERS) => {
+                    rest.become_speaker(guild, channel).await
+                }
+                Some(permissions) if !permissions.contains(Permissions::REQUEST_TO_SPEAK) => {
+                    warn!("Not allowed to speak or request to speak on stage {channel}");
+                    return;
+                }
+                // Without knowing our permissions, asking doesn't hurt.
+                _ => rest.request_to_speak(guild, channel).await,
+            };
+
+            if let Err(e) = res {
+                warn!("Could not request to speak on stage {channel}: {e}");
+            }
+        });
+    }
+
+    fn has_humans(&self) -> bool {
+        self.participants
+            .iter()
+            .flatten()
+            .any(|user| !self.cache.is_bot(*user))
+    }
+
+    /// Starts or stops the timer for hanging up a call without participants. It only runs once
+    /// we're connected, before that the voice channel may be empty for good reason.
+    fn update_empty_since(&mut self) {
+        let empty = self.answer != AnswerState::Connecting && !self.has_humans();
+        self.timers.set_empty(empty, std::time::Instant::now());
+    }
+
+    /// Leaves the voice channel because of [reason], the Asterisk channel is hung up afterwards.
+    async fn time_out(&mut self, reason: HangupReason) {
+        debug!("Hanging up {}: {reason:?}", self.asterisk_channel.name());
+        self.hangup_reason = Some(reason);
+
+        let voice = std::mem::replace(
+            &mut self.voice,
+            VoiceTaskState::ShuttingDown {
+                hung_up_locally: false,
+            },
+        );
+        if let VoiceTaskState::VoiceStarted { handle } = voice {
+            handle.leave_and_close().await;
+        }
+    }
+
+    /// Moves the call to [channel]. Within the guild, the voice task only changes the channel. For
+    /// another guild, a new voice task joins right away, while the bot leaves the previous guild
+    /// in the background. Until the new voice session is up, audio for Discord is dropped and
+    /// Asterisk receives silence.
+    async fn move_to(&mut self, guild: Id<GuildMarker>, channel: Id<ChannelMarker>) -> ChanRes<()> {
+        let VoiceTaskState::VoiceStarted { handle } = &self.voice else {
+            return Err(DiscordError::InternalError {
+                source: anyhow!("Call not connected yet"),
+            });
+        };
+        debug!(
+            "Moving {} to {guild}/{channel}",
+            self.asterisk_channel.name()
+        );
+        self.cache.check_join(channel, !self.listen_only)?;
+
+        let mut silence = interval(Self::MOVE_SILENCE_INTERVAL);
+        silence.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        if guild == self.info.guild {
+            self.move_silence = Some(silence);
+            if let Err(e) = handle.move_to(channel).await {
+                self.move_silence = None;
+                return Err(e);
+            }
+        } else {
+            let events = self
+                .discord
+                .exclusive_server_events(guild)
+                .await
+                .ok_or(DiscordError::AlreadyInChannelOnServer)?;
+            self.move_silence = Some(silence);
+            // The previous voice task has to close the capture before the new one appends to it.
+            if self.pcap.is_some() {
+                if let Err(e) = handle.set_capture(None).await {
+                    warn!("Could not stop capturing packets: {e}");
+                }
+            }
+
+            let handle = VoiceTaskHandle::start_task(
+                self.discord.message_sender(),
+                events,
+                self.discord.bot_user(),
+                guild,
+                channel,
+                self.send_counters.clone(),
+                self.voice_flags,
+            )
+            .await;
+            self.apply_speaking(&handle).await;
+            if let Some(path) = self.pcap.clone() {
+                if let Err(e) = handle.continue_capture(path).await {
+                    warn!("Could not capture packets: {e}");
+                }
+            }
+            let previous =
+                std::mem::replace(&mut self.voice, VoiceTaskState::VoiceStarted { handle });
+
+            // Leaving takes a few round trips to Discord, which the call shouldn't wait for. The
+            // nickname only applied to the guild we're leaving.
+            let rest = self.rest.clone();
+            let previous_guild = self.info.guild;
+            let nickname_change = self.nickname_change.take();
+            tokio::spawn(async move {
+                restore_nickname(&rest, previous_guild, nickname_change).await;
+                if let VoiceTaskState::VoiceStarted { handle } = previous {
+                    handle.leave_and_close().await;
+                }
+            });
+            self.change_nickname(guild);
+        }
+
+        // Nobody from the previous voice channel is left to hear from.
+        self.rtp.remove_all_participants();
+        self.info = CallInfo::new(&self.cache, guild, channel);
+        if let Some(call) = &self.presence_call {
+            call.moved(self.info.place());
+        }
+        self.last_notice = None;
+        self.speak_requested = false;
+
+        self.update_participants()
+    }
+
+    /// Changes our nickname in [guild] for the call, if one was requested.
+    fn change_nickname(&mut self, guild: Id<GuildMarker>) {
+        let Some(nickname) = self.nickname.clone() else {
+            return;
+        };
+
+        let previous = self.cache.nickname(guild, self.discord.bot_user());
+        let rest = self.rest.clone();
+        let change = tokio::spawn(async move { rest.set_nickname(guild, Some(&nickname)).await });
+        self.nickname_change = Some((change, previous));
+    }
+
+    /// Voice tasks start out speaking, so tell a new one if Asterisk went silent before.
+    async fn apply_speaking(&self, handle: &VoiceTaskHandle) {
+        if !self.speaking {
+            if let Err(e) = handle.set_speaking(false).await {
+                warn!("Could not stop speaking: {e}");
+            }
+        }
+    }
+
+    async fn restore_nickname(&mut self) {
+        restore_nickname(&self.rest, self.info.guild, self.nickname_change.take()).await;
+    }
+
+    /// Posts [event] into the log channel configured for the guild of the call, if any.
+    fn post_log(&self, event: CallLogEvent) {
+        let Some(&log_channel) = self.log_channels.get(&self.info.guild) else {
+            return;
+        };
+
+        let embed = CallLogEntry {
+            event,
+            caller: self.caller.as_deref(),
+            channel: self.info.channel,
+            asterisk_channel: self.asterisk_channel.name(),
+        }
+        .embed();
+        let rest = self.rest.clone();
+        // Don't hold up the call while Discord processes the message.
+        tokio::spawn(async move {
+            if let Err(e) = rest.post_embed(log_channel, &embed).await {
+                warn!("Could not log call into {log_channel}: {e}");
+            }
+        });
+    }
+
+    fn queue_control(&self, frame_type: ast_control_frame_type) -> ChanRes<()> {
+        self.queue_thread.request(
+            self.asterisk_channel.clone(),
+            ChannelWriteKind::Control { frame_type },
+        )
+    }
+
+    /// The names of the participants if there are only a few of them, otherwise how many there
+    /// are. An empty call is described by the guild and channel name.
+    fn participants_summary(&self) -> String {
+        let participants = self.participants.as_deref().unwrap_or_default();
+        match participants.len() {
+            0 => self.info.caller_name(),
+            count if count > Self::MAX_NAMED_PARTICIPANTS => format!("{count} participants"),
+            _ => participants
+                .iter()
+                .map(|user| self.display_name(*user))
+                .collect::<Vec<_>>()
+                .join(", "),
+        }
+    }
+
+    /// The name [user] is shown with in the guild, or their id if we don't know it.
+    fn display_name(&self, user: Id<UserMarker>) -> String {
+        self.cache
+            .display_name(self.info.guild, user)
+            .unwrap_or_else(|| user.to_string())
+    }
+
     pub async fn run(mut self) {
         let hung_up_locally = loop {
             if let VoiceTaskState::ShuttingDown { hung_up_locally } = &self.voice {
@@ -357,6 +1278,21 @@ impl CallWorker {
                         frame,
                     },
                 ),
+                WorkerEvent::MoveSilence => {
+                    let (data, frame) = self.rtp.silence();
+                    self.queue_thread.request(
+                        self.asterisk_channel.clone(),
+                        ChannelWriteKind::Frame {
+                            backing_memory: data,
+                            frame,
+                        },
+                    )
+                }
+                WorkerEvent::UpdateSpeaker => self.update_active_speaker(),
+                WorkerEvent::Timeout(reason) => {
+                    self.time_out(reason).await;
+                    Ok(())
+                }
             };
 
             if let Err(e) = res {
@@ -366,8 +1302,72 @@ impl CallWorker {
         };
 
         trace!("Ending call. Hung up locally: {hung_up_locally}");
+        self.rtp.finish_capture();
+        self.restore_nickname().await;
+        self.presence_call = None;
+        let stats = self.stats();
+        if self.log_started {
+            self.post_log(CallLogEvent::Ended {
+                duration: self
+                    .timers
+                    .answered_at()
+                    .map(|answered_at| answered_at.elapsed()),
+                participants: &self.participants_seen,
+                stats: &stats,
+            });
+        }
         if !hung_up_locally {
-            self.asterisk_channel.queue_hangup();
+            // Both go through the queue thread, so that the variables are set before the hangup
+            // is handled.
+            let channel = self.asterisk_channel.clone();
+            let mut variables = qos_variables(&stats);
+            if let Some(reason) = self.hangup_reason {
+                variables.push((c"DISCORD_HANGUP_REASON", reason.name().to_string()));
+            }
+            let _ = self
+                .queue_thread
+                .request(channel.clone(), ChannelWriteKind::Variables(variables));
+            let cause = self.hangup_reason.map(hangup_cause);
+            let _ = self
+                .queue_thread
+                .request(channel, ChannelWriteKind::Hangup { cause });
         }
     }
 }
+
+/// Waits for the nickname [change] in [guild] and sets the nickname from before it again.
+async fn restore_nickname(
+    rest: &RestClient,
+    guild: Id<GuildMarker>,
+    change: Option<(JoinHandle<ChanRes<()>>, Option<String>)>,
+) {
+    let Some((change, previous)) = change else {
+        return;
+    };
+
+    match change.await {
+        Ok(Ok(())) => {
+            if let Err(e) = rest.set_nickname(guild, previous.as_deref()).await {
+                warn!("Could not restore nickname in {guild}: {e}");
+            }
+        }
+        Ok(Err(e)) => warn!("Could not change nickname in {guild}: {e}"),
+        Err(_) => {}
+    }
+}
+
+fn hangup_cause(reason: HangupReason) -> c_int {
+    match reason {
+        // The others have left, like a normal hangup on their end.
+        HangupReason::Empty => AST_CAUSE_NORMAL_CLEARING,
+        HangupReason::MaxDuration | HangupReason::Idle => AST_CAUSE_RECOVERY_ON_TIMER_EXPIRE,
+    }
+}
+
+/// Channel variables describing the quality of a call, similar to `RTPAUDIOQOS`.
+pub fn qos_variables(stats: &CallStats) -> Vec<(&'static CStr, String)> {
+    vec![
+        (c"DISCORD_QOS", stats.qos()),
+        (c"DISCORD_QOS_PARTICIPANTS", stats.participants_qos()),
+    ]
+}
@@ -1,6 +1,7 @@
 use std::{
-    ffi::{c_char, c_int, CStr},
+    ffi::{c_char, c_int, CStr, CString},
     os::raw::c_void,
+    path::Path,
     ptr::{self, null, null_mut},
 };
 
@@ -10,14 +11,32 @@ use asterisk::{
     channel::Channel,
     formats::{Format, FormatCapabilities},
 };
+use chan_discord_common::{
+    audio::{archive::CallArchive, recording::CallRecording},
+    dial::Destination,
+    error::{ChanRes, DiscordError},
+};
 use log::{debug, trace, warn};
+use twilight_model::id::{
+    marker::{ChannelMarker, GuildMarker},
+    Id,
+};
 
 use asterisk_sys::bindings::{
-    __ast_channel_alloc, ama_flags_AST_AMA_NONE, ast_assigned_ids, ast_channel,
-    ast_channel_state_AST_STATE_DOWN, ast_channel_tech, ast_format_cap, ast_frame, ast_null_frame,
+    __ast_channel_alloc, ama_flags_AST_AMA_NONE, ao2_lock_req_AO2_LOCK_REQ_MUTEX, ast_assigned_ids,
+    ast_channel, ast_channel_state_AST_STATE_DOWN, ast_channel_tech,
+    ast_control_frame_type_AST_CONTROL_HOLD, ast_control_frame_type_AST_CONTROL_PROGRESS,
+    ast_control_frame_type_AST_CONTROL_RINGING, ast_control_frame_type_AST_CONTROL_UNHOLD,
+    ast_format_cap, ast_frame, ast_null_frame, AST_CAUSE_CALL_REJECTED, AST_CAUSE_FAILURE,
+    AST_CAUSE_OUTGOING_CALL_BARRED, AST_CAUSE_USER_BUSY,
 };
 
-use crate::{call::CallHandle, with_worker};
+use crate::{
+    call::{qos_variables, CallCapture, CallHandle, PendingMove},
+    config::{AlertingMode, HoldMode},
+    functions::copy_to_buffer,
+    with_worker,
+};
 
 pub static mut DISCORD_TECH: ast_channel_tech = const {
     let mut tech = unsafe { std::mem::zeroed::<ast_channel_tech>() };
@@ -28,6 +47,9 @@ pub static mut DISCORD_TECH: ast_channel_tech = const {
     tech.call = Some(call);
     tech.hangup = Some(hangup);
     tech.fixup = Some(fixup);
+    tech.indicate = Some(indicate);
+    tech.transfer = Some(transfer);
+    tech.func_channel_read = Some(func_channel_read);
 
     tech.read = Some(read);
     tech.write = Some(write);
@@ -35,6 +57,85 @@ pub static mut DISCORD_TECH: ast_channel_tech = const {
     tech
 };
 
+pub fn is_discord_channel(channel: &Channel) -> bool {
+    ptr::eq(channel.tech(), unsafe { ptr::addr_of!(DISCORD_TECH) })
+}
+
+/// Finds the Discord channel that [channel] is talking to: Either [channel] itself, or the other
+/// side of the two-party bridge it's in.
+pub fn discord_channel_for(channel: &Channel) -> Option<Ao2<Channel>> {
+    if is_discord_channel(channel) {
+        let raw = ptr::addr_of!(*channel.to_asterisk()).cast_mut();
+        return Some(Channel::from_obj(unsafe { Ao2::clone_raw(raw) }));
+    }
+
+    channel
+        .bridge_peer()
+        .filter(|peer| is_discord_channel(peer))
+}
+
+/// Locks a Discord channel and runs [body] with the call it represents.
+///
+/// Returns `None` if [channel] is not a Discord channel or if it has already been hung up.
+pub fn with_call<R>(channel: &Ao2<Channel>, body: impl FnOnce(&mut CallHandle) -> R) -> Option<R> {
+    let lock = unsafe { channel.lock(ao2_lock_req_AO2_LOCK_REQ_MUTEX) }.ok()?;
+    if !is_discord_channel(&lock) {
+        return None;
+    }
+
+    let call = unsafe { lock.get_tech_data().cast::<CallHandle>().as_mut()? };
+    Some(body(call))
+}
+
+/// Moves the call on a Discord [channel] to the voice [voice_channel] in [guild], see
+/// [CallHandle::start_move], and renames the caller after it.
+///
+/// The channel is only locked to start the move and to take on its result, so that audio keeps
+/// flowing while we wait for the worker.
+///
+/// Returns `None` if [channel] is not a Discord channel or if it has already been hung up.
+pub fn move_call(
+    channel: &Ao2<Channel>,
+    guild: Id<GuildMarker>,
+    voice_channel: Id<ChannelMarker>,
+) -> Option<ChanRes<()>> {
+    let pending = with_call(channel, |call| {
+        // Moving is subject to the same lists as dialing.
+        let profile = call.profile();
+        if let Some(Err(e)) =
+            with_worker(|discord| discord.check_access(guild, voice_channel, profile))
+        {
+            return Err(e);
+        }
+
+        Ok(call.start_move(guild, voice_channel))
+    })?;
+    let info = match pending.and_then(PendingMove::wait) {
+        Ok(info) => info,
+        Err(e) => return Some(Err(e)),
+    };
+
+    let mut lock = unsafe { channel.lock(ao2_lock_req_AO2_LOCK_REQ_MUTEX) }.ok()?;
+    if !is_discord_channel(&lock) {
+        return None;
+    }
+
+    // The channel may have been hung up during the move.
+    let call = unsafe { lock.get_tech_data().cast::<CallHandle>().as_mut()? };
+    lock.set_caller_name(&info.caller_name());
+    call.moved(info);
+    Some(Ok(()))
+}
+
+/// Like [with_call], but looking up the Discord channel by the name of a channel.
+pub fn with_call_by_name<R>(name: &str, body: impl FnOnce(&mut CallHandle) -> R) -> Option<R> {
+    let name = CString::new(name).ok()?;
+    let channel = Channel::get_by_name(&name)?;
+    let channel = discord_channel_for(&channel)?;
+
+    with_call(&channel, body)
+}
+
 unsafe extern "C" fn read(_chan: *mut ast_channel) -> *mut ast_frame {
     debug!("Should not call read for Discord channels, we're pushing frames into channel");
     ptr::addr_of_mut!(ast_null_frame)
@@ -60,15 +161,30 @@ unsafe extern "C" fn requester(
     ids: *const ast_assigned_ids,
     requestor: *const ast_channel,
     addr: *const c_char,
-    _cause: *mut c_int,
+    cause: *mut c_int,
 ) -> *mut ast_channel {
-    let Some(destination) = CallHandle::parse_destination_addr(CStr::from_ptr(addr)) else {
+    let address = CStr::from_ptr(addr).to_string_lossy();
+    let Some(destination) = Destination::parse(&address) else {
         warn!(
-            "Requested discord call with invalid destination {:?}, format is <server>/<channel>",
-            CStr::from_ptr(addr)
+            "Requested discord call with invalid destination {address:?}, format is \
+            <server>/<channel>[,<options>]"
         );
         return null_mut();
     };
+    let (guild, voice_channel) = (destination.guild, destination.channel);
+    let profile = destination.options.profile.as_deref();
+    if let Some(Err(e)) = with_worker(|discord| discord.check_access(guild, voice_channel, profile))
+    {
+        warn!("Refusing call to {guild}/{voice_channel}: {e}");
+        if let Some(cause) = cause.as_mut() {
+            *cause = dial_failure_cause(&e);
+        }
+        return null_mut();
+    }
+    // Options don't become part of the channel name.
+    let Ok(name) = CString::new(format!("{guild}/{voice_channel}")) else {
+        return null_mut();
+    };
 
     if !requestor.is_null() {
         let requestor = Channel::from_asterisk(requestor.as_ref().unwrap());
@@ -110,7 +226,7 @@ unsafe extern "C" fn requester(
         c_line!(),
         c_str!("requester"),
         c"Discord/%s".as_ptr(),
-        addr,
+        name.as_ptr(),
     )) else {
         return null_mut();
     };
@@ -123,8 +239,7 @@ unsafe extern "C" fn requester(
     snapshot.channel.set_writeformat(&Format::slin48());
     snapshot.channel.set_native_formats(&capabilities);
 
-    let Some(call) =
-        with_worker(|discord| discord.prepare_call(channel.clone(), destination.0, destination.1))
+    let Some(call) = with_worker(|discord| discord.prepare_call(channel.clone(), destination))
     else {
         warn!("Worker not set up, can't start channel.");
         return null_mut();
@@ -133,14 +248,15 @@ unsafe extern "C" fn requester(
     let call = match call {
         Ok(call) => call,
         Err(e) => {
-            warn!(
-                "Could not prepare call to {}/{}: {}",
-                destination.0, destination.1, e
-            );
+            warn!("Could not prepare call to {guild}/{voice_channel}: {e}");
+            if let Some(cause) = cause.as_mut() {
+                *cause = dial_failure_cause(&e);
+            }
             return null_mut();
         }
     };
 
+    snapshot.channel.set_caller_name(&call.info().caller_name());
     snapshot
         .channel
         .set_tech_data(Box::into_raw(Box::new(call)).cast::<c_void>());
@@ -149,12 +265,32 @@ unsafe extern "C" fn requester(
     Channel::into_raw(channel)
 }
 
+/// The hangup cause of a dial that failed because the call couldn't be prepared.
+fn dial_failure_cause(error: &DiscordError) -> c_int {
+    match error {
+        DiscordError::MissingPermissions { .. } => AST_CAUSE_OUTGOING_CALL_BARRED,
+        DiscordError::ChannelFull | DiscordError::AlreadyInChannelOnServer => AST_CAUSE_USER_BUSY,
+        DiscordError::NotAllowed { .. } => AST_CAUSE_CALL_REJECTED,
+        _ => AST_CAUSE_FAILURE,
+    }
+}
+
 unsafe extern "C" fn call(chan: *mut ast_channel, _addr: *const c_char, _timeout: c_int) -> c_int {
     // Note: This is called with an exclusive lock on the channel, so we can use mut
     let chan = Channel::from_asterisk_mut(chan.as_mut().unwrap());
-    let call = chan.get_tech_data().cast::<CallHandle>().as_ref().unwrap();
+    let capture = CallCapture {
+        recording: start_capture(chan, c"DISCORD_RECORDING", CallRecording::new),
+        archive: start_capture(chan, c"DISCORD_ARCHIVE", CallArchive::new),
+        pcap: start_capture(chan, c"DISCORD_PCAP", |directory, prefix| {
+            std::fs::create_dir_all(&directory)?;
+            Ok(Path::new(&directory).join(format!("{prefix}.pcap")))
+        }),
+    };
+    // Dial has made the calling side our connected line by now.
+    let caller = chan.connected_line().caller_id();
+    let call = chan.get_tech_data().cast::<CallHandle>().as_mut().unwrap();
 
-    match call.start_joining() {
+    match call.start_joining(capture, caller) {
         Ok(()) => 0,
         Err(e) => {
             debug!(
@@ -166,13 +302,42 @@ unsafe extern "C" fn call(chan: *mut ast_channel, _addr: *const c_char, _timeout
     }
 }
 
+/// Prepares a recording, archive or packet capture of the call if [variable] is set to a directory
+/// on the channel. Files are named after the unique id of the channel.
+fn start_capture<T>(
+    chan: &Channel,
+    variable: &CStr,
+    create: impl FnOnce(String, String) -> anyhow::Result<T>,
+) -> Option<T> {
+    let directory = chan.get_variable(variable)?;
+    if directory.is_empty() {
+        return None;
+    }
+
+    match create(directory.clone(), chan.unique_id().to_string()) {
+        Ok(capture) => {
+            debug!("Capturing {} into {directory}", chan.name());
+            Some(capture)
+        }
+        Err(e) => {
+            warn!("Not capturing {} ({variable:?}): {e:?}", chan.name());
+            None
+        }
+    }
+}
+
 unsafe extern "C" fn hangup(chan: *mut ast_channel) -> c_int {
     let chan = Channel::from_asterisk_mut(chan.as_mut().unwrap());
-    let call = Box::from_raw(chan.get_tech_data().cast::<CallHandle>());
+    let mut call = Box::from_raw(chan.get_tech_data().cast::<CallHandle>());
     trace!("hangup called on discord channel tech");
 
     let res = match call.hangup() {
-        Ok(()) => 0,
+        Ok(stats) => {
+            for (name, value) in qos_variables(&stats) {
+                chan.set_variable(name, &value);
+            }
+            0
+        }
         Err(e) => {
             debug!("Could not hang up: {e:?}");
             1
@@ -182,6 +347,156 @@ unsafe extern "C" fn hangup(chan: *mut ast_channel) -> c_int {
     res
 }
 
+const HOLD: c_int = ast_control_frame_type_AST_CONTROL_HOLD as c_int;
+const UNHOLD: c_int = ast_control_frame_type_AST_CONTROL_UNHOLD as c_int;
+const RINGING: c_int = ast_control_frame_type_AST_CONTROL_RINGING as c_int;
+const PROGRESS: c_int = ast_control_frame_type_AST_CONTROL_PROGRESS as c_int;
+
+/// Passes indications from the other side of the call on to Discord, as configured with `hold`
+/// and `alerting`.
+///
+/// Returning an error makes Asterisk generate tones for some indications itself, which is how we
+/// play the ring tone.
+unsafe extern "C" fn indicate(
+    chan: *mut ast_channel,
+    condition: c_int,
+    data: *const c_void,
+    datalen: usize,
+) -> c_int {
+    // Note: This is called with an exclusive lock on the channel, so we can use mut
+    let chan = Channel::from_asterisk_mut(chan.as_mut().unwrap());
+    let Some(call) = chan.get_tech_data().cast::<CallHandle>().as_mut() else {
+        return -1;
+    };
+    let options = call.options().clone();
+
+    let res = match condition {
+        HOLD => match options.hold {
+            HoldMode::Mute => call.set_muted(true),
+            HoldMode::MusicOnHold => {
+                // The holding side may suggest a music class.
+                let suggested = (datalen > 0)
+                    .then(|| CStr::from_ptr(data.cast::<c_char>()))
+                    .filter(|class| !class.is_empty());
+                chan.start_music_on_hold(suggested, options.music_class.as_deref());
+                Ok(())
+            }
+        },
+        UNHOLD => match options.hold {
+            HoldMode::Mute => call.set_muted(false),
+            HoldMode::MusicOnHold => {
+                chan.stop_music_on_hold();
+                Ok(())
+            }
+        },
+        // Only the tone mode may fail the ring indication, which is what makes Asterisk play the
+        // ring tone.
+        RINGING | PROGRESS => match options.alerting {
+            AlertingMode::None => Ok(()),
+            AlertingMode::Notice => {
+                let notice = match condition {
+                    RINGING => "Ringing…",
+                    _ => "Call in progress…",
+                };
+                if let Err(e) = call.post_notice(notice) {
+                    debug!("Could not post notice into Discord: {e}");
+                }
+                Ok(())
+            }
+            AlertingMode::Tone if condition == RINGING => return -1,
+            AlertingMode::Tone => {
+                // The other side sends early media now.
+                chan.stop_tones();
+                Ok(())
+            }
+        },
+        _ => return -1,
+    };
+
+    match res {
+        Ok(()) => 0,
+        Err(e) => {
+            debug!("Could not indicate {condition} to Discord: {e}");
+            -1
+        }
+    }
+}
+
+/// Moves the call to another voice channel for `Transfer(Discord/<guild>/<channel>)`, without
+/// hanging up the Asterisk channel.
+///
+/// Asterisk keeps the channel locked during this, but the worker answers as soon as the bot starts
+/// joining the new voice channel.
+unsafe extern "C" fn transfer(chan: *mut ast_channel, dest: *const c_char) -> c_int {
+    let chan = Channel::from_obj(Ao2::clone_raw(chan));
+    let dest = CStr::from_ptr(dest).to_string_lossy();
+    // Transfer() strips our technology from the destination, other callers might not.
+    let address = dest.strip_prefix("Discord/").unwrap_or(&dest);
+    let Some((guild, voice_channel)) = Destination::parse_address(address) else {
+        warn!("Can't transfer to {dest:?}, format is <server>/<channel>");
+        return -1;
+    };
+
+    match move_call(&chan, guild, voice_channel) {
+        Some(Ok(())) => 0,
+        Some(Err(e)) => {
+            warn!("Could not transfer {} to {address}: {e}", chan.name());
+            -1
+        }
+        None => -1,
+    }
+}
+
+/// Answers `CHANNEL(<item>)` on Discord channels for these items:
+///
+/// - `discord_guild`, `discord_channel`: The ids of the guild and voice channel.
+/// - `discord_guild_name`, `discord_channel_name`: Their names, empty if they weren't known when
+///   the call was placed.
+/// - `discord_participants`: The ids of the users currently in the voice channel, separated by
+///   commas.
+unsafe extern "C" fn func_channel_read(
+    chan: *mut ast_channel,
+    _function: *const c_char,
+    data: *mut c_char,
+    buf: *mut c_char,
+    len: usize,
+) -> c_int {
+    let chan = Channel::from_obj(Ao2::clone_raw(chan));
+    let item = CStr::from_ptr(data).to_string_lossy();
+
+    let value = with_call(&chan, |call| {
+        let info = call.info();
+        let value = match item.trim() {
+            "discord_guild" => info.guild.to_string(),
+            "discord_guild_name" => info.guild_name.clone().unwrap_or_default(),
+            "discord_channel" => info.channel.to_string(),
+            "discord_channel_name" => info.channel_name.clone().unwrap_or_default(),
+            "discord_participants" => match call.participants() {
+                Ok(participants) => participants
+                    .iter()
+                    .map(|user| user.to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                Err(e) => {
+                    debug!("Could not read participants: {e}");
+                    return None;
+                }
+            },
+            _ => return None,
+        };
+
+        Some(value)
+    });
+
+    match value.flatten() {
+        Some(value) => {
+            copy_to_buffer(&value, buf, len);
+            0
+        }
+        None => -1,
+    }
+}
+
 unsafe extern "C" fn fixup(_old: *mut ast_channel, new: *mut ast_channel) -> c_int {
     // We need to drop references to the old channel in our CallHandle structure
     let chan = Channel::from_obj(Ao2::clone_raw(new));
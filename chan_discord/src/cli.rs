@@ -0,0 +1,154 @@
+use std::path::PathBuf;
+
+use asterisk::cli::{CliArgs, CliEntry, CliResult};
+use chan_discord_common::audio::mixer::Volume;
+
+use crate::{channel_tech::with_call_by_name, functions::parse_user_id};
+
+pub static mut CLI_COMMANDS: [CliEntry; 4] = [
+    CliEntry::new(
+        c"discord set volume",
+        c"Change the volume of a Discord participant",
+        c"Usage: discord set volume <channel> <user id> <volume>\n       \
+Changes the volume of a participant in the Discord call on or bridged with <channel>.\n       \
+<volume> is a change in decibels (e.g. -6), mute or unmute.\n",
+        set_volume,
+    ),
+    CliEntry::new(
+        c"discord capture start",
+        c"Capture the packets of a Discord call",
+        c"Usage: discord capture start <channel> <file>\n       \
+Writes decrypted RTP and RTCP packets of the Discord call on or bridged with <channel>\n       \
+into a pcap <file>.\n",
+        capture_start,
+    ),
+    CliEntry::new(
+        c"discord capture stop",
+        c"Stop capturing the packets of a Discord call",
+        c"Usage: discord capture stop <channel>\n       \
+Stops a packet capture started on the Discord call on or bridged with <channel>.\n",
+        capture_stop,
+    ),
+    CliEntry::new(
+        c"discord show call",
+        c"Show statistics of a Discord call",
+        c"Usage: discord show call <channel>\n       \
+Shows packet counters, loss and jitter of each participant in the Discord call on or bridged\n       \
+with <channel>.\n",
+        show_call,
+    ),
+];
+
+fn set_volume(args: &CliArgs) -> CliResult {
+    let argv = args.argv();
+    let [_, _, _, channel, user, volume] = argv[..] else {
+        return CliResult::ShowUsage;
+    };
+
+    let Some(user) = parse_user_id(user) else {
+        args.print(&format!("Invalid Discord user id: {user}"));
+        return CliResult::ShowUsage;
+    };
+    let Ok(volume) = volume.parse::<Volume>() else {
+        args.print(&format!("Invalid volume: {volume}"));
+        return CliResult::ShowUsage;
+    };
+
+    match with_call_by_name(channel, |call| call.set_user_volume(user, volume)) {
+        Some(Ok(())) => {
+            args.print(&format!("Volume of {user} on {channel} set to {volume}"));
+            CliResult::Success
+        }
+        Some(Err(e)) => {
+            args.print(&format!("Could not change volume: {e}"));
+            CliResult::Failure
+        }
+        None => {
+            args.print(&format!("{channel} is not connected to a Discord call"));
+            CliResult::Failure
+        }
+    }
+}
+
+fn capture_start(args: &CliArgs) -> CliResult {
+    let argv = args.argv();
+    let [_, _, _, channel, file] = argv[..] else {
+        return CliResult::ShowUsage;
+    };
+
+    set_capture(args, channel, Some(PathBuf::from(file)))
+}
+
+fn capture_stop(args: &CliArgs) -> CliResult {
+    let argv = args.argv();
+    let [_, _, _, channel] = argv[..] else {
+        return CliResult::ShowUsage;
+    };
+
+    set_capture(args, channel, None)
+}
+
+fn set_capture(args: &CliArgs, channel: &str, path: Option<PathBuf>) -> CliResult {
+    let message = match &path {
+        Some(path) => format!("Capturing packets of {channel} into {}", path.display()),
+        None => format!("Stopped capturing packets of {channel}"),
+    };
+
+    match with_call_by_name(channel, |call| call.set_capture(path)) {
+        Some(Ok(())) => {
+            args.print(&message);
+            CliResult::Success
+        }
+        Some(Err(e)) => {
+            args.print(&format!("Could not change packet capture: {e}"));
+            CliResult::Failure
+        }
+        None => {
+            args.print(&format!("{channel} is not connected to a Discord call"));
+            CliResult::Failure
+        }
+    }
+}
+
+fn show_call(args: &CliArgs) -> CliResult {
+    let argv = args.argv();
+    let [_, _, _, channel] = argv[..] else {
+        return CliResult::ShowUsage;
+    };
+
+    let stats = match with_call_by_name(channel, |call| call.stats()) {
+        Some(Ok(stats)) => stats,
+        Some(Err(e)) => {
+            args.print(&format!("Could not get call statistics: {e}"));
+            return CliResult::Failure;
+        }
+        None => {
+            args.print(&format!("{channel} is not connected to a Discord call"));
+            return CliResult::Failure;
+        }
+    };
+
+    args.print(&format!(
+        "Sent: {} packets, {} bytes",
+        stats.sent.packets, stats.sent.bytes
+    ));
+    args.print(&format!(
+        "{:<20} {:>10} {:>10} {:>8} {:>8} {:>8} {:>10}",
+        "User", "SSRC", "Received", "Lost", "Late", "Errors", "Jitter"
+    ));
+    for participant in &stats.participants {
+        let received = &participant.received;
+        args.print(&format!(
+            "{:<20} {:>10} {:>10} {:>8} {:>8} {:>8} {:>8.1}ms",
+            participant.user,
+            participant.ssrc,
+            received.packets,
+            received.lost,
+            received.late,
+            received.decode_errors,
+            received.jitter.as_secs_f64() * 1000.0
+        ));
+    }
+
+    CliResult::Success
+}
@@ -0,0 +1,274 @@
+use std::{collections::HashMap, ffi::CString, str::FromStr};
+
+use asterisk::config::{AsteriskConfig, ConfigCategory};
+use chan_discord_common::{
+    access::{AccessList, AccessTarget},
+    dial::{parse_seconds, CallTimeouts, Destination},
+    discord::presence::PresenceTemplates,
+};
+use log::{info, warn};
+use twilight_model::id::{
+    marker::{ChannelMarker, GuildMarker},
+    Id,
+};
+
+use crate::jitter::JitterBufferKind;
+
+/// Options from the general section of discord.conf.
+pub struct ModuleOptions {
+    pub token: String,
+    pub call: CallOptions,
+    pub access: AccessOptions,
+    /// The activity of the bot, set with `presencecall`, `presencecalls` and `presenceidle`.
+    pub presence: PresenceTemplates,
+}
+
+/// Where calls may go: the `allow` and `deny` lists of the general section apply to every call,
+/// those of other sections (profiles) to calls dialed with that profile.
+#[derive(Debug, Clone, Default)]
+pub struct AccessOptions {
+    pub general: AccessList,
+    pub profiles: HashMap<String, AccessList>,
+}
+
+/// The options applying to every call.
+#[derive(Debug, Clone, Default)]
+pub struct CallOptions {
+    pub jitter_buffer: JitterBufferKind,
+    pub hold: HoldMode,
+    /// The music on hold class used with [HoldMode::MusicOnHold] if the holding side doesn't
+    /// suggest one, `musicclass` in discord.conf.
+    pub music_class: Option<CString>,
+    pub alerting: AlertingMode,
+    pub answer: AnswerPolicy,
+    /// `emptytimeout`, `maxduration` and `idletimeout`, which dial options can override.
+    pub timeouts: CallTimeouts,
+    /// The text channels calls are logged into, by the guild of the voice channel. Set with
+    /// `logchannel=<guild>/<channel>` lines.
+    pub log_channels: HashMap<Id<GuildMarker>, Id<ChannelMarker>>,
+}
+
+/// What Discord hears while the other side has put the call on hold, set with `hold`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HoldMode {
+    /// Nothing, and the bot shows up as muted.
+    #[default]
+    Mute,
+    MusicOnHold,
+}
+
+/// How Discord learns that the other side is ringing or making progress, set with `alerting`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AlertingMode {
+    /// Nothing, Discord hears silence until the call is answered.
+    #[default]
+    None,
+    /// A message in the chat of the voice channel.
+    Notice,
+    /// The ring tone of the channel's tone zone, generated by Asterisk.
+    Tone,
+}
+
+/// When a call is answered on the Asterisk side, set with `answer`. Before that, the audio from
+/// Discord is passed on as early media.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AnswerPolicy {
+    /// As soon as we're connected to the voice channel.
+    #[default]
+    Connect,
+    /// Once someone other than a bot is in the voice channel.
+    Participant,
+    /// Once someone in the voice channel talks.
+    Speech,
+}
+
+impl ModuleOptions {
+    pub fn from_config(config: &AsteriskConfig) -> Option<Self> {
+        let category = config.category(c"general")?;
+        let mut token: Option<String> = None;
+        let mut call = CallOptions::default();
+        let mut access = AccessOptions::default();
+        let mut presence = PresenceTemplates::default();
+
+        for variable in &category {
+            let Ok(name) = variable.name().to_str() else {
+                continue;
+            };
+            let Ok(value) = variable.value().to_str() else {
+                warn!("Invalid config field {name}: Not valid utf8");
+                return None;
+            };
+
+            if name == "token" {
+                token = Some(value.to_string());
+            } else if name == "jitterbuffer" {
+                let Ok(kind) = value.parse() else {
+                    warn!("Invalid jitterbuffer {value}, expected asterisk or adaptive");
+                    return None;
+                };
+                call.jitter_buffer = kind;
+            } else if name == "hold" {
+                let Ok(mode) = value.parse() else {
+                    warn!("Invalid hold {value}, expected mute or moh");
+                    return None;
+                };
+                call.hold = mode;
+            } else if name == "musicclass" {
+                call.music_class = Some(variable.value().to_owned()).filter(|c| !c.is_empty());
+            } else if name == "alerting" {
+                let Ok(mode) = value.parse() else {
+                    warn!("Invalid alerting {value}, expected none, notice or tone");
+                    return None;
+                };
+                call.alerting = mode;
+            } else if name == "answer" {
+                let Ok(policy) = value.parse() else {
+                    warn!("Invalid answer {value}, expected connect, participant or speech");
+                    return None;
+                };
+                call.answer = policy;
+            } else if name == "logchannel" {
+                let Some((guild, channel)) = Destination::parse_address(value) else {
+                    warn!("Invalid logchannel {value}, expected <server>/<channel>");
+                    return None;
+                };
+                call.log_channels.insert(guild, channel);
+            } else if name == "allow" || name == "deny" {
+                if !add_access_entries(&mut access.general, name, value) {
+                    return None;
+                }
+            } else if let Some(template) = match name {
+                "presencecall" => Some(&mut presence.call),
+                "presencecalls" => Some(&mut presence.calls),
+                "presenceidle" => Some(&mut presence.idle),
+                _ => None,
+            } {
+                *template = value.to_string();
+            } else if let Some(timeout) = match name {
+                "emptytimeout" => Some(&mut call.timeouts.empty),
+                "maxduration" => Some(&mut call.timeouts.max_duration),
+                "idletimeout" => Some(&mut call.timeouts.idle),
+                _ => None,
+            } {
+                let Some(seconds) = parse_seconds(value) else {
+                    warn!("Invalid {name} {value}, expected a number of seconds");
+                    return None;
+                };
+                *timeout = Some(seconds);
+            } else {
+                info!("Unknown variable {name} in configuration file");
+            }
+        }
+
+        for profile in config.category_names() {
+            let Ok(name) = profile.to_str() else {
+                warn!("Invalid profile {profile:?}: Not valid utf8");
+                return None;
+            };
+            if name == "general" {
+                continue;
+            }
+
+            let list = access_list_from_profile(&config.category(profile)?)?;
+            access.profiles.insert(name.to_string(), list);
+        }
+
+        Some(ModuleOptions {
+            token: token?,
+            call,
+            access,
+            presence,
+        })
+    }
+}
+
+impl AccessOptions {
+    /// Checks [target] against the general lists and those of [profile], returning why calls to
+    /// it aren't allowed.
+    pub fn check(&self, target: &AccessTarget, profile: Option<&str>) -> Result<(), String> {
+        if !self.general.permits(target) {
+            return Err("not allowed in the general section".to_string());
+        }
+
+        match profile {
+            Some(profile) => match self.profiles.get(profile) {
+                Some(list) if list.permits(target) => Ok(()),
+                Some(_) => Err(format!("not allowed in profile {profile}")),
+                None => Err(format!("unknown profile {profile}")),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+fn access_list_from_profile(category: &ConfigCategory) -> Option<AccessList> {
+    let mut list = AccessList::default();
+
+    for variable in category {
+        let (Ok(name), Ok(value)) = (variable.name().to_str(), variable.value().to_str()) else {
+            warn!("Invalid profile entry: Not valid utf8");
+            return None;
+        };
+
+        if name == "allow" || name == "deny" {
+            if !add_access_entries(&mut list, name, value) {
+                return None;
+            }
+        } else {
+            info!("Unknown variable {name} in profile");
+        }
+    }
+
+    Some(list)
+}
+
+/// Adds the entries of an `allow` or `deny` line to [list], returning whether they were valid.
+fn add_access_entries(list: &mut AccessList, name: &str, value: &str) -> bool {
+    let added = match name {
+        "allow" => list.allow(value),
+        _ => list.deny(value),
+    };
+    if added.is_none() {
+        warn!("Invalid {name} {value}, expected <server>[/<channel>] by id or name");
+    }
+
+    added.is_some()
+}
+
+impl FromStr for HoldMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mute" => Ok(HoldMode::Mute),
+            "moh" => Ok(HoldMode::MusicOnHold),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for AlertingMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(AlertingMode::None),
+            "notice" => Ok(AlertingMode::Notice),
+            "tone" => Ok(AlertingMode::Tone),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for AnswerPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "connect" => Ok(AnswerPolicy::Connect),
+            "participant" => Ok(AnswerPolicy::Participant),
+            "speech" => Ok(AnswerPolicy::Speech),
+            _ => Err(()),
+        }
+    }
+}
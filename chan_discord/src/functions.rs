@@ -0,0 +1,108 @@
+use std::{
+    ffi::{c_char, c_int, CStr},
+    ptr,
+};
+
+use asterisk::{
+    astobj2::{Ao2, AsteriskWrapper},
+    channel::Channel,
+};
+use asterisk_sys::bindings::{ast_channel, ast_custom_function};
+use chan_discord_common::audio::mixer::Volume;
+use log::warn;
+use twilight_model::id::{marker::UserMarker, Id};
+
+use crate::channel_tech::{discord_channel_for, with_call};
+
+/// `DISCORD_USER_VOLUME(<user id>)`: Reads or changes the volume of a Discord participant, either
+/// on a Discord channel or on a channel bridged with one.
+///
+/// Values are changes in decibels (`-6`), `mute` or `unmute`.
+pub static mut DISCORD_USER_VOLUME: ast_custom_function = const {
+    let mut function = unsafe { std::mem::zeroed::<ast_custom_function>() };
+    function.name = c"DISCORD_USER_VOLUME".as_ptr();
+    function.read = Some(read_user_volume);
+    function.write = Some(write_user_volume);
+
+    function
+};
+
+pub fn parse_user_id(str: &str) -> Option<Id<UserMarker>> {
+    Id::new_checked(str.trim().parse().ok()?)
+}
+
+unsafe fn function_target(
+    chan: *mut ast_channel,
+    data: *const c_char,
+) -> Option<(Ao2<Channel>, Id<UserMarker>)> {
+    let Some(chan) = chan.as_ref() else {
+        warn!("DISCORD_USER_VOLUME requires a channel");
+        return None;
+    };
+    let chan = Channel::from_asterisk(chan);
+
+    let Some(user) = CStr::from_ptr(data).to_str().ok().and_then(parse_user_id) else {
+        warn!("DISCORD_USER_VOLUME requires a Discord user id as argument");
+        return None;
+    };
+
+    let Some(discord) = discord_channel_for(chan) else {
+        warn!("{} is not connected to a Discord channel", chan.name());
+        return None;
+    };
+
+    Some((discord, user))
+}
+
+unsafe extern "C" fn read_user_volume(
+    chan: *mut ast_channel,
+    _function: *const c_char,
+    data: *mut c_char,
+    buf: *mut c_char,
+    len: usize,
+) -> c_int {
+    let Some((discord, user)) = function_target(chan, data) else {
+        return -1;
+    };
+
+    match with_call(&discord, |call| call.user_volume(user)) {
+        Some(Ok(volume)) => {
+            copy_to_buffer(&volume.to_string(), buf, len);
+            0
+        }
+        _ => -1,
+    }
+}
+
+unsafe extern "C" fn write_user_volume(
+    chan: *mut ast_channel,
+    _function: *const c_char,
+    data: *mut c_char,
+    value: *const c_char,
+) -> c_int {
+    let Some((discord, user)) = function_target(chan, data) else {
+        return -1;
+    };
+
+    let value = CStr::from_ptr(value).to_string_lossy();
+    let Ok(volume) = value.parse::<Volume>() else {
+        warn!("Invalid volume for DISCORD_USER_VOLUME: {value}");
+        return -1;
+    };
+
+    match with_call(&discord, |call| call.set_user_volume(user, volume)) {
+        Some(Ok(())) => 0,
+        _ => -1,
+    }
+}
+
+/// Copies [value] into a buffer of [len] bytes, truncating it if necessary.
+pub unsafe fn copy_to_buffer(value: &str, buf: *mut c_char, len: usize) {
+    if len == 0 {
+        return;
+    }
+
+    let copied = value.len().min(len - 1);
+    ptr::copy_nonoverlapping(value.as_ptr().cast::<c_char>(), buf, copied);
+    *buf.add(copied) = 0;
+}
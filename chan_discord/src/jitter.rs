@@ -0,0 +1,111 @@
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use asterisk::jitterbuffer::{JitterBuffer, JitterBufferErr, JitterFrameType};
+use asterisk_sys::bindings::jb_conf;
+use chan_discord_common::audio::adaptive_jitter::{AdaptiveConfig, AdaptiveJitterBuffer};
+use chan_discord_common::audio::jitter::{
+    self, BufferedFrame, JitterBufferFactory, JitterStats, Playout,
+};
+
+/// The jitter buffer used for audio received from Discord, set with `jitterbuffer` in the general
+/// section of discord.conf.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum JitterBufferKind {
+    #[default]
+    Asterisk,
+    Adaptive,
+}
+
+impl JitterBufferKind {
+    pub fn factory(self) -> JitterBufferFactory {
+        match self {
+            JitterBufferKind::Asterisk => AsteriskJitterBuffer::factory(),
+            JitterBufferKind::Adaptive => {
+                Box::new(|_| Box::new(AdaptiveJitterBuffer::new(AdaptiveConfig::default())))
+            }
+        }
+    }
+}
+
+impl FromStr for JitterBufferKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "asterisk" => Ok(JitterBufferKind::Asterisk),
+            "adaptive" => Ok(JitterBufferKind::Adaptive),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The adaptive jitter buffer from Asterisk (`main/jitterbuf.c`).
+pub struct AsteriskJitterBuffer {
+    buffer: JitterBuffer<Vec<i16>>,
+    dropped: u64,
+}
+
+impl AsteriskJitterBuffer {
+    pub fn factory() -> JitterBufferFactory {
+        Box::new(|now| {
+            let mut config = jb_conf {
+                max_jitterbuf: 100,
+                resync_threshold: 1000,
+                max_contig_interp: 0,
+                target_extra: 40,
+            };
+
+            Box::new(AsteriskJitterBuffer {
+                buffer: JitterBuffer::new(&mut config, now),
+                dropped: 0,
+            })
+        })
+    }
+}
+
+impl jitter::JitterBuffer for AsteriskJitterBuffer {
+    fn put(&mut self, frame: BufferedFrame, now: Instant) {
+        // Frames that should be dropped are freed with the error.
+        let res = self.buffer.put(
+            Box::new(frame.samples),
+            JitterFrameType::Voice,
+            frame.duration,
+            frame.timestamp,
+            now,
+        );
+        if let Err(JitterBufferErr::Drop { .. }) = res {
+            self.dropped += 1;
+        }
+    }
+
+    fn get(&mut self, expected_duration: Duration, now: Instant) -> Option<Playout> {
+        loop {
+            break match self.buffer.get(expected_duration, now) {
+                Ok(frame) => Some(Playout::Frame(*frame.data)),
+                Err(JitterBufferErr::Drop { frame }) => {
+                    drop(frame);
+                    self.dropped += 1;
+                    continue;
+                }
+                Err(JitterBufferErr::Interpolate) => Some(Playout::Conceal {
+                    duration: expected_duration,
+                }),
+                Err(
+                    JitterBufferErr::Empty | JitterBufferErr::Scheduled | JitterBufferErr::NoFrame,
+                ) => None,
+            };
+        }
+    }
+
+    fn next_frame(&self) -> Option<Instant> {
+        self.buffer.next_frame()
+    }
+
+    fn stats(&self) -> JitterStats {
+        JitterStats {
+            late: self.dropped,
+            ..Default::default()
+        }
+    }
+}
@@ -5,25 +5,38 @@ use std::{
 };
 
 use asterisk::{
+    application,
     astobj2::{Ao2, AsteriskWrapper},
+    cli::CliEntry,
     config::AsteriskConfig,
     formats::{Format, FormatCapabilities},
     logger::AsteriskLogger,
+    manager::ManagerAction,
 };
 use asterisk_sys::bindings::{
-    ast_channel_register, ast_channel_unregister, ast_format_cap, ast_module_info,
+    __ast_custom_function_register, ast_channel_register, ast_channel_unregister,
+    ast_custom_function_unregister, ast_format_cap, ast_module_info,
     ast_module_load_result_AST_MODULE_LOAD_DECLINE, ast_module_load_result_AST_MODULE_LOAD_SUCCESS,
     ast_module_register, ast_module_support_level_AST_MODULE_SUPPORT_UNKNOWN,
-    ast_module_unregister,
+    ast_module_unregister, EVENT_FLAG_CALL,
 };
 use channel_tech::DISCORD_TECH;
+use cli::CLI_COMMANDS;
+use config::ModuleOptions;
 use ctor::{ctor, dtor};
+use functions::DISCORD_USER_VOLUME;
 use log::{info, warn};
 use queue_thread::QueueThread;
 use thread::DiscordThread;
 
+mod applications;
 mod call;
 mod channel_tech;
+mod cli;
+mod config;
+mod functions;
+mod jitter;
+mod manager;
 mod queue_thread;
 mod rtp_receiver;
 mod thread;
@@ -31,35 +44,6 @@ mod thread;
 static WORKER: OnceLock<RwLock<Option<DiscordThread>>> = OnceLock::new();
 static QUEUE_THREAD: OnceLock<QueueThread> = OnceLock::new();
 
-struct ModuleOptions {
-    token: String,
-}
-
-impl ModuleOptions {
-    fn from_config(config: &AsteriskConfig) -> Option<Self> {
-        let category = config.category(c"general")?;
-        let mut token: Option<String> = None;
-
-        for variable in &category {
-            let Ok(name) = variable.name().to_str() else {
-                continue;
-            };
-            let Ok(value) = variable.value().to_str() else {
-                warn!("Invalid config field {name}: Not valid utf8");
-                return None;
-            };
-
-            if name == "token" {
-                token = Some(value.to_string());
-            } else {
-                info!("Unknown variable {name} in configuration file");
-            }
-        }
-
-        Some(ModuleOptions { token: token? })
-    }
-}
-
 pub fn with_worker<F, R>(body: F) -> Option<R>
 where
     F: FnOnce(&DiscordThread) -> R,
@@ -113,7 +97,12 @@ unsafe extern "C" fn load_module() -> c_int {
     };
 
     // Try to spawn the worker
-    let discord = match DiscordThread::start(options.token) {
+    let discord = match DiscordThread::start(
+        options.token,
+        options.call,
+        options.access,
+        options.presence,
+    ) {
         Ok(discord) => discord,
         Err(e) => {
             warn!("Could not start discord: {e}");
@@ -125,6 +114,36 @@ unsafe extern "C" fn load_module() -> c_int {
     // Register channel technology
     ast_channel_register(ptr::addr_of!(DISCORD_TECH));
 
+    // Register dialplan functions and applications, CLI commands and manager actions
+    let module = INFO.self_;
+    __ast_custom_function_register(ptr::addr_of_mut!(DISCORD_USER_VOLUME), module);
+    if application::register(
+        c"DiscordMove",
+        applications::discord_move,
+        c"Move a Discord call to another voice channel",
+        c"DiscordMove(<server>/<channel>): Moves the Discord call on this channel, or on the \
+channel bridged with it, to another voice channel without hanging up. Sets DISCORD_MOVE_STATUS \
+to SUCCESS or FAILURE.",
+        module,
+    )
+    .is_err()
+    {
+        warn!("Could not register dialplan applications");
+    }
+    if CliEntry::register_all(&mut *ptr::addr_of_mut!(CLI_COMMANDS), module).is_err() {
+        warn!("Could not register CLI commands");
+    }
+    if ManagerAction::register(
+        c"DiscordSetVolume",
+        EVENT_FLAG_CALL,
+        manager::set_volume,
+        module,
+    )
+    .is_err()
+    {
+        warn!("Could not register manager actions");
+    }
+
     ast_module_load_result_AST_MODULE_LOAD_SUCCESS
 }
 
@@ -138,6 +157,10 @@ unsafe extern "C" fn unload_module() -> c_int {
         write.take();
     }
 
+    ManagerAction::unregister(c"DiscordSetVolume");
+    application::unregister(c"DiscordMove");
+    CliEntry::unregister_all(&mut *ptr::addr_of_mut!(CLI_COMMANDS));
+    ast_custom_function_unregister(ptr::addr_of_mut!(DISCORD_USER_VOLUME));
     ast_channel_unregister(ptr::addr_of!(DISCORD_TECH));
 
     let old_capabilities = std::mem::replace(&mut DISCORD_TECH.capabilities, null_mut());
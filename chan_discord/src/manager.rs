@@ -0,0 +1,33 @@
+use std::ffi::c_int;
+
+use asterisk::manager::ManagerAction;
+use asterisk_sys::bindings::{mansession, message};
+use chan_discord_common::audio::mixer::Volume;
+
+use crate::{channel_tech::with_call_by_name, functions::parse_user_id};
+
+/// `Action: DiscordSetVolume` with `Channel`, `User` and `Volume` headers.
+pub unsafe extern "C" fn set_volume(s: *mut mansession, m: *const message) -> c_int {
+    let action = ManagerAction::from_raw(s, m);
+
+    let Some(channel) = action.header(c"Channel") else {
+        action.error("Channel not specified");
+        return 0;
+    };
+    let Some(user) = action.header(c"User").and_then(parse_user_id) else {
+        action.error("User is not a valid Discord user id");
+        return 0;
+    };
+    let Some(Ok(volume)) = action.header(c"Volume").map(str::parse::<Volume>) else {
+        action.error("Volume is not a valid volume");
+        return 0;
+    };
+
+    match with_call_by_name(channel, |call| call.set_user_volume(user, volume)) {
+        Some(Ok(())) => action.ack("Volume changed"),
+        Some(Err(e)) => action.error(&format!("Could not change volume: {e}")),
+        None => action.error("Channel is not connected to a Discord call"),
+    }
+
+    0
+}
@@ -1,4 +1,7 @@
-use std::sync::mpsc;
+use std::{
+    ffi::{c_int, CStr},
+    sync::mpsc,
+};
 
 use anyhow::anyhow;
 use asterisk::{astobj2::Ao2, channel::Channel};
@@ -30,7 +33,10 @@ pub struct QueueThread {
 }
 
 pub enum ChannelWriteKind {
-    Hangup,
+    /// Hangs up the channel, with an `AST_CAUSE_*` cause if one is given.
+    Hangup {
+        cause: Option<c_int>,
+    },
     Control {
         frame_type: ast_control_frame_type,
     },
@@ -38,6 +44,8 @@ pub enum ChannelWriteKind {
         backing_memory: Vec<i16>,
         frame: ast_frame,
     },
+    Variables(Vec<(&'static CStr, String)>),
+    ConnectedLineName(String),
 }
 
 impl QueueThread {
@@ -51,9 +59,12 @@ impl QueueThread {
                     let channel = request.channel;
 
                     match request.write {
-                        ChannelWriteKind::Hangup => {
+                        ChannelWriteKind::Hangup { cause: None } => {
                             channel.queue_hangup();
                         }
+                        ChannelWriteKind::Hangup { cause: Some(cause) } => {
+                            channel.queue_hangup_with_cause(cause);
+                        }
                         ChannelWriteKind::Control { frame_type } => {
                             channel.queue_control(frame_type);
                         }
@@ -63,6 +74,14 @@ impl QueueThread {
                         } => {
                             channel.queue_frame(&mut frame);
                         }
+                        ChannelWriteKind::Variables(variables) => {
+                            for (name, value) in variables {
+                                channel.set_variable(name, &value);
+                            }
+                        }
+                        ChannelWriteKind::ConnectedLineName(name) => {
+                            channel.queue_connected_line_name(&name);
+                        }
                     }
                 }
 
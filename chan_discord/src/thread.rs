@@ -2,7 +2,13 @@ use std::thread::JoinHandle;
 
 use asterisk::{astobj2::Ao2, channel::Channel};
 use chan_discord_common::{
-    discord::Discord,
+    access::AccessTarget,
+    dial::Destination,
+    discord::{
+        cache::DiscordCache,
+        presence::{Presence, PresenceTemplates},
+        Discord,
+    },
     error::{ChanRes, DiscordError},
     utils::{request_channel, RequestReceiver, RequestSender},
 };
@@ -12,7 +18,10 @@ use twilight_model::id::{
     Id,
 };
 
-use crate::call::{CallHandle, CallWorker};
+use crate::{
+    call::{CallHandle, CallWorker},
+    config::{AccessOptions, CallOptions},
+};
 
 /// Thread using an asynchronous Tokio runtime to manage Discord gateway web sockets as well as the
 /// RTP sockets.
@@ -22,27 +31,36 @@ use crate::call::{CallHandle, CallWorker};
 pub struct DiscordThread {
     handle: Option<JoinHandle<()>>,
     send: RequestSender<ThreadRequest, ChanRes<ThreadResponse>>,
+    cache: DiscordCache,
+    access: AccessOptions,
 }
 
 enum ThreadRequest {
     Setup {
         token: String,
+        options: CallOptions,
+        presence: PresenceTemplates,
     },
     PrepareCall {
         asterisk_channel: Ao2<Channel>,
-        server: Id<GuildMarker>,
-        channel: Id<ChannelMarker>,
+        destination: Destination,
     },
     Stop,
 }
 
 enum ThreadResponse {
     Empty,
+    Started { cache: DiscordCache },
     CallPrepared { call: CallHandle },
 }
 
 impl DiscordThread {
-    pub fn start(token: String) -> ChanRes<Self> {
+    pub fn start(
+        token: String,
+        options: CallOptions,
+        access: AccessOptions,
+        presence: PresenceTemplates,
+    ) -> ChanRes<Self> {
         let (send, mut recv) = request_channel::<ThreadRequest, ChanRes<ThreadResponse>>();
 
         let handle = std::thread::Builder::new()
@@ -55,41 +73,84 @@ impl DiscordThread {
 
                 runtime.block_on(async move {
                     let (request, response) = recv.request().await.unwrap();
-                    let ThreadRequest::Setup { token } = request else {
+                    let ThreadRequest::Setup {
+                        token,
+                        options,
+                        presence,
+                    } = request
+                    else {
                         return;
                     };
 
-                    let mut worker = match DiscordThreadWorker::setup(token, recv).await {
+                    let setup = DiscordThreadWorker::setup(token, options, presence, recv).await;
+                    let mut worker = match setup {
                         Ok(worker) => worker,
                         Err(e) => {
                             let _ = response.send(Err(e));
                             return;
                         }
                     };
-                    let _ = response.send(Ok(ThreadResponse::Empty));
+                    let cache = worker.discord.cache();
+                    let _ = response.send(Ok(ThreadResponse::Started { cache }));
                     worker.run().await;
                 });
             })
             .map_err(|e| DiscordError::InternalError { source: e.into() })?;
 
-        let thread = Self {
+        let started = send
+            .request_blocking(ThreadRequest::Setup {
+                token,
+                options,
+                presence,
+            })
+            .map_err(|e| DiscordError::InternalError { source: e.into() })?;
+        let cache = match started {
+            Ok(ThreadResponse::Started { cache }) => cache,
+            Ok(_) => panic!("Expected started response"),
+            Err(e) => {
+                let _ = handle.join();
+                return Err(e);
+            }
+        };
+
+        Ok(Self {
             handle: Some(handle),
             send,
+            cache,
+            access,
+        })
+    }
+
+    /// Checks the allow and deny lists of discord.conf, and those of [profile], for a call to
+    /// [channel] in [guild].
+    pub fn check_access(
+        &self,
+        guild: Id<GuildMarker>,
+        channel: Id<ChannelMarker>,
+        profile: Option<&str>,
+    ) -> ChanRes<()> {
+        let guild_name = self.cache.guild_name(guild);
+        let channel_name = self.cache.channel_name(channel);
+        let target = AccessTarget {
+            guild,
+            channel,
+            guild_name: guild_name.as_deref(),
+            channel_name: channel_name.as_deref(),
         };
-        thread.request(ThreadRequest::Setup { token })?;
-        Ok(thread)
+
+        self.access
+            .check(&target, profile)
+            .map_err(|reason| DiscordError::NotAllowed { reason })
     }
 
     pub fn prepare_call(
         &self,
         asterisk: Ao2<Channel>,
-        server: Id<GuildMarker>,
-        channel: Id<ChannelMarker>,
+        destination: Destination,
     ) -> ChanRes<CallHandle> {
         let response = self.request(ThreadRequest::PrepareCall {
             asterisk_channel: asterisk,
-            server,
-            channel,
+            destination,
         })?;
 
         match response {
@@ -117,15 +178,25 @@ impl Drop for DiscordThread {
 struct DiscordThreadWorker {
     recv: RequestReceiver<ThreadRequest, ChanRes<ThreadResponse>>,
     discord: Discord,
+    options: CallOptions,
+    presence: Presence,
 }
 
 impl DiscordThreadWorker {
     async fn setup(
         token: String,
+        options: CallOptions,
+        presence: PresenceTemplates,
         recv: RequestReceiver<ThreadRequest, ChanRes<ThreadResponse>>,
     ) -> ChanRes<Self> {
-        let discord = Discord::start(token).await?;
-        Ok(Self { discord, recv })
+        let discord = Discord::start(token, presence).await?;
+        let presence = discord.presence();
+        Ok(Self {
+            discord,
+            recv,
+            options,
+            presence,
+        })
     }
 
     async fn run(&mut self) {
@@ -144,21 +215,31 @@ impl DiscordThreadWorker {
                 }
                 ThreadRequest::PrepareCall {
                     asterisk_channel,
-                    server,
-                    channel,
+                    destination,
                 } => {
-                    let Some(events) = self.discord.exclusive_server_events(server).await else {
+                    // Discord ignores joins it doesn't allow, the call would never connect.
+                    let speaks = !destination.options.listen_only;
+                    if let Err(e) = self.discord.cache().check_join(destination.channel, speaks) {
+                        let _ = response.send(Err(e));
+                        continue;
+                    }
+
+                    let events = self
+                        .discord
+                        .exclusive_server_events(destination.guild)
+                        .await;
+                    let Some(events) = events else {
                         let _ = response.send(Err(DiscordError::AlreadyInChannelOnServer));
                         continue;
                     };
 
                     let (worker, handle) = match CallWorker::new(
                         asterisk_channel,
-                        server,
-                        channel,
-                        self.discord.bot_user(),
-                        self.discord.message_sender(),
+                        &destination,
+                        &self.discord,
                         events,
+                        self.options.clone(),
+                        self.presence.clone(),
                     ) {
                         Ok(res) => res,
                         Err(e) => {
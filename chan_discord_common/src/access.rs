@@ -0,0 +1,179 @@
+use twilight_model::id::{
+    marker::{ChannelMarker, GuildMarker},
+    Id,
+};
+
+/// Which guilds and voice channels calls may go to, from the `allow` and `deny` entries of a
+/// section in discord.conf.
+///
+/// Denied entries always win. Without any allowed entries, everything else is allowed. Names we
+/// don't know can't be allowed, but are assumed to be denied.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccessList {
+    allow: Vec<AccessEntry>,
+    deny: Vec<AccessEntry>,
+}
+
+/// A whole guild, `<guild>`, or a voice channel in it, `<guild>/<channel>`. Both are given by id
+/// or by name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessEntry {
+    guild: NameOrId,
+    channel: Option<NameOrId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum NameOrId {
+    Id(u64),
+    Name(String),
+}
+
+/// The voice channel a call goes to, with the names we know.
+pub struct AccessTarget<'a> {
+    pub guild: Id<GuildMarker>,
+    pub channel: Id<ChannelMarker>,
+    pub guild_name: Option<&'a str>,
+    pub channel_name: Option<&'a str>,
+}
+
+impl AccessList {
+    /// Adds the entries of an `allow` line, separated by commas.
+    pub fn allow(&mut self, entries: &str) -> Option<()> {
+        self.allow.extend(AccessEntry::parse_list(entries)?);
+        Some(())
+    }
+
+    /// Adds the entries of a `deny` line, separated by commas.
+    pub fn deny(&mut self, entries: &str) -> Option<()> {
+        self.deny.extend(AccessEntry::parse_list(entries)?);
+        Some(())
+    }
+
+    pub fn permits(&self, target: &AccessTarget) -> bool {
+        if self.deny.iter().any(|entry| entry.matches(target, true)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|entry| entry.matches(target, false))
+    }
+}
+
+impl AccessEntry {
+    fn parse_list(str: &str) -> Option<Vec<Self>> {
+        str.split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(Self::parse)
+            .collect()
+    }
+
+    pub fn parse(str: &str) -> Option<Self> {
+        let (guild, channel) = match str.split_once('/') {
+            Some((guild, channel)) => (guild, Some(NameOrId::parse(channel)?)),
+            None => (str, None),
+        };
+
+        Some(Self {
+            guild: NameOrId::parse(guild)?,
+            channel,
+        })
+    }
+
+    /// Whether [target] is covered by this entry, with [unknown] as the result of comparing a name
+    /// we don't know.
+    fn matches(&self, target: &AccessTarget, unknown: bool) -> bool {
+        self.guild
+            .matches(target.guild.get(), target.guild_name, unknown)
+            && self.channel.as_ref().is_none_or(|channel| {
+                channel.matches(target.channel.get(), target.channel_name, unknown)
+            })
+    }
+}
+
+impl NameOrId {
+    fn parse(str: &str) -> Option<Self> {
+        let str = str.trim();
+        if str.is_empty() || str.contains('/') {
+            return None;
+        }
+
+        Some(match str.parse::<u64>() {
+            Ok(id) => Self::Id(id),
+            Err(_) => Self::Name(str.to_string()),
+        })
+    }
+
+    /// Names are compared ignoring case.
+    fn matches(&self, id: u64, name: Option<&str>, unknown: bool) -> bool {
+        match (self, name) {
+            (NameOrId::Id(expected), _) => *expected == id,
+            (NameOrId::Name(expected), Some(name)) => name.eq_ignore_ascii_case(expected),
+            (NameOrId::Name(_), None) => unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use twilight_model::id::Id;
+
+    use super::{AccessEntry, AccessList, AccessTarget};
+
+    fn target<'a>(guild_name: Option<&'a str>, channel_name: Option<&'a str>) -> AccessTarget<'a> {
+        AccessTarget {
+            guild: Id::new(1234),
+            channel: Id::new(5678),
+            guild_name,
+            channel_name,
+        }
+    }
+
+    #[test]
+    fn parses_entries() {
+        assert!(AccessEntry::parse("1234").is_some());
+        assert!(AccessEntry::parse("Office / Lobby").is_some());
+        assert_eq!(AccessEntry::parse(""), None);
+        assert_eq!(AccessEntry::parse("1234/"), None);
+        assert_eq!(AccessEntry::parse("1234/5678/9"), None);
+
+        let mut list = AccessList::default();
+        assert_eq!(list.allow("1234, Office/Lobby,"), Some(()));
+        assert_eq!(list.deny("Office//"), None);
+    }
+
+    #[test]
+    fn allows_everything_by_default() {
+        assert!(AccessList::default().permits(&target(None, None)));
+    }
+
+    #[test]
+    fn checks_allowed_guilds_and_channels() {
+        let mut list = AccessList::default();
+        list.allow("Office/Lobby, 42").unwrap();
+
+        assert!(list.permits(&target(Some("office"), Some("LOBBY"))));
+        assert!(!list.permits(&target(Some("Office"), Some("Kitchen"))));
+        // We can't tell whether unknown names match.
+        assert!(!list.permits(&target(None, Some("Lobby"))));
+
+        let mut list = AccessList::default();
+        list.deny("Office").unwrap();
+        assert!(!list.permits(&target(None, Some("Lobby"))));
+
+        let mut list = AccessList::default();
+        list.allow("1234").unwrap();
+        assert!(list.permits(&target(None, None)));
+    }
+
+    #[test]
+    fn denied_entries_win() {
+        let mut list = AccessList::default();
+        list.allow("1234").unwrap();
+        list.deny("1234/5678").unwrap();
+        assert!(!list.permits(&target(None, None)));
+
+        let mut list = AccessList::default();
+        list.deny("Office/Lobby").unwrap();
+        assert!(list.permits(&target(Some("Office"), Some("Kitchen"))));
+        assert!(!list.permits(&target(Some("Office"), Some("Lobby"))));
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use super::jitter::{BufferedFrame, JitterBuffer, JitterStats, Playout};
+
+/// Limits for the delay of an [AdaptiveJitterBuffer].
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptiveConfig {
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+    /// How many of the most recent frames are considered when picking the delay.
+    pub history: usize,
+}
+
+impl Default for AdaptiveConfig {
+    fn default() -> Self {
+        Self {
+            min_delay: Duration::from_millis(40),
+            max_delay: Duration::from_millis(200),
+            // 5 seconds of 20ms frames
+            history: 250,
+        }
+    }
+}
+
+/// A jitter buffer that picks its delay based on how late recent frames arrived.
+///
+/// Frames are ordered by their sequence number and played back to back. When the next frame is
+/// missing but later ones have arrived, it is concealed. The delay grows as soon as a frame
+/// arrives too late to be played, but only shrinks when a new talkspurt starts, so that shrinking
+/// never cuts into speech.
+pub struct AdaptiveJitterBuffer {
+    config: AdaptiveConfig,
+    frames: BTreeMap<u64, BufferedFrame>,
+    highest_sequence: Option<u64>,
+    played_until: Option<u64>,
+    // Sequence number and due time of the next frame, None until a talkspurt starts.
+    next: Option<(u64, Instant)>,
+    // Arrival time and timestamp of the frame that arrived earliest compared to its timestamp.
+    // The lateness of all other frames is measured against it.
+    reference: Option<(Instant, i64)>,
+    lateness: VecDeque<Duration>,
+    last_lateness: Option<Duration>,
+    delay: Duration,
+    // Delay that was added after a late frame and still needs to be filled with concealed audio.
+    pending_growth: Duration,
+    frame_duration: Duration,
+    jitter_ms: f64,
+    stats: JitterStats,
+}
+
+impl AdaptiveJitterBuffer {
+    pub fn new(config: AdaptiveConfig) -> Self {
+        Self {
+            config,
+            frames: BTreeMap::new(),
+            highest_sequence: None,
+            played_until: None,
+            next: None,
+            reference: None,
+            lateness: VecDeque::with_capacity(config.history + 1),
+            last_lateness: None,
+            delay: config.min_delay,
+            pending_growth: Duration::ZERO,
+            frame_duration: Duration::from_millis(20),
+            jitter_ms: 0.0,
+            stats: JitterStats::default(),
+        }
+    }
+
+    /// Extends a 16 bit sequence number so that it keeps increasing when it wraps around.
+    fn extend_sequence(&mut self, sequence: u16) -> u64 {
+        let extended = match self.highest_sequence {
+            // Start in the middle of the range, so that reordered frames from before the first one
+            // don't underflow.
+            None => (1 << 32) + sequence as u64,
+            Some(highest) => {
+                let delta = sequence.wrapping_sub(highest as u16) as i16;
+                highest.wrapping_add_signed(delta as i64)
+            }
+        };
+
+        self.highest_sequence = Some(self.highest_sequence.unwrap_or(0).max(extended));
+        extended
+    }
+
+    /// Records how late a frame with [timestamp] arrived and returns the time at which it would
+    /// have arrived without any jitter.
+    fn measure(&mut self, timestamp: i64, now: Instant) -> Instant {
+        let expected = match self.reference {
+            Some((arrival, reference_timestamp)) => {
+                offset(arrival, timestamp - reference_timestamp)
+            }
+            None => now,
+        };
+
+        let mut lateness = now.saturating_duration_since(expected);
+        if lateness > self.config.max_delay {
+            // Either the timestamps jumped, or the network path changed. Either way, the old
+            // measurements are useless now.
+            self.lateness.clear();
+            self.last_lateness = None;
+            self.reference = Some((now, timestamp));
+            lateness = Duration::ZERO;
+        } else if self.reference.is_none() || now < expected {
+            // All previous frames were later than we thought.
+            let shift = expected.saturating_duration_since(now);
+            for previous in self.lateness.iter_mut().chain(&mut self.last_lateness) {
+                *previous += shift;
+            }
+            self.reference = Some((now, timestamp));
+        }
+
+        if let Some(last) = self.last_lateness {
+            let difference = (lateness.as_secs_f64() - last.as_secs_f64()).abs() * 1000.0;
+            self.jitter_ms += (difference - self.jitter_ms) / 16.0;
+        }
+        self.last_lateness = Some(lateness);
+
+        self.lateness.push_back(lateness);
+        if self.lateness.len() > self.config.history {
+            self.lateness.pop_front();
+        }
+
+        now - lateness
+    }
+
+    fn target_delay(&self) -> Duration {
+        let latest = self.lateness.iter().max().copied().unwrap_or_default();
+        latest.clamp(self.config.min_delay, self.config.max_delay)
+    }
+
+    /// Raises the delay to the target in whole frames, after a frame arrived too late.
+    fn grow(&mut self) {
+        let target = self.target_delay();
+        if target <= self.delay {
+            return;
+        }
+
+        let frames = (target - self.delay)
+            .as_micros()
+            .div_ceil(self.frame_duration.as_micros().max(1));
+        let growth = self.frame_duration * frames as u32;
+        self.delay += growth;
+        self.pending_growth += growth;
+    }
+}
+
+impl JitterBuffer for AdaptiveJitterBuffer {
+    fn put(&mut self, frame: BufferedFrame, now: Instant) {
+        self.stats.received += 1;
+        self.frame_duration = frame.duration;
+
+        let sequence = self.extend_sequence(frame.sequence_number);
+        let expected = self.measure(frame.timestamp, now);
+
+        if self.played_until.is_some_and(|played| sequence < played) {
+            self.stats.late += 1;
+            self.grow();
+            return;
+        }
+        if self.frames.contains_key(&sequence) {
+            self.stats.duplicates += 1;
+            return;
+        }
+
+        // The buffer ran empty and the next frame was due already, so a new talkspurt starts.
+        if self.frames.is_empty() && self.next.is_some_and(|(_, due)| due <= now) {
+            self.next = None;
+        }
+
+        match self.next {
+            None => {
+                self.delay = self.target_delay();
+                self.pending_growth = Duration::ZERO;
+                self.next = Some((sequence, expected + self.delay));
+            }
+            Some((next, due)) if sequence < next => {
+                // Reordered frames at the start of a talkspurt
+                let earlier = frame.duration * (next - sequence) as u32;
+                self.next = Some((sequence, due.checked_sub(earlier).unwrap_or(due)));
+            }
+            Some(_) => {}
+        }
+
+        self.frames.insert(sequence, frame);
+    }
+
+    fn get(&mut self, expected_duration: Duration, now: Instant) -> Option<Playout> {
+        let (mut sequence, due) = self.next?;
+        if due > now || self.frames.is_empty() {
+            return None;
+        }
+
+        if self.pending_growth >= expected_duration {
+            self.pending_growth -= expected_duration;
+            self.next = Some((sequence, due + expected_duration));
+            self.stats.concealed += 1;
+            return Some(Playout::Conceal {
+                duration: expected_duration,
+            });
+        }
+
+        // If frames this far ahead have arrived, the missing ones would arrive too late anyway.
+        let (&first, _) = self.frames.first_key_value()?;
+        let max_gap = self.config.max_delay.as_micros() / expected_duration.as_micros().max(1);
+        let gap = first.saturating_sub(sequence);
+        if gap as u128 > max_gap {
+            self.stats.lost += gap;
+            sequence = first;
+        }
+
+        self.played_until = Some(sequence + 1);
+        match self.frames.remove(&sequence) {
+            Some(frame) => {
+                self.next = Some((sequence + 1, due + frame.duration));
+                Some(Playout::Frame(frame.samples))
+            }
+            None => {
+                self.next = Some((sequence + 1, due + expected_duration));
+                self.stats.lost += 1;
+                self.stats.concealed += 1;
+                Some(Playout::Conceal {
+                    duration: expected_duration,
+                })
+            }
+        }
+    }
+
+    fn next_frame(&self) -> Option<Instant> {
+        if self.frames.is_empty() {
+            return None;
+        }
+
+        self.next.map(|(_, due)| due)
+    }
+
+    fn stats(&self) -> JitterStats {
+        JitterStats {
+            jitter: Duration::from_secs_f64(self.jitter_ms / 1000.0),
+            delay: self.delay,
+            ..self.stats
+        }
+    }
+}
+
+fn offset(time: Instant, milliseconds: i64) -> Instant {
+    let duration = Duration::from_millis(milliseconds.unsigned_abs());
+    if milliseconds >= 0 {
+        time + duration
+    } else {
+        time.checked_sub(duration).unwrap_or(time)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{AdaptiveConfig, AdaptiveJitterBuffer};
+    use crate::audio::jitter::{BufferedFrame, JitterBuffer, Playout};
+
+    const FRAME: Duration = Duration::from_millis(20);
+    const CONCEAL: Option<Playout> = Some(Playout::Conceal { duration: FRAME });
+
+    fn frame(sequence_number: u16, timestamp: i64) -> BufferedFrame {
+        BufferedFrame {
+            samples: vec![sequence_number as i16],
+            duration: FRAME,
+            timestamp,
+            sequence_number,
+        }
+    }
+
+    fn played(sequence_number: u16) -> Option<Playout> {
+        Some(Playout::Frame(vec![sequence_number as i16]))
+    }
+
+    #[test]
+    fn plays_in_sequence_order() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut buffer = AdaptiveJitterBuffer::new(AdaptiveConfig::default());
+
+        buffer.put(frame(0, 0), at(0));
+        buffer.put(frame(2, 40), at(41));
+        buffer.put(frame(1, 20), at(45));
+
+        assert_eq!(buffer.next_frame(), Some(at(40)));
+        assert_eq!(buffer.get(FRAME, at(39)), None);
+        assert_eq!(buffer.get(FRAME, at(40)), played(0));
+        assert_eq!(buffer.get(FRAME, at(60)), played(1));
+        assert_eq!(buffer.get(FRAME, at(80)), played(2));
+        assert_eq!(buffer.next_frame(), None);
+    }
+
+    #[test]
+    fn handles_sequence_wraparound() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut buffer = AdaptiveJitterBuffer::new(AdaptiveConfig::default());
+
+        buffer.put(frame(0, 20), at(20));
+        buffer.put(frame(u16::MAX, 0), at(21));
+
+        assert_eq!(buffer.get(FRAME, at(40)), played(u16::MAX));
+        assert_eq!(buffer.get(FRAME, at(60)), played(0));
+    }
+
+    #[test]
+    fn conceals_lost_frames() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut buffer = AdaptiveJitterBuffer::new(AdaptiveConfig::default());
+
+        buffer.put(frame(0, 0), at(0));
+        buffer.put(frame(1, 20), at(20));
+        buffer.put(frame(3, 60), at(60));
+
+        assert_eq!(buffer.get(FRAME, at(40)), played(0));
+        assert_eq!(buffer.get(FRAME, at(60)), played(1));
+        assert_eq!(buffer.get(FRAME, at(80)), CONCEAL);
+        assert_eq!(buffer.get(FRAME, at(100)), played(3));
+
+        let stats = buffer.stats();
+        assert_eq!((stats.received, stats.lost, stats.concealed), (3, 1, 1));
+    }
+
+    #[test]
+    fn grows_delay_after_late_frames() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut buffer = AdaptiveJitterBuffer::new(AdaptiveConfig::default());
+
+        buffer.put(frame(0, 0), at(0));
+        buffer.put(frame(1, 20), at(20));
+        assert_eq!(buffer.get(FRAME, at(40)), played(0));
+        buffer.put(frame(2, 40), at(40));
+        assert_eq!(buffer.get(FRAME, at(60)), played(1));
+        buffer.put(frame(4, 80), at(80));
+        assert_eq!(buffer.get(FRAME, at(80)), played(2));
+        assert_eq!(buffer.get(FRAME, at(100)), CONCEAL);
+        buffer.put(frame(5, 100), at(100));
+        assert_eq!(buffer.get(FRAME, at(120)), played(4));
+
+        // 65ms late, so the delay grows by two frames.
+        buffer.put(frame(3, 60), at(125));
+        assert_eq!(buffer.stats().late, 1);
+        assert_eq!(buffer.stats().delay, Duration::from_millis(80));
+
+        assert_eq!(buffer.get(FRAME, at(140)), CONCEAL);
+        assert_eq!(buffer.get(FRAME, at(160)), CONCEAL);
+        assert_eq!(buffer.get(FRAME, at(180)), played(5));
+    }
+
+    #[test]
+    fn shrinks_delay_between_talkspurts() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut buffer = AdaptiveJitterBuffer::new(AdaptiveConfig {
+            history: 2,
+            ..Default::default()
+        });
+
+        buffer.put(frame(0, 0), at(0));
+        assert_eq!(buffer.get(FRAME, at(40)), played(0));
+
+        // The next talkspurt starts with a delay fitting the 90ms late frame.
+        buffer.put(frame(1, 40), at(130));
+        assert_eq!(buffer.next_frame(), Some(at(130)));
+        assert_eq!(buffer.get(FRAME, at(130)), played(1));
+
+        buffer.put(frame(2, 200), at(205));
+        buffer.put(frame(3, 220), at(220));
+        assert_eq!(buffer.next_frame(), Some(at(290)));
+        assert_eq!(buffer.get(FRAME, at(290)), played(2));
+        assert_eq!(buffer.get(FRAME, at(310)), played(3));
+
+        // Once that frame is out of the history, the delay goes back to the minimum.
+        buffer.put(frame(4, 400), at(400));
+        assert_eq!(buffer.stats().delay, Duration::from_millis(40));
+        assert_eq!(buffer.next_frame(), Some(at(440)));
+    }
+}
@@ -0,0 +1,365 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+
+use anyhow::Context;
+use log::{debug, warn};
+use ogg::{PacketWriteEndInfo, PacketWriter};
+use twilight_model::id::{marker::UserMarker, Id};
+
+use super::recording::Timeline;
+use crate::constants::{NUM_SAMPLES, OPUS_SILENCE_FRAME, SAMPLE_RATE};
+
+/// Archives the Opus packets of a call into Ogg/Opus files, without decoding them.
+///
+/// Like [super::recording::CallRecording], every received SSRC and the audio we send end up in
+/// separate files that all start when the archive was created. Gaps in the RTP timestamps (lost
+/// packets or pauses in transmission) are filled with silence frames to keep the files aligned.
+pub struct CallArchive {
+    directory: PathBuf,
+    prefix: String,
+    timeline_start: Instant,
+    received: Vec<(u32, Id<UserMarker>, OpusTrack)>,
+}
+
+/// A single Ogg/Opus file, aligned to the start of the call archive.
+pub struct OpusTrack {
+    stream: Option<OggOpusStream<BufWriter<File>>>,
+    timeline: Timeline,
+}
+
+/// Writes Opus packets into an Ogg container, with granule positions following the position of
+/// each packet in the stream.
+pub struct OggOpusStream<W: Write> {
+    writer: PacketWriter<W>,
+    serial: u32,
+    granule_position: u64,
+    packets_in_page: usize,
+}
+
+impl CallArchive {
+    /// Prepares an archive writing files named `<prefix>-...` into [directory].
+    pub fn new(directory: impl Into<PathBuf>, prefix: impl Into<String>) -> anyhow::Result<Self> {
+        let directory = directory.into();
+        std::fs::create_dir_all(&directory)
+            .with_context(|| format!("Could not create archive directory {directory:?}"))?;
+
+        Ok(Self {
+            directory,
+            prefix: prefix.into(),
+            timeline_start: Instant::now(),
+            received: vec![],
+        })
+    }
+
+    /// Creates the track for packets we're sending to Discord, which are always mono.
+    pub fn sent_track(&self) -> OpusTrack {
+        let file = format!("{}-sent.opus", self.prefix);
+        OpusTrack::create(self.directory.join(file), 1, self.timeline_start)
+    }
+
+    /// Archives an Opus [payload] received from [ssrc], creating a new file when the SSRC is new or
+    /// now belongs to another user.
+    pub fn write_received(
+        &mut self,
+        user: Id<UserMarker>,
+        ssrc: u32,
+        timestamp: u32,
+        now: Instant,
+        payload: &[u8],
+    ) {
+        let index = self
+            .received
+            .iter()
+            .position(|(s, u, _)| *s == ssrc && *u == user);
+        let index = match index {
+            Some(index) => index,
+            None => {
+                let file = format!("{}-{user}-{ssrc}.opus", self.prefix);
+                // Discord clients send stereo audio.
+                let track = OpusTrack::create(self.directory.join(file), 2, self.timeline_start);
+                self.received.push((ssrc, user, track));
+                self.received.len() - 1
+            }
+        };
+
+        self.received[index].2.write(timestamp, now, payload);
+    }
+
+    /// Lets the next packet from [ssrc] start a new stream, whose timestamps have nothing to do
+    /// with the previous ones.
+    pub fn restart_received(&mut self, ssrc: u32) {
+        for (_, _, track) in self.received.iter_mut().filter(|(s, _, _)| *s == ssrc) {
+            track.restart();
+        }
+    }
+
+    pub fn finish(self) {
+        for (_, _, track) in self.received {
+            track.finish();
+        }
+    }
+}
+
+impl std::fmt::Debug for CallArchive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CallArchive")
+            .field("directory", &self.directory)
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+impl OpusTrack {
+    fn create(path: PathBuf, channels: u8, timeline_start: Instant) -> Self {
+        let stream = File::create(&path)
+            .and_then(|file| OggOpusStream::new(BufWriter::new(file), channels, rand::random()));
+
+        let stream = match stream {
+            Ok(stream) => {
+                debug!("Archiving Opus packets to {path:?}");
+                Some(stream)
+            }
+            Err(e) => {
+                warn!("Could not create archive file {path:?}: {e}");
+                None
+            }
+        };
+
+        Self {
+            stream,
+            timeline: Timeline::new(timeline_start),
+        }
+    }
+
+    /// Writes an Opus [payload] with the RTP [timestamp].
+    pub fn write(&mut self, timestamp: u32, now: Instant, payload: &[u8]) {
+        let Some(stream) = &mut self.stream else {
+            return;
+        };
+
+        let position = self.timeline.position(timestamp, now);
+        if let Err(e) = stream.write(position, payload) {
+            warn!("Could not write archive, stopping this track: {e}");
+            self.stream = None;
+        }
+    }
+
+    /// Places the next packet by its arrival time, after the granule position reached so far.
+    pub fn restart(&mut self) {
+        if let Some(stream) = &self.stream {
+            self.timeline.restart(stream.granule_position);
+        }
+    }
+
+    pub fn finish(self) {
+        if let Some(stream) = self.stream {
+            if let Err(e) = stream.finish() {
+                warn!("Could not finish archive: {e}");
+            }
+        }
+    }
+}
+
+impl<W: Write> OggOpusStream<W> {
+    // Ending a page every second keeps the file usable if we never get to finish it.
+    const PACKETS_PER_PAGE: usize = 50;
+
+    /// Starts a stream by writing the identification and comment headers (RFC 7845, section 5).
+    pub fn new(writer: W, channels: u8, serial: u32) -> io::Result<Self> {
+        let mut writer = PacketWriter::new(writer);
+
+        let mut head = Vec::with_capacity(19);
+        head.extend_from_slice(b"OpusHead");
+        head.push(1); // version
+        head.push(channels);
+        head.extend_from_slice(&0u16.to_le_bytes()); // pre-skip
+        head.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+        head.push(0); // channel mapping family
+        writer.write_packet(head.into(), serial, PacketWriteEndInfo::EndPage, 0)?;
+
+        let vendor = concat!("chan_discord ", env!("CARGO_PKG_VERSION"));
+        let mut tags = Vec::new();
+        tags.extend_from_slice(b"OpusTags");
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor.as_bytes());
+        tags.extend_from_slice(&0u32.to_le_bytes()); // no user comments
+        writer.write_packet(tags.into(), serial, PacketWriteEndInfo::EndPage, 0)?;
+
+        Ok(Self {
+            writer,
+            serial,
+            granule_position: 0,
+            packets_in_page: 0,
+        })
+    }
+
+    /// Writes [packet], which starts [position] samples into the stream.
+    ///
+    /// Packets starting before the end of the previous packet (duplicates or packets that arrived
+    /// out of order) are dropped, gaps before the packet are filled with silence.
+    pub fn write(&mut self, position: u64, packet: &[u8]) -> io::Result<()> {
+        let Some(samples) = packet_samples(packet) else {
+            debug!("Not archiving invalid Opus packet");
+            return Ok(());
+        };
+        if position < self.granule_position {
+            return Ok(());
+        }
+
+        while position - self.granule_position >= NUM_SAMPLES as u64 {
+            self.write_packet(&OPUS_SILENCE_FRAME, NUM_SAMPLES as usize, false)?;
+        }
+        self.write_packet(packet, samples, false)
+    }
+
+    /// Ends the stream and flushes the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        // The last packet needs to be marked as such, but we don't know it's the last one until
+        // the call ends. So end the stream with an additional silence frame.
+        self.write_packet(&OPUS_SILENCE_FRAME, NUM_SAMPLES as usize, true)?;
+
+        let mut writer = self.writer.into_inner();
+        writer.flush()?;
+        Ok(writer)
+    }
+
+    fn write_packet(&mut self, packet: &[u8], samples: usize, last: bool) -> io::Result<()> {
+        self.granule_position += samples as u64;
+        self.packets_in_page += 1;
+
+        let end = if last {
+            PacketWriteEndInfo::EndStream
+        } else if self.packets_in_page >= Self::PACKETS_PER_PAGE {
+            self.packets_in_page = 0;
+            PacketWriteEndInfo::EndPage
+        } else {
+            PacketWriteEndInfo::NormalPacket
+        };
+
+        self.writer
+            .write_packet(packet.into(), self.serial, end, self.granule_position)
+    }
+}
+
+/// The amount of samples (at 48kHz) in an Opus packet, according to its TOC byte (RFC 6716,
+/// section 3.1).
+pub fn packet_samples(packet: &[u8]) -> Option<usize> {
+    let toc = *packet.first()?;
+    let config = (toc >> 3) as usize;
+
+    let frame_samples = match config {
+        // SILK-only: 10, 20, 40 or 60ms
+        0..=11 => [480, 960, 1920, 2880][config % 4],
+        // Hybrid: 10 or 20ms
+        12..=15 => [480, 960][config % 2],
+        // CELT-only: 2.5, 5, 10 or 20ms
+        _ => [120, 240, 480, 960][config % 4],
+    };
+    let frames = match toc & 0x3 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => (*packet.get(1)? & 0x3F) as usize,
+    };
+
+    let samples = frame_samples * frames;
+    // Packets can't be longer than 120ms.
+    (samples > 0 && samples <= 5760).then_some(samples)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    use ogg::PacketReader;
+
+    use super::{packet_samples, CallArchive, OggOpusStream};
+    use crate::constants::OPUS_SILENCE_FRAME;
+
+    #[test]
+    fn packet_durations() {
+        assert_eq!(packet_samples(&OPUS_SILENCE_FRAME), Some(960));
+        // SILK 60ms, two frames
+        assert_eq!(packet_samples(&[0x03 << 3 | 1, 0]), Some(5760));
+        // SILK 20ms, arbitrary number of frames
+        assert_eq!(packet_samples(&[0x09 << 3 | 3, 3]), Some(2880));
+        assert_eq!(packet_samples(&[0x09 << 3 | 3]), None);
+        assert_eq!(packet_samples(&[]), None);
+    }
+
+    #[test]
+    fn writes_granule_positions() {
+        let mut stream = OggOpusStream::new(Cursor::new(vec![]), 2, 1234).unwrap();
+        stream.write(0, &[0x78, 1, 2, 3]).unwrap();
+        // Duplicated packet, dropped
+        stream.write(0, &[0x78, 1, 2, 3]).unwrap();
+        // Two packets lost, the gap is filled with silence.
+        stream.write(3 * 960, &[0x78, 4, 5, 6]).unwrap();
+
+        let mut buffer = stream.finish().unwrap();
+        buffer.set_position(0);
+        let mut reader = PacketReader::new(buffer);
+
+        let head = reader.read_packet_expected().unwrap();
+        assert_eq!(&head.data[..8], b"OpusHead");
+        assert_eq!(head.data[9], 2);
+        let tags = reader.read_packet_expected().unwrap();
+        assert_eq!(&tags.data[..8], b"OpusTags");
+
+        let mut packets = vec![];
+        while let Some(packet) = reader.read_packet().unwrap() {
+            packets.push(packet);
+        }
+
+        let data: Vec<&[u8]> = packets.iter().map(|p| &p.data[..]).collect();
+        assert_eq!(
+            data,
+            vec![
+                &[0x78, 1, 2, 3][..],
+                &OPUS_SILENCE_FRAME,
+                &OPUS_SILENCE_FRAME,
+                &[0x78, 4, 5, 6],
+                &OPUS_SILENCE_FRAME,
+            ]
+        );
+
+        let last = packets.last().unwrap();
+        assert!(last.last_in_stream());
+        assert_eq!(last.absgp_page(), 5 * 960);
+    }
+
+    #[test]
+    fn rebases_restarted_streams() {
+        let directory = std::env::temp_dir().join(format!("archive-{}", std::process::id()));
+        let mut archive = CallArchive::new(&directory, "call").unwrap();
+        let start = archive.timeline_start;
+        let user = twilight_model::id::Id::new(1234);
+
+        archive.write_received(user, 42, 5000, start, &[0x78, 1]);
+        // The sender restarted with a timestamp from before, which would otherwise be dropped.
+        archive.restart_received(42);
+        let later = start + Duration::from_millis(20);
+        archive.write_received(user, 42, 100, later, &[0x78, 2]);
+        archive.write_received(user, 42, 100 + 960, later, &[0x78, 3]);
+        archive.finish();
+
+        let file = std::fs::File::open(directory.join("call-1234-42.opus")).unwrap();
+        let mut reader = PacketReader::new(file);
+        let mut packets = vec![];
+        while let Some(packet) = reader.read_packet().unwrap() {
+            packets.push(packet);
+        }
+
+        let data: Vec<&[u8]> = packets[2..].iter().map(|p| &p.data[..]).collect();
+        assert_eq!(
+            data,
+            vec![&[0x78, 1][..], &[0x78, 2], &[0x78, 3], &OPUS_SILENCE_FRAME]
+        );
+        assert_eq!(packets.last().unwrap().absgp_page(), 4 * 960);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
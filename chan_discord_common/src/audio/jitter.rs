@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// Orders the decoded frames of a single participant and decides when each of them is played.
+///
+/// All methods take the current time as a parameter instead of looking at the clock, so that
+/// captured calls can be replayed with their original timing.
+pub trait JitterBuffer: Send {
+    /// Adds a frame that arrived at [now].
+    fn put(&mut self, frame: BufferedFrame, now: Instant);
+
+    /// Returns what to play at [now], if anything is due.
+    fn get(&mut self, expected_duration: Duration, now: Instant) -> Option<Playout>;
+
+    /// When the next frame is due to be played, if any frames are buffered.
+    fn next_frame(&self) -> Option<Instant>;
+
+    /// Counters about the frames that went through this buffer so far.
+    fn stats(&self) -> JitterStats {
+        JitterStats::default()
+    }
+}
+
+pub struct BufferedFrame {
+    pub samples: Vec<i16>,
+    pub duration: Duration,
+    /// In milliseconds, relative to the first frame of the stream.
+    pub timestamp: i64,
+    pub sequence_number: u16,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Playout {
+    Frame(Vec<i16>),
+    /// A frame is missing, either because it was lost or because it didn't arrive in time. The
+    /// caller should make up [duration] of audio, e.g. with a [Concealment].
+    Conceal {
+        duration: Duration,
+    },
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct JitterStats {
+    pub received: u64,
+    /// Frames that arrived after they should have been played.
+    pub late: u64,
+    pub duplicates: u64,
+    /// Frames that were never received, or too late.
+    pub lost: u64,
+    pub concealed: u64,
+    /// Interarrival jitter as in RFC 3550, section 6.4.1.
+    pub jitter: Duration,
+    pub delay: Duration,
+}
+
+/// Creates a jitter buffer for a new stream starting at the given time.
+pub type JitterBufferFactory = Box<dyn Fn(Instant) -> Box<dyn JitterBuffer> + Send>;
+
+/// A jitter buffer that plays every frame a fixed delay after the time it would have arrived
+/// without any jitter, judging by the first frame of the stream.
+///
+/// Frames arriving after a later frame has already been played are dropped.
+pub struct FixedDelayBuffer {
+    delay: Duration,
+    // The time at which the frame with timestamp 0 would have arrived.
+    stream_start: Option<Instant>,
+    frames: BTreeMap<i64, Vec<i16>>,
+    played_until: Option<i64>,
+    late: u64,
+}
+
+impl FixedDelayBuffer {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            stream_start: None,
+            frames: BTreeMap::new(),
+            played_until: None,
+            late: 0,
+        }
+    }
+
+    fn due(&self, timestamp: i64) -> Option<Instant> {
+        let start = self.stream_start? + self.delay;
+        Some(if timestamp >= 0 {
+            start + Duration::from_millis(timestamp as u64)
+        } else {
+            start
+                .checked_sub(Duration::from_millis(timestamp.unsigned_abs()))
+                .unwrap_or(start)
+        })
+    }
+}
+
+impl JitterBuffer for FixedDelayBuffer {
+    fn put(&mut self, frame: BufferedFrame, now: Instant) {
+        let timestamp = frame.timestamp;
+        if self.played_until.is_some_and(|played| timestamp < played) {
+            self.late += 1;
+            return;
+        }
+
+        self.stream_start.get_or_insert_with(|| {
+            now.checked_sub(Duration::from_millis(timestamp.max(0) as u64))
+                .unwrap_or(now)
+        });
+        self.frames.insert(timestamp, frame.samples);
+    }
+
+    fn get(&mut self, _expected_duration: Duration, now: Instant) -> Option<Playout> {
+        let (&timestamp, _) = self.frames.first_key_value()?;
+        if self.due(timestamp)? > now {
+            return None;
+        }
+
+        let frame = self.frames.remove(&timestamp)?;
+        self.played_until = Some(timestamp + 1);
+        Some(Playout::Frame(frame))
+    }
+
+    fn next_frame(&self) -> Option<Instant> {
+        let (&timestamp, _) = self.frames.first_key_value()?;
+        self.due(timestamp)
+    }
+
+    fn stats(&self) -> JitterStats {
+        JitterStats {
+            late: self.late,
+            delay: self.delay,
+            ..Default::default()
+        }
+    }
+}
+
+/// Makes up audio for missing frames by repeating the last frame that was played, halving its
+/// volume with every frame so that longer losses fade into silence.
+#[derive(Default)]
+pub struct Concealment {
+    last_frame: Vec<i16>,
+    concealed: u32,
+}
+
+impl Concealment {
+    const MAX_CONCEALED_FRAMES: u32 = 4;
+
+    /// Remembers a frame that was played, the next concealed frame is based on it.
+    pub fn played(&mut self, frame: &[i16]) {
+        self.last_frame.clear();
+        self.last_frame.extend_from_slice(frame);
+        self.concealed = 0;
+    }
+
+    pub fn conceal(&mut self, samples: usize) -> Vec<i16> {
+        self.concealed += 1;
+        if self.last_frame.is_empty() || self.concealed > Self::MAX_CONCEALED_FRAMES {
+            return vec![0; samples];
+        }
+
+        let shift = self.concealed;
+        self.last_frame
+            .iter()
+            .cycle()
+            .take(samples)
+            .map(|sample| sample >> shift)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{BufferedFrame, Concealment, FixedDelayBuffer, JitterBuffer, Playout};
+
+    const FRAME: Duration = Duration::from_millis(20);
+
+    fn frame(sample: i16, timestamp: i64) -> BufferedFrame {
+        BufferedFrame {
+            samples: vec![sample],
+            duration: FRAME,
+            timestamp,
+            sequence_number: (timestamp / 20) as u16,
+        }
+    }
+
+    #[test]
+    fn reorders_frames() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut buffer = FixedDelayBuffer::new(Duration::from_millis(40));
+
+        buffer.put(frame(1, 0), at(0));
+        buffer.put(frame(3, 40), at(41));
+        buffer.put(frame(2, 20), at(45));
+
+        assert_eq!(buffer.next_frame(), Some(at(40)));
+        assert_eq!(buffer.get(FRAME, at(39)), None);
+        assert_eq!(buffer.get(FRAME, at(40)), Some(Playout::Frame(vec![1])));
+        assert_eq!(buffer.get(FRAME, at(60)), Some(Playout::Frame(vec![2])));
+        assert_eq!(buffer.get(FRAME, at(80)), Some(Playout::Frame(vec![3])));
+        assert_eq!(buffer.next_frame(), None);
+    }
+
+    #[test]
+    fn drops_late_frames() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut buffer = FixedDelayBuffer::new(Duration::from_millis(20));
+
+        buffer.put(frame(1, 0), at(0));
+        buffer.put(frame(3, 40), at(40));
+        assert_eq!(buffer.get(FRAME, at(20)), Some(Playout::Frame(vec![1])));
+        assert_eq!(buffer.get(FRAME, at(60)), Some(Playout::Frame(vec![3])));
+
+        buffer.put(frame(2, 20), at(70));
+        assert_eq!(buffer.next_frame(), None);
+    }
+
+    #[test]
+    fn concealment_fades_out() {
+        let mut concealment = Concealment::default();
+        assert_eq!(concealment.conceal(2), [0, 0]);
+
+        concealment.played(&[800, -800]);
+        assert_eq!(concealment.conceal(3), [400, -400, 400]);
+        assert_eq!(concealment.conceal(2), [200, -200]);
+        concealment.conceal(2);
+        concealment.conceal(2);
+        assert_eq!(concealment.conceal(2), [0, 0]);
+    }
+}
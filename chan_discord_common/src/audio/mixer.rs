@@ -0,0 +1,412 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::hash::Hash;
+use std::str::FromStr;
+
+use crate::constants::NUM_SAMPLES;
+
+/// Mixes decoded audio from multiple Discord participants into a single mono stream.
+///
+/// Every source has its own sample buffer which is kept across calls to [Mixer::mix_block], so
+/// frames of different lengths don't lose samples. The output is always a fixed block of
+/// [BLOCK_SAMPLES] samples. After applying per-source gains, the sum runs through a limiter and a
+/// soft clipper instead of saturating, which avoids the harsh distortion of hard clipping when
+/// multiple people talk at once.
+pub struct Mixer<K> {
+    sources: HashMap<K, MixerSource>,
+    limiter_gain: f32,
+}
+
+/// A linear gain factor applied to a source before mixing it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gain(f32);
+
+/// A volume adjustment for a participant, as requested from the Asterisk side.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Volume {
+    #[default]
+    Unchanged,
+    Decibels(f32),
+    Muted,
+}
+
+struct MixerSource {
+    buffer: VecDeque<i16>,
+    gain: Gain,
+    // Whether samples were added since the last block was mixed. Sources that stopped delivering
+    // audio get their partial block flushed instead of waiting for more samples.
+    fresh: bool,
+    // In dBov, after applying the gain
+    level: f32,
+}
+
+/// 20ms of audio, the block size of the mixer.
+pub const BLOCK_SAMPLES: usize = NUM_SAMPLES as usize;
+
+impl<K: Eq + Hash + Copy> Mixer<K> {
+    // At most 200ms of audio are buffered per source, older samples are dropped.
+    const MAX_BUFFERED_SAMPLES: usize = 10 * BLOCK_SAMPLES;
+
+    // The limiter starts reducing the gain once the mixed signal exceeds this peak level.
+    const LIMITER_THRESHOLD: f32 = 0.9;
+    // How quickly the limiter recovers after loud input, per block. A factor of 0.1 recovers most
+    // of the gain within 200ms.
+    const LIMITER_RELEASE: f32 = 0.1;
+
+    // Samples below this level pass the soft clipper unchanged.
+    const SOFT_CLIP_KNEE: f32 = 0.8;
+
+    // The level meters cover the range of RFC 6464 audio levels.
+    const SILENT_LEVEL: f32 = -127.0;
+    // Like a peak meter, levels fall by 20dB per second after loud input.
+    const LEVEL_RELEASE: f32 = 0.4;
+
+    pub fn new() -> Self {
+        Self {
+            sources: HashMap::new(),
+            limiter_gain: 1.0,
+        }
+    }
+
+    /// Registers a source, doing nothing if it has already been added.
+    pub fn add_source(&mut self, source: K) {
+        self.sources.entry(source).or_insert_with(|| MixerSource {
+            buffer: VecDeque::new(),
+            gain: Gain::UNITY,
+            fresh: false,
+            level: Self::SILENT_LEVEL,
+        });
+    }
+
+    pub fn remove_source(&mut self, source: &K) {
+        self.sources.remove(source);
+    }
+
+    pub fn set_gain(&mut self, source: K, gain: Gain) {
+        if let Some(source) = self.sources.get_mut(&source) {
+            source.gain = gain;
+        }
+    }
+
+    /// Appends decoded samples for a source that has been added with [Mixer::add_source].
+    pub fn push(&mut self, source: K, samples: &[i16]) {
+        let Some(source) = self.sources.get_mut(&source) else {
+            return;
+        };
+
+        source.buffer.extend(samples);
+        source.fresh = true;
+
+        let overflow = source
+            .buffer
+            .len()
+            .saturating_sub(Self::MAX_BUFFERED_SAMPLES);
+        source.buffer.drain(..overflow);
+    }
+
+    /// Feeds the level meter of a source with the level of its latest packet, as measured by the
+    /// sender. This doesn't need the packet to be decoded.
+    pub fn meter_level(&mut self, source: K, level_db: f32) {
+        let Some(source) = self.sources.get_mut(&source) else {
+            return;
+        };
+
+        let gain_db = 20.0 * source.gain.0.log10();
+        let level = (level_db + gain_db).max(Self::SILENT_LEVEL);
+        source.level = source.level.max(level);
+    }
+
+    /// The metered level of a source in dBov.
+    pub fn level(&self, source: &K) -> Option<f32> {
+        self.sources.get(source).map(|source| source.level)
+    }
+
+    /// Whether [Mixer::mix_block] would currently return a block.
+    pub fn has_block(&self) -> bool {
+        self.sources.values().any(|s| s.has_block())
+    }
+
+    /// Whether any source has samples buffered, even if not enough for a block.
+    pub fn has_samples(&self) -> bool {
+        self.sources.values().any(|s| !s.buffer.is_empty())
+    }
+
+    /// Stops waiting for the rest of partial blocks, so the next [Mixer::mix_block] pads them
+    /// with silence.
+    pub fn end_partial_blocks(&mut self) {
+        for source in self.sources.values_mut() {
+            source.fresh = false;
+        }
+    }
+
+    /// Mixes the next [BLOCK_SAMPLES] samples of all sources.
+    ///
+    /// Returns `None` if no source has buffered enough audio for a full block. Sources that only
+    /// have a partial block buffered keep it for the next call, unless they haven't received new
+    /// samples since the last block was mixed (in which case the rest is padded with silence).
+    pub fn mix_block(&mut self) -> Option<Vec<i16>> {
+        if !self.has_block() {
+            return None;
+        }
+
+        let mut mixed = [0f32; BLOCK_SAMPLES];
+        for source in self.sources.values_mut() {
+            let available = source.buffer.len();
+            let take = if available >= BLOCK_SAMPLES {
+                BLOCK_SAMPLES
+            } else if !source.fresh {
+                available
+            } else {
+                0
+            };
+            source.fresh = false;
+            source.level = (source.level - Self::LEVEL_RELEASE).max(Self::SILENT_LEVEL);
+
+            let gain = source.gain.0;
+            for (i, sample) in source.buffer.drain(..take).enumerate() {
+                mixed[i] += gain * (sample as f32 / 32768.0);
+            }
+        }
+
+        Some(self.limit_and_clip(&mut mixed))
+    }
+
+    fn limit_and_clip(&mut self, mixed: &mut [f32; BLOCK_SAMPLES]) -> Vec<i16> {
+        let peak = mixed.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+        let target = if peak > Self::LIMITER_THRESHOLD {
+            Self::LIMITER_THRESHOLD / peak
+        } else {
+            1.0
+        };
+
+        let previous = self.limiter_gain;
+        // Attack instantly, release slowly.
+        let next = if target < previous {
+            target
+        } else {
+            (previous + (1.0 - previous) * Self::LIMITER_RELEASE).min(target)
+        };
+        self.limiter_gain = next;
+
+        mixed
+            .iter()
+            .enumerate()
+            .map(|(i, sample)| {
+                // Interpolate between the old and the new gain to avoid audible steps.
+                let progress = (i + 1) as f32 / BLOCK_SAMPLES as f32;
+                let gain = previous + (next - previous) * progress;
+                let limited = gain.min(target) * sample;
+
+                (Self::soft_clip(limited) * 32767.0).round() as i16
+            })
+            .collect()
+    }
+
+    fn soft_clip(sample: f32) -> f32 {
+        let magnitude = sample.abs();
+        if magnitude <= Self::SOFT_CLIP_KNEE {
+            return sample;
+        }
+
+        // Above the knee, approach full scale asymptotically.
+        let headroom = 1.0 - Self::SOFT_CLIP_KNEE;
+        let clipped = Self::SOFT_CLIP_KNEE
+            + headroom * ((magnitude - Self::SOFT_CLIP_KNEE) / headroom).tanh();
+        clipped.copysign(sample)
+    }
+}
+
+impl MixerSource {
+    fn has_block(&self) -> bool {
+        self.buffer.len() >= BLOCK_SAMPLES || (!self.fresh && !self.buffer.is_empty())
+    }
+}
+
+impl Gain {
+    pub const UNITY: Gain = Gain(1.0);
+    pub const MUTED: Gain = Gain(0.0);
+
+    /// Creates a gain from a change in decibels, e.g. `-6.0` to roughly halve the amplitude.
+    pub fn from_db(db: f32) -> Self {
+        Self(10f32.powf(db / 20.0))
+    }
+
+    pub fn linear(&self) -> f32 {
+        self.0
+    }
+}
+
+impl Volume {
+    // Boosting participants by more than this is almost certainly a mistake.
+    const MAX_DB: f32 = 24.0;
+
+    pub fn gain(&self) -> Gain {
+        match self {
+            Volume::Unchanged => Gain::UNITY,
+            Volume::Decibels(db) => Gain::from_db(*db),
+            Volume::Muted => Gain::MUTED,
+        }
+    }
+}
+
+impl FromStr for Volume {
+    type Err = ();
+
+    /// Parses `mute`, `unmute` or a change in decibels like `-6` or `3dB`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.to_ascii_lowercase().as_str() {
+            "mute" | "muted" | "off" => return Ok(Volume::Muted),
+            "unmute" | "on" => return Ok(Volume::Unchanged),
+            _ => {}
+        };
+
+        let db = s.strip_suffix("dB").or(s.strip_suffix("db")).unwrap_or(s);
+        match db.trim().parse::<f32>() {
+            Ok(0.0) => Ok(Volume::Unchanged),
+            Ok(db) if db.is_finite() && db <= Self::MAX_DB => Ok(Volume::Decibels(db)),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for Volume {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Volume::Unchanged => write!(f, "0"),
+            Volume::Decibels(db) => write!(f, "{db}"),
+            Volume::Muted => write!(f, "mute"),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Copy> Default for Mixer<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Gain, Mixer, Volume, BLOCK_SAMPLES};
+
+    #[test]
+    fn single_source_passes_through() {
+        let mut mixer = Mixer::new();
+        mixer.add_source(1);
+
+        let input: Vec<i16> = (0..BLOCK_SAMPLES as i16).map(|i| i * 10 - 4800).collect();
+        mixer.push(1, &input);
+
+        assert_eq!(mixer.mix_block(), Some(input));
+        assert_eq!(mixer.mix_block(), None);
+    }
+
+    #[test]
+    fn keeps_partial_blocks_across_calls() {
+        let mut mixer = Mixer::new();
+        mixer.add_source(1);
+        mixer.push(1, &vec![100; BLOCK_SAMPLES + BLOCK_SAMPLES / 2]);
+
+        assert_eq!(mixer.mix_block(), Some(vec![100; BLOCK_SAMPLES]));
+
+        mixer.push(1, &vec![200; BLOCK_SAMPLES / 2]);
+        let block = mixer.mix_block().unwrap();
+        assert_eq!(block[..BLOCK_SAMPLES / 2], vec![100; BLOCK_SAMPLES / 2]);
+        assert_eq!(block[BLOCK_SAMPLES / 2..], vec![200; BLOCK_SAMPLES / 2]);
+    }
+
+    #[test]
+    fn flushes_stale_partial_blocks() {
+        let mut mixer = Mixer::new();
+        mixer.add_source(1);
+        mixer.add_source(2);
+        mixer.push(1, &vec![100; BLOCK_SAMPLES / 2]);
+        mixer.push(2, &vec![100; BLOCK_SAMPLES]);
+
+        // Source 1 just received samples, so it waits for more.
+        assert_eq!(mixer.mix_block(), Some(vec![100; BLOCK_SAMPLES]));
+
+        // Without new samples, its remaining audio is padded with silence.
+        let block = mixer.mix_block().unwrap();
+        assert_eq!(block[..BLOCK_SAMPLES / 2], vec![100; BLOCK_SAMPLES / 2]);
+        assert_eq!(block[BLOCK_SAMPLES / 2..], vec![0; BLOCK_SAMPLES / 2]);
+    }
+
+    #[test]
+    fn ends_partial_blocks_on_request() {
+        let mut mixer = Mixer::new();
+        mixer.add_source(1);
+        mixer.push(1, &vec![100; BLOCK_SAMPLES / 2]);
+
+        // Nothing else would mix it, so the caller has to come back for it.
+        assert_eq!(mixer.mix_block(), None);
+        assert!(mixer.has_samples());
+
+        mixer.end_partial_blocks();
+        let block = mixer.mix_block().unwrap();
+        assert_eq!(block[..BLOCK_SAMPLES / 2], vec![100; BLOCK_SAMPLES / 2]);
+        assert_eq!(block[BLOCK_SAMPLES / 2..], vec![0; BLOCK_SAMPLES / 2]);
+        assert!(!mixer.has_samples());
+    }
+
+    #[test]
+    fn applies_gain() {
+        let mut mixer = Mixer::new();
+        mixer.add_source(1);
+        mixer.add_source(2);
+        mixer.set_gain(1, Gain::from_db(-6.0));
+        mixer.set_gain(2, Gain::MUTED);
+        mixer.push(1, &vec![10000; BLOCK_SAMPLES]);
+        mixer.push(2, &vec![10000; BLOCK_SAMPLES]);
+
+        let block = mixer.mix_block().unwrap();
+        assert!(block.iter().all(|&s| (4990..=5020).contains(&s)));
+    }
+
+    #[test]
+    fn does_not_hard_clip() {
+        let mut mixer = Mixer::new();
+        for source in 0..3 {
+            mixer.add_source(source);
+            mixer.push(source, &vec![30000; BLOCK_SAMPLES]);
+        }
+
+        let block = mixer.mix_block().unwrap();
+        assert!(block.iter().all(|&s| s > 20000 && s < i16::MAX));
+    }
+
+    #[test]
+    fn meters_levels() {
+        let mut mixer = Mixer::new();
+        mixer.add_source(1);
+        mixer.add_source(2);
+        mixer.set_gain(2, Gain::from_db(-6.0));
+        assert_eq!(mixer.level(&1), Some(-127.0));
+
+        mixer.meter_level(1, -20.0);
+        mixer.meter_level(1, -40.0);
+        mixer.meter_level(2, -20.0);
+        assert_eq!(mixer.level(&1), Some(-20.0));
+        assert!((mixer.level(&2).unwrap() + 26.0).abs() < 0.01);
+
+        mixer.push(1, &[0; BLOCK_SAMPLES]);
+        mixer.mix_block();
+        assert!((mixer.level(&1).unwrap() + 20.4).abs() < 0.01);
+
+        mixer.set_gain(1, Gain::MUTED);
+        mixer.meter_level(1, 0.0);
+        assert!((mixer.level(&1).unwrap() + 20.4).abs() < 0.01);
+        assert_eq!(mixer.level(&3), None);
+    }
+
+    #[test]
+    fn parse_volume() {
+        assert_eq!("-6".parse(), Ok(Volume::Decibels(-6.0)));
+        assert_eq!("3dB".parse(), Ok(Volume::Decibels(3.0)));
+        assert_eq!("0".parse(), Ok(Volume::Unchanged));
+        assert_eq!("mute".parse(), Ok(Volume::Muted));
+        assert_eq!("100".parse::<Volume>(), Err(()));
+        assert_eq!("loud".parse::<Volume>(), Err(()));
+    }
+}
@@ -0,0 +1,10 @@
+pub mod adaptive_jitter;
+pub mod archive;
+pub mod jitter;
+pub mod mixer;
+pub mod receiver;
+pub mod recording;
+pub mod resegment;
+pub mod sequence;
+pub mod speaker;
+pub mod vad;
@@ -0,0 +1,579 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use log::{debug, trace, warn};
+use twilight_model::id::{marker::UserMarker, Id};
+
+use super::archive::CallArchive;
+use super::jitter::{BufferedFrame, Concealment, JitterBuffer, JitterBufferFactory, Playout};
+use super::mixer::{Mixer, Volume};
+use super::recording::CallRecording;
+use super::sequence::{SequenceCheck, SequenceTracker};
+use super::speaker::ParticipantLevel;
+use super::vad::level_db;
+use crate::constants::SAMPLE_RATE;
+use crate::discord::rtp::VoicePacket;
+use crate::error::{ChanRes, DiscordError};
+use crate::stats::{ParticipantStats, ReceiveCounter};
+use crate::utils::rtp::parse_extensions;
+
+/// Turns the RTP streams of all other participants in a call into a single mixed stream.
+///
+/// Packets are decoded as they arrive and then go through a jitter buffer per SSRC, before the
+/// frames that are due are mixed into 20ms blocks. This doesn't depend on Asterisk and never looks
+/// at the clock, the current time is passed in by callers.
+pub struct ReceivePipeline {
+    user_id_to_ssrc: HashMap<Id<UserMarker>, u32>,
+    ssrc_to_participant: HashMap<u32, OtherParticipant>,
+    // Volumes are tracked per user so that they survive SSRC changes and users re-joining.
+    user_volumes: HashMap<Id<UserMarker>, Volume>,
+    mixer: Mixer<u32>,
+    recording: Option<CallRecording>,
+    archive: Option<CallArchive>,
+    known_next: Option<KnownNextFrameTime>,
+    // When the partial blocks left in the mixer are mixed, unless their sources deliver the rest.
+    flush_partial_at: Option<Instant>,
+    new_jitter_buffer: JitterBufferFactory,
+    // Statistics of participants that have left the call or changed their SSRC.
+    past_participants: Vec<ParticipantStats>,
+    // SSRCs of video streams, which we don't decode.
+    ignored_ssrcs: HashSet<u32>,
+}
+
+pub enum FetchResult {
+    Block(Vec<i16>),
+    CheckBackLater { time: Instant },
+    NoneQueued,
+}
+
+struct OtherParticipant {
+    user: Id<UserMarker>,
+    decoder: opus::Decoder,
+    initial_timestamp: Option<u32>,
+    jitter_buffer: Option<Box<dyn JitterBuffer>>,
+    concealment: Concealment,
+    last_voice_length: Duration,
+    sequence: SequenceTracker,
+    counter: ReceiveCounter,
+    // Packets the jitter buffers dropped before the sender restarted its stream.
+    late_before_restart: u64,
+    last_packet: Option<Instant>,
+    // When the sender last flagged a packet as containing speech.
+    last_voice: Option<Instant>,
+}
+
+#[derive(Clone, Copy)]
+struct KnownNextFrameTime {
+    due: Instant,
+    ssrc: u32,
+}
+
+impl ReceivePipeline {
+    const ASSUMED_VOICE_LENGTH: Duration = Duration::from_millis(20);
+    // How long a partial block waits for the rest of its samples.
+    const PARTIAL_BLOCK_WAIT: Duration = Duration::from_millis(20);
+    // Senders stop sending packets during silence, so the level of the last packet expires.
+    const LEVEL_HOLD: Duration = Duration::from_millis(200);
+    const SILENT_LEVEL: f32 = -127.0;
+
+    pub fn new(new_jitter_buffer: JitterBufferFactory) -> Self {
+        Self {
+            user_id_to_ssrc: HashMap::new(),
+            ssrc_to_participant: HashMap::new(),
+            user_volumes: HashMap::new(),
+            mixer: Mixer::new(),
+            recording: None,
+            archive: None,
+            known_next: None,
+            flush_partial_at: None,
+            new_jitter_buffer,
+            past_participants: vec![],
+            ignored_ssrcs: HashSet::new(),
+        }
+    }
+
+    /// Starts receiving audio from [user] on [ssrc]. When a user changes their SSRC, the old one
+    /// is dropped along with its decoder and jitter buffer.
+    pub fn map_user_id(&mut self, user: Id<UserMarker>, ssrc: u32) -> ChanRes<()> {
+        if self.ignored_ssrcs.contains(&ssrc) {
+            debug!("Not mapping user {user} to ssrc {ssrc}, it belongs to a video stream");
+            return Ok(());
+        }
+
+        if let Some(participant) = self.ssrc_to_participant.get(&ssrc) {
+            if participant.user == user {
+                // Already mapped, nothing to do
+                return Ok(());
+            }
+
+            // Discord reassigned the SSRC of someone who left to another user.
+            let previous_user = participant.user;
+            debug!("SSRC {ssrc} moved from user {previous_user} to {user}");
+            self.user_id_to_ssrc.remove(&previous_user);
+            self.remove_participant(ssrc);
+        }
+
+        if let Some(previous_ssrc) = self.user_id_to_ssrc.insert(user, ssrc) {
+            debug!("User {user} changed ssrc from {previous_ssrc} to {ssrc}");
+            self.remove_participant(previous_ssrc);
+        }
+
+        self.ssrc_to_participant.insert(
+            ssrc,
+            OtherParticipant {
+                user,
+                decoder: opus::Decoder::new(SAMPLE_RATE, opus::Channels::Stereo)
+                    .map_err(|e| DiscordError::InternalError { source: e.into() })?,
+                jitter_buffer: None,
+                concealment: Concealment::default(),
+                initial_timestamp: None,
+                last_voice_length: Self::ASSUMED_VOICE_LENGTH,
+                sequence: SequenceTracker::default(),
+                counter: ReceiveCounter::default(),
+                late_before_restart: 0,
+                last_packet: None,
+                last_voice: None,
+            },
+        );
+        self.mixer.add_source(ssrc);
+        self.mixer.set_gain(ssrc, self.user_volume(user).gain());
+
+        Ok(())
+    }
+
+    pub fn unmap_user_id(&mut self, user: Id<UserMarker>) {
+        if let Some(ssrc) = self.user_id_to_ssrc.remove(&user) {
+            self.remove_participant(ssrc);
+        }
+    }
+
+    /// Drops packets from [ssrc] without warning about them, for streams we don't handle like
+    /// video and its retransmissions.
+    pub fn ignore_ssrc(&mut self, ssrc: u32) {
+        if self.ignored_ssrcs.insert(ssrc) && self.ssrc_to_participant.contains_key(&ssrc) {
+            warn!("SSRC {ssrc} was mapped for audio, but belongs to a video stream");
+            self.user_id_to_ssrc.retain(|_, mapped| *mapped != ssrc);
+            self.remove_participant(ssrc);
+        }
+    }
+
+    /// Drops every participant, for when the call moves to another voice channel where SSRCs are
+    /// assigned anew. Their statistics and volumes are kept.
+    pub fn remove_all_participants(&mut self) {
+        let ssrcs: Vec<u32> = self.ssrc_to_participant.keys().copied().collect();
+        for ssrc in ssrcs {
+            self.remove_participant(ssrc);
+        }
+        self.user_id_to_ssrc.clear();
+        self.ignored_ssrcs.clear();
+    }
+
+    fn remove_participant(&mut self, ssrc: u32) {
+        if let Some(participant) = self.ssrc_to_participant.remove(&ssrc) {
+            self.past_participants.push(participant.stats(ssrc));
+        }
+        self.mixer.remove_source(&ssrc);
+        // Should the SSRC come back, it's a new stream.
+        if let Some(recording) = &mut self.recording {
+            recording.restart_received(ssrc);
+        }
+        if let Some(archive) = &mut self.archive {
+            archive.restart_received(ssrc);
+        }
+
+        if self.known_next.is_some_and(|known| known.ssrc == ssrc) {
+            self.known_next = None;
+        }
+    }
+
+    pub fn is_mapped(&self, ssrc: u32) -> bool {
+        self.ssrc_to_participant.contains_key(&ssrc)
+    }
+
+    pub fn set_user_volume(&mut self, user: Id<UserMarker>, volume: Volume) {
+        self.user_volumes.insert(user, volume);
+
+        if let Some(ssrc) = self.user_id_to_ssrc.get(&user) {
+            self.mixer.set_gain(*ssrc, volume.gain());
+        }
+    }
+
+    pub fn user_volume(&self, user: Id<UserMarker>) -> Volume {
+        self.user_volumes.get(&user).copied().unwrap_or_default()
+    }
+
+    pub fn set_recording(&mut self, recording: Option<CallRecording>) {
+        self.recording = recording;
+    }
+
+    pub fn set_archive(&mut self, archive: Option<CallArchive>) {
+        self.archive = archive;
+    }
+
+    pub fn finish_capture(&mut self) {
+        if let Some(recording) = self.recording.take() {
+            if let Err(e) = recording.finish() {
+                warn!("Could not finish call recording: {e:?}");
+            }
+        }
+        if let Some(archive) = self.archive.take() {
+            archive.finish();
+        }
+    }
+
+    /// Statistics of everyone who sent audio in this call, including participants that left.
+    pub fn stats(&self) -> Vec<ParticipantStats> {
+        let current = self
+            .ssrc_to_participant
+            .iter()
+            .map(|(ssrc, participant)| participant.stats(*ssrc));
+
+        self.past_participants
+            .iter()
+            .cloned()
+            .chain(current)
+            .collect()
+    }
+
+    /// The current levels of all participants whose packets carry audio levels.
+    pub fn levels(&self, now: Instant) -> Vec<ParticipantLevel> {
+        let is_recent = |time: Option<Instant>| {
+            time.is_some_and(|time| now.saturating_duration_since(time) < Self::LEVEL_HOLD)
+        };
+
+        self.ssrc_to_participant
+            .iter()
+            .filter_map(|(ssrc, participant)| {
+                let level_db = self.mixer.level(ssrc)?;
+
+                Some(ParticipantLevel {
+                    user: participant.user,
+                    ssrc: *ssrc,
+                    level_db: if is_recent(participant.last_packet) {
+                        level_db
+                    } else {
+                        Self::SILENT_LEVEL
+                    },
+                    voice: is_recent(participant.last_voice),
+                })
+            })
+            .collect()
+    }
+
+    /// When the next frame of any participant is due.
+    pub fn next_frame_time(&mut self) -> Option<Instant> {
+        self.next_known_frame().map(|known| known.due)
+    }
+
+    fn next_known_frame(&mut self) -> Option<KnownNextFrameTime> {
+        match self.known_next {
+            Some(known) => Some(known),
+            None => {
+                let map = &self.ssrc_to_participant;
+                let (ssrc, time) = map
+                    .iter()
+                    .filter_map(|(ssrc, entry)| {
+                        Some((ssrc, entry.jitter_buffer.as_ref()?.next_frame()?))
+                    })
+                    .min_by_key(|(_, time)| *time)?;
+
+                let time = KnownNextFrameTime {
+                    due: time,
+                    ssrc: *ssrc,
+                };
+                self.known_next = Some(time);
+                Some(time)
+            }
+        }
+    }
+
+    /// Takes all frames that are due at [now] out of the jitter buffers and mixes them.
+    pub fn fetch(&mut self, now: Instant) -> FetchResult {
+        if self.next_frame_time().is_some_and(|due| due <= now) {
+            self.play_out(now);
+            // The jitterbuffers have moved on, so the next due time needs to be recomputed.
+            self.known_next = None;
+        } else if self.flush_partial_at.is_some_and(|flush| flush <= now) {
+            self.mixer.end_partial_blocks();
+        } else {
+            return self.check_back_later();
+        }
+
+        let block = self.mixer.mix_block();
+        self.flush_partial_at = self
+            .mixer
+            .has_samples()
+            .then_some(now + Self::PARTIAL_BLOCK_WAIT);
+        match block {
+            Some(block) => FetchResult::Block(block),
+            None => self.check_back_later(),
+        }
+    }
+
+    /// When [ReceivePipeline::fetch] could return the next block. Partial blocks get mixed even if
+    /// no more frames are due, so they aren't held back until the next packet arrives.
+    fn check_back_later(&mut self) -> FetchResult {
+        let next_frame = self.next_frame_time();
+        match next_frame.into_iter().chain(self.flush_partial_at).min() {
+            Some(time) => FetchResult::CheckBackLater { time },
+            None => FetchResult::NoneQueued,
+        }
+    }
+
+    fn play_out(&mut self, now: Instant) {
+        for (ssrc, entry) in self.ssrc_to_participant.iter_mut() {
+            let Some(jitter_buffer) = &mut entry.jitter_buffer else {
+                continue;
+            };
+
+            match jitter_buffer.get(entry.last_voice_length, now) {
+                Some(Playout::Frame(frame)) => {
+                    entry.concealment.played(&frame);
+                    self.mixer.push(*ssrc, &frame);
+                }
+                Some(Playout::Conceal { duration }) => {
+                    let samples = duration.as_micros() * SAMPLE_RATE as u128 / 1_000_000;
+                    let frame = entry.concealment.conceal(samples as usize);
+                    self.mixer.push(*ssrc, &frame);
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Decodes a packet that arrived at [now] and adds it to the jitter buffer of its sender.
+    pub fn handle_packet(&mut self, packet: VoicePacket, now: Instant) {
+        match packet {
+            VoicePacket::Rtp(packet) => {
+                let Some((extensions, range)) =
+                    parse_extensions(&packet.buffer, packet.data_range.clone())
+                else {
+                    debug!(
+                        "Not enough of packet left after its header extensions, ssrc {}",
+                        packet.ssrc
+                    );
+                    return;
+                };
+                let data = &packet.buffer[range];
+
+                let Some(participant) = self.ssrc_to_participant.get_mut(&packet.ssrc) else {
+                    if self.ignored_ssrcs.contains(&packet.ssrc) {
+                        return;
+                    }
+                    debug!(
+                        "Received RTP packet from unknown sender, ssrc: {}",
+                        packet.ssrc
+                    );
+                    return;
+                };
+
+                let previous_lost = match participant.sequence.check(packet.sequence_number) {
+                    SequenceCheck::InOrder { lost } => {
+                        participant.counter.record_lost(lost);
+                        lost > 0
+                    }
+                    SequenceCheck::Reordered => {
+                        participant.counter.record_reordered();
+                        false
+                    }
+                    SequenceCheck::Duplicate => {
+                        trace!(
+                            "Dropping duplicate packet {} of ssrc {}",
+                            packet.sequence_number,
+                            packet.ssrc
+                        );
+                        return;
+                    }
+                    SequenceCheck::Ignored => {
+                        debug!(
+                            "Ignoring packet {} of ssrc {}, it's far away from the current sequence",
+                            packet.sequence_number, packet.ssrc
+                        );
+                        return;
+                    }
+                    SequenceCheck::Restart => {
+                        debug!("Sender with ssrc {} restarted its stream", packet.ssrc);
+                        participant.restart();
+                        if let Some(recording) = &mut self.recording {
+                            recording.restart_received(packet.ssrc);
+                        }
+                        if let Some(archive) = &mut self.archive {
+                            archive.restart_received(packet.ssrc);
+                        }
+                        if self
+                            .known_next
+                            .is_some_and(|known| known.ssrc == packet.ssrc)
+                        {
+                            self.known_next = None;
+                        }
+                        false
+                    }
+                };
+                participant.counter.record_packet(packet.timestamp, now);
+                participant.last_packet = Some(now);
+
+                let audio_level = extensions.audio_level();
+                if let Some(level) = audio_level {
+                    self.mixer.meter_level(packet.ssrc, level.db());
+                    if level.voice {
+                        participant.last_voice = Some(now);
+                    }
+                }
+
+                if let Some(archive) = &mut self.archive {
+                    archive.write_received(
+                        participant.user,
+                        packet.ssrc,
+                        packet.timestamp,
+                        now,
+                        data,
+                    );
+                }
+
+                if previous_lost {
+                    // Opus packets may carry a lower quality copy of the previous frame. Without
+                    // one, the decoder conceals the loss instead, which still sounds better than
+                    // concealing it later by fading out the last frame.
+                    let samples = duration_to_samples(participant.last_voice_length);
+                    let sequence_number = packet.sequence_number.wrapping_sub(1);
+                    if let Ok(voice) = participant.decode(data, true, samples) {
+                        participant.sequence.mark_received(sequence_number);
+                        participant.buffer(
+                            voice,
+                            packet.timestamp.wrapping_sub(samples as u32),
+                            sequence_number,
+                            now,
+                            &self.new_jitter_buffer,
+                        );
+                    }
+                }
+
+                match participant.decode(data, false, 960) {
+                    Ok(voice) => {
+                        if audio_level.is_none() {
+                            self.mixer.meter_level(packet.ssrc, level_db(&voice));
+                        }
+
+                        if let Some(recording) = &mut self.recording {
+                            recording.write_received(
+                                participant.user,
+                                packet.ssrc,
+                                packet.timestamp,
+                                now,
+                                &voice,
+                            );
+                        }
+
+                        participant.last_voice_length = Duration::from_millis(
+                            (1000 * voice.len() as u64) / (SAMPLE_RATE as u64),
+                        );
+
+                        // The expected time for the next frame may have changed.
+                        let Some(time) = participant.buffer(
+                            voice,
+                            packet.timestamp,
+                            packet.sequence_number,
+                            now,
+                            &self.new_jitter_buffer,
+                        ) else {
+                            return;
+                        };
+
+                        if let Some(known) = &mut self.known_next {
+                            if known.ssrc == packet.ssrc {
+                                known.due = time;
+                            } else if time < known.due {
+                                known.due = time;
+                                known.ssrc = packet.ssrc;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        participant.counter.record_decode_error();
+                        warn!("Could not decode voice data: {e}");
+                    }
+                }
+            }
+            VoicePacket::Rtcp(_packet) => {}
+        };
+    }
+}
+
+impl OtherParticipant {
+    /// Decodes a packet into mono samples, or with [fec] the frame before it. [samples] is the
+    /// length of the frame per channel.
+    fn decode(&mut self, data: &[u8], fec: bool, samples: usize) -> Result<Vec<i16>, opus::Error> {
+        let mut voice = vec![0; 2 * samples];
+        let actual_samples = self.decoder.decode(data, &mut voice, fec)?;
+
+        // Monoize the samples, rounding towards positive infinity.
+        for i in 0..actual_samples {
+            let left = voice[2 * i] as i32;
+            let right = voice[2 * i + 1] as i32;
+
+            voice[i] = ((left + right + 1) >> 1) as i16;
+        }
+        voice.truncate(actual_samples);
+
+        Ok(voice)
+    }
+
+    /// Adds a decoded frame to the jitter buffer and returns when its next frame is due.
+    fn buffer(
+        &mut self,
+        samples: Vec<i16>,
+        rtp_timestamp: u32,
+        sequence_number: u16,
+        now: Instant,
+        new_jitter_buffer: &JitterBufferFactory,
+    ) -> Option<Instant> {
+        let jitter_buffer = self
+            .jitter_buffer
+            .get_or_insert_with(|| new_jitter_buffer(now));
+        let base_timestamp = *self.initial_timestamp.get_or_insert(rtp_timestamp);
+
+        let frame = BufferedFrame {
+            duration: Duration::from_millis((1000 * samples.len() as u64) / (SAMPLE_RATE as u64)),
+            samples,
+            // In RTP, the timestamp is measured in samples, but we want to measure it in
+            // milliseconds.
+            timestamp: (1000 * rtp_timestamp.wrapping_sub(base_timestamp) as i32 as i64)
+                / (SAMPLE_RATE as i64),
+            sequence_number,
+        };
+        jitter_buffer.put(frame, now);
+
+        jitter_buffer.next_frame()
+    }
+
+    /// Starts over after the sender restarted its stream. Its timestamps and sequence numbers
+    /// have nothing to do with the old ones, so the jitter buffer can't be reused.
+    fn restart(&mut self) {
+        if let Some(jitter_buffer) = self.jitter_buffer.take() {
+            self.late_before_restart += jitter_buffer.stats().late;
+        }
+        self.initial_timestamp = None;
+        self.concealment = Concealment::default();
+        if let Err(e) = self.decoder.reset_state() {
+            warn!("Could not reset decoder: {e}");
+        }
+    }
+
+    fn stats(&self, ssrc: u32) -> ParticipantStats {
+        let late = self.late_before_restart
+            + self
+                .jitter_buffer
+                .as_ref()
+                .map_or(0, |jitter_buffer| jitter_buffer.stats().late);
+
+        ParticipantStats {
+            user: self.user,
+            ssrc,
+            received: self.counter.stats(late),
+        }
+    }
+}
+
+fn duration_to_samples(duration: Duration) -> usize {
+    (duration.as_micros() * SAMPLE_RATE as u128 / 1_000_000) as usize
+}
@@ -0,0 +1,393 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use hound::{SampleFormat, WavSpec, WavWriter};
+use log::{debug, warn};
+use serde::Serialize;
+use twilight_model::id::{marker::UserMarker, Id};
+
+use crate::constants::SAMPLE_RATE;
+
+/// Records every participant of a call into a separate WAV file.
+///
+/// All files start at the same point in time (when the recording was created), so they can be
+/// mixed or compared later without additional alignment. Next to the audio files, a JSON file
+/// describes which file belongs to which Discord user.
+pub struct CallRecording {
+    directory: PathBuf,
+    prefix: String,
+    timeline_start: Instant,
+    metadata: RecordingMetadata,
+    received: Vec<(u32, Id<UserMarker>, Track)>,
+}
+
+/// A single mono 48kHz audio file, aligned to the start of the call recording.
+pub struct Track {
+    writer: Option<WavWriter<BufWriter<File>>>,
+    timeline: Timeline,
+    written_samples: u64,
+}
+
+/// Maps RTP timestamps of a stream onto a timeline starting at a fixed instant.
+///
+/// The first packet is placed according to its arrival time, later packets relative to it
+/// according to their timestamps. This keeps jitter in arrival times out of the recording. When the
+/// timestamps jump far away from the arrival times, packets are placed by arrival time again.
+pub struct Timeline {
+    start: Instant,
+    last: Option<(u32, u64)>,
+    // Where a restarted stream starts at the earliest, to not overlap what was written before.
+    earliest: u64,
+}
+
+#[derive(Serialize)]
+struct RecordingMetadata {
+    started_at_unix_ms: u128,
+    sample_rate: u32,
+    tracks: Vec<TrackMetadata>,
+}
+
+#[derive(Serialize)]
+struct TrackMetadata {
+    file: String,
+    direction: TrackDirection,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_id: Option<Id<UserMarker>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ssrc: Option<u32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum TrackDirection {
+    Received,
+    Sent,
+    Mixed,
+}
+
+impl CallRecording {
+    /// Prepares a recording writing files named `<prefix>-...` into [directory].
+    pub fn new(directory: impl Into<PathBuf>, prefix: impl Into<String>) -> anyhow::Result<Self> {
+        let directory = directory.into();
+        std::fs::create_dir_all(&directory)
+            .with_context(|| format!("Could not create recording directory {directory:?}"))?;
+
+        Ok(Self {
+            directory,
+            prefix: prefix.into(),
+            timeline_start: Instant::now(),
+            metadata: RecordingMetadata {
+                started_at_unix_ms: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis(),
+                sample_rate: SAMPLE_RATE,
+                tracks: vec![],
+            },
+            received: vec![],
+        })
+    }
+
+    /// Creates the track for audio we're sending to Discord.
+    pub fn sent_track(&mut self) -> Track {
+        let file = format!("{}-sent.wav", self.prefix);
+        self.create_track(file, TrackDirection::Sent, None, None)
+    }
+
+    /// Creates a track for the mix of all received audio, as it is passed on to Asterisk.
+    pub fn mixed_track(&mut self) -> Track {
+        let file = format!("{}-mixed.wav", self.prefix);
+        self.create_track(file, TrackDirection::Mixed, None, None)
+    }
+
+    /// The time at which all tracks of this recording start.
+    pub fn start(&self) -> Instant {
+        self.timeline_start
+    }
+
+    /// Records decoded audio received from [ssrc], creating a new file when the SSRC is new or now
+    /// belongs to another user.
+    pub fn write_received(
+        &mut self,
+        user: Id<UserMarker>,
+        ssrc: u32,
+        timestamp: u32,
+        now: Instant,
+        samples: &[i16],
+    ) {
+        let index = self
+            .received
+            .iter()
+            .position(|(s, u, _)| *s == ssrc && *u == user);
+        let index = match index {
+            Some(index) => index,
+            None => {
+                let file = format!("{}-{user}-{ssrc}.wav", self.prefix);
+                let track =
+                    self.create_track(file, TrackDirection::Received, Some(user), Some(ssrc));
+                self.received.push((ssrc, user, track));
+                self.received.len() - 1
+            }
+        };
+
+        self.received[index].2.write(timestamp, now, samples);
+    }
+
+    /// Lets the next audio from [ssrc] start a new stream, whose timestamps have nothing to do with
+    /// the previous ones.
+    pub fn restart_received(&mut self, ssrc: u32) {
+        for (_, _, track) in self.received.iter_mut().filter(|(s, _, _)| *s == ssrc) {
+            track.restart();
+        }
+    }
+
+    /// Finalizes all received tracks and writes the metadata file.
+    pub fn finish(self) -> anyhow::Result<()> {
+        for (_, _, track) in self.received {
+            track.finish();
+        }
+
+        let path = self.directory.join(format!("{}.json", self.prefix));
+        let file = File::create(&path).with_context(|| format!("Could not create {path:?}"))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &self.metadata)?;
+        debug!("Wrote recording metadata to {path:?}");
+        Ok(())
+    }
+
+    fn create_track(
+        &mut self,
+        file: String,
+        direction: TrackDirection,
+        user_id: Option<Id<UserMarker>>,
+        ssrc: Option<u32>,
+    ) -> Track {
+        let track = Track::create(&self.directory.join(&file), self.timeline_start);
+        if track.writer.is_some() {
+            self.metadata.tracks.push(TrackMetadata {
+                file,
+                direction,
+                user_id,
+                ssrc,
+            });
+        }
+
+        track
+    }
+}
+
+impl std::fmt::Debug for CallRecording {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CallRecording")
+            .field("directory", &self.directory)
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Track {
+    fn create(path: &Path, timeline_start: Instant) -> Self {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+
+        let writer = match WavWriter::create(path, spec) {
+            Ok(writer) => Some(writer),
+            Err(e) => {
+                warn!("Could not create recording file {path:?}: {e}");
+                None
+            }
+        };
+
+        Self {
+            writer,
+            timeline: Timeline::new(timeline_start),
+            written_samples: 0,
+        }
+    }
+
+    /// Writes [samples] with the RTP [timestamp], filling gaps since the last write with silence.
+    pub fn write(&mut self, timestamp: u32, now: Instant, samples: &[i16]) {
+        let Some(writer) = &mut self.writer else {
+            return;
+        };
+
+        let position = self.timeline.position(timestamp, now);
+        // Samples overlapping with what we've already written (e.g. retransmitted or reordered
+        // packets) are skipped.
+        let skip = self.written_samples.saturating_sub(position) as usize;
+        let silence = position.saturating_sub(self.written_samples);
+
+        let res: hound::Result<()> = (|| {
+            for _ in 0..silence {
+                writer.write_sample(0i16)?;
+            }
+            for sample in samples.iter().skip(skip) {
+                writer.write_sample(*sample)?;
+            }
+            Ok(())
+        })();
+
+        match res {
+            Ok(()) => {
+                self.written_samples += silence + samples.len().saturating_sub(skip) as u64;
+            }
+            Err(e) => {
+                warn!("Could not write recording, stopping this track: {e}");
+                self.writer = None;
+            }
+        }
+    }
+
+    /// Places the next samples by their arrival time, after the ones written so far.
+    pub fn restart(&mut self) {
+        self.timeline.restart(self.written_samples);
+    }
+
+    pub fn finish(self) {
+        if let Some(writer) = self.writer {
+            if let Err(e) = writer.finalize() {
+                warn!("Could not finalize recording: {e}");
+            }
+        }
+    }
+}
+
+impl Timeline {
+    // Timestamps further away from the arrival time than this don't belong to the same stream.
+    const MAX_JUMP: Duration = Duration::from_secs(5);
+
+    pub fn new(start: Instant) -> Self {
+        Self {
+            start,
+            last: None,
+            earliest: 0,
+        }
+    }
+
+    /// Returns the position of the first sample with the RTP [timestamp], in samples since the
+    /// start of the timeline.
+    pub fn position(&mut self, timestamp: u32, now: Instant) -> u64 {
+        let arrival = duration_to_samples(now.saturating_duration_since(self.start));
+        let position = match self.last {
+            None => arrival.max(self.earliest),
+            Some((last_timestamp, last_position)) => {
+                let delta = timestamp.wrapping_sub(last_timestamp) as i32 as i64;
+                let position = (last_position as i64 + delta).max(0) as u64;
+                if position.abs_diff(arrival) <= duration_to_samples(Self::MAX_JUMP) {
+                    position
+                } else {
+                    debug!("RTP timestamp {timestamp} jumped away from the arrival time");
+                    arrival.max(last_position)
+                }
+            }
+        };
+
+        self.last = Some((timestamp, position));
+        position
+    }
+
+    /// Places the next packet by its arrival time again, but not before [written], the end of
+    /// what has been written for the previous stream.
+    pub fn restart(&mut self, written: u64) {
+        self.last = None;
+        self.earliest = written;
+    }
+}
+
+fn duration_to_samples(duration: Duration) -> u64 {
+    (duration.as_micros() * SAMPLE_RATE as u128 / 1_000_000) as u64
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{CallRecording, Timeline};
+
+    #[test]
+    fn timeline_follows_rtp_timestamps() {
+        let start = Instant::now();
+        let mut timeline = Timeline::new(start);
+
+        assert_eq!(
+            timeline.position(u32::MAX - 959, start + Duration::from_secs(1)),
+            48_000
+        );
+        // Arrival jitter doesn't matter after the first packet, wraparound is handled.
+        assert_eq!(timeline.position(1, start), 48_961);
+        assert_eq!(timeline.position(u32::MAX - 959, start), 48_000);
+    }
+
+    #[test]
+    fn timeline_survives_discontinuities() {
+        let start = Instant::now();
+        let mut timeline = Timeline::new(start);
+        assert_eq!(timeline.position(1000, start), 0);
+        assert_eq!(
+            timeline.position(1960, start + Duration::from_millis(20)),
+            960
+        );
+
+        // The sender restarted with a random timestamp, which is placed by its arrival time.
+        assert_eq!(
+            timeline.position(2_000_000_000, start + Duration::from_secs(1)),
+            48_000
+        );
+        assert_eq!(
+            timeline.position(2_000_000_960, start + Duration::from_millis(1020)),
+            48_960
+        );
+
+        // After a restart, but not on top of what has already been written.
+        timeline.restart(96_000);
+        assert_eq!(timeline.position(7, start + Duration::from_secs(1)), 96_000);
+        timeline.restart(0);
+        assert_eq!(
+            timeline.position(7, start + Duration::from_secs(3)),
+            144_000
+        );
+    }
+
+    #[test]
+    fn records_aligned_tracks() {
+        let directory = std::env::temp_dir().join(format!("recording-{}", std::process::id()));
+        let mut recording = CallRecording::new(&directory, "call").unwrap();
+        let start = recording.timeline_start;
+        let user = twilight_model::id::Id::new(1234);
+
+        let mut sent = recording.sent_track();
+        sent.write(0, start, &[1; 960]);
+        sent.finish();
+
+        recording.write_received(user, 42, 5000, start + Duration::from_millis(20), &[2; 960]);
+        // A lost packet, the gap is filled with silence.
+        recording.write_received(user, 42, 5000 + 1920, start, &[3; 960]);
+        recording.finish().unwrap();
+
+        let samples: Vec<i16> = hound::WavReader::open(directory.join("call-1234-42.wav"))
+            .unwrap()
+            .into_samples()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(samples.len(), 4 * 960);
+        assert!(samples[..960].iter().all(|s| *s == 0));
+        assert!(samples[960..1920].iter().all(|s| *s == 2));
+        assert!(samples[1920..2880].iter().all(|s| *s == 0));
+        assert!(samples[2880..].iter().all(|s| *s == 3));
+
+        let metadata: serde_json::Value =
+            serde_json::from_reader(std::fs::File::open(directory.join("call.json")).unwrap())
+                .unwrap();
+        assert_eq!(metadata["tracks"][0]["file"], "call-sent.wav");
+        assert_eq!(metadata["tracks"][1]["user_id"], "1234");
+        assert_eq!(metadata["tracks"][1]["ssrc"], 42);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
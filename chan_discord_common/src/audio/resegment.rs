@@ -0,0 +1,156 @@
+use std::time::{Duration, Instant};
+
+use crate::constants::{NUM_SAMPLES, SAMPLE_RATE};
+
+/// Cuts outgoing audio into the 20ms frames we send to Discord.
+///
+/// Asterisk channels can write frames of any length (10ms and 30ms frames are common for some
+/// technologies), but Opus only supports a few frame sizes and Discord expects 20ms. This buffers
+/// samples until a full frame is available, and derives RTP timestamps from the amount of samples
+/// actually sent instead of assuming that every written frame is 20ms long.
+///
+/// When no audio is written for a while (e.g. because the other side stopped sending), the
+/// timestamp jumps ahead by the length of the gap so that it keeps following the wall clock.
+pub struct Resegmenter {
+    buffer: Vec<i16>,
+    next_timestamp: u32,
+    next_expected: Option<Instant>,
+}
+
+pub struct OutgoingFrame {
+    pub timestamp: u32,
+    pub samples: Vec<i16>,
+}
+
+const FRAME_SAMPLES: usize = NUM_SAMPLES as usize;
+
+impl Resegmenter {
+    // Frames written by Asterisk arrive with some jitter, only larger delays are treated as gaps.
+    const GAP_TOLERANCE: Duration = Duration::from_millis(60);
+
+    pub fn new(initial_timestamp: u32) -> Self {
+        Self {
+            buffer: Vec::with_capacity(2 * FRAME_SAMPLES),
+            next_timestamp: initial_timestamp,
+            next_expected: None,
+        }
+    }
+
+    /// Adds [samples] written at [now], returning all frames that are complete afterwards.
+    pub fn push(&mut self, samples: &[i16], now: Instant) -> Vec<OutgoingFrame> {
+        let mut frames = vec![];
+
+        if let Some(gap) = self
+            .next_expected
+            .and_then(|expected| now.checked_duration_since(expected))
+            .filter(|gap| *gap > Self::GAP_TOLERANCE)
+        {
+            let mut gap_samples = duration_to_samples(gap);
+
+            // Don't hold back the end of the previous audio until the next gap, send it now and
+            // count the padding towards the gap.
+            if !self.buffer.is_empty() {
+                gap_samples = gap_samples.saturating_sub(FRAME_SAMPLES - self.buffer.len());
+                self.buffer.resize(FRAME_SAMPLES, 0);
+                frames.push(self.take_frame());
+            }
+
+            self.next_timestamp = self.next_timestamp.wrapping_add(gap_samples as u32);
+        }
+
+        self.next_expected = Some(now + samples_to_duration(samples.len()));
+        self.buffer.extend_from_slice(samples);
+        while self.buffer.len() >= FRAME_SAMPLES {
+            frames.push(self.take_frame());
+        }
+
+        frames
+    }
+
+    fn take_frame(&mut self) -> OutgoingFrame {
+        let rest = self.buffer.split_off(FRAME_SAMPLES);
+        let samples = std::mem::replace(&mut self.buffer, rest);
+
+        let timestamp = self.next_timestamp;
+        self.next_timestamp = timestamp.wrapping_add(FRAME_SAMPLES as u32);
+
+        OutgoingFrame { timestamp, samples }
+    }
+}
+
+fn duration_to_samples(duration: Duration) -> usize {
+    (duration.as_micros() * SAMPLE_RATE as u128 / 1_000_000) as usize
+}
+
+fn samples_to_duration(samples: usize) -> Duration {
+    Duration::from_micros(samples as u64 * 1_000_000 / SAMPLE_RATE as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{Resegmenter, FRAME_SAMPLES};
+
+    fn timestamps(frames: &[super::OutgoingFrame]) -> Vec<u32> {
+        frames.iter().map(|f| f.timestamp).collect()
+    }
+
+    #[test]
+    fn combines_short_frames() {
+        let start = Instant::now();
+        let mut resegmenter = Resegmenter::new(1000);
+
+        assert!(resegmenter.push(&[1; 480], start).is_empty());
+        let frames = resegmenter.push(&[2; 480], start + Duration::from_millis(10));
+        assert_eq!(timestamps(&frames), vec![1000]);
+        assert_eq!(frames[0].samples.len(), FRAME_SAMPLES);
+
+        assert!(resegmenter
+            .push(&[3; 480], start + Duration::from_millis(20))
+            .is_empty());
+        let frames = resegmenter.push(&[4; 480], start + Duration::from_millis(30));
+        assert_eq!(timestamps(&frames), vec![1960]);
+    }
+
+    #[test]
+    fn splits_long_frames() {
+        let start = Instant::now();
+        let mut resegmenter = Resegmenter::new(u32::MAX - 1000);
+
+        let mut all = vec![];
+        for i in 0..4 {
+            all.extend(resegmenter.push(&[0; 1440], start + Duration::from_millis(30 * i)));
+        }
+
+        // 120ms of audio are exactly six frames, timestamps wrap around.
+        let expected: Vec<u32> = (0..6)
+            .map(|i| (u32::MAX - 1000).wrapping_add(960 * i))
+            .collect();
+        assert_eq!(timestamps(&all), expected);
+        assert!(all.iter().all(|f| f.samples.len() == FRAME_SAMPLES));
+    }
+
+    #[test]
+    fn jumps_over_gaps() {
+        let start = Instant::now();
+        let mut resegmenter = Resegmenter::new(0);
+
+        assert_eq!(timestamps(&resegmenter.push(&[0; 960], start)), vec![0]);
+        let frames = resegmenter.push(&[0; 960], start + Duration::from_secs(1));
+        assert_eq!(timestamps(&frames), vec![48_000]);
+    }
+
+    #[test]
+    fn flushes_partial_frame_on_gap() {
+        let start = Instant::now();
+        let mut resegmenter = Resegmenter::new(0);
+
+        assert!(resegmenter.push(&[5; 480], start).is_empty());
+        let frames = resegmenter.push(&[0; 960], start + Duration::from_secs(1));
+
+        assert_eq!(timestamps(&frames), vec![0, 48_000]);
+        assert_eq!(frames[0].samples[..480], [5; 480]);
+        assert_eq!(frames[0].samples[480..], [0; 480]);
+    }
+}
@@ -0,0 +1,136 @@
+/// Follows the sequence numbers of a single RTP stream, similar to RFC 3550, appendix A.1.
+#[derive(Default)]
+pub struct SequenceTracker {
+    // Highest sequence number so far, extended so that it keeps increasing when it wraps around.
+    highest: Option<u64>,
+    // Bit i is set if the packet with sequence number `highest - i` was received.
+    received: u64,
+    // A packet far away from the current sequence announces a restart of the stream if it's
+    // followed by this sequence number.
+    restart_candidate: Option<u16>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SequenceCheck {
+    /// Newer than all packets so far, with [lost] packets missing in between.
+    InOrder {
+        lost: u64,
+    },
+    /// Older than the newest packet, filling a gap.
+    Reordered,
+    Duplicate,
+    /// Too far away from the current sequence, ignored unless the next packet continues from it.
+    Ignored,
+    /// The sender restarted its stream with new sequence numbers, starting with this packet.
+    Restart,
+}
+
+impl SequenceTracker {
+    const MAX_DROPOUT: i64 = 3000;
+    const MAX_MISORDER: i64 = 100;
+    const WINDOW: u64 = u64::BITS as u64;
+
+    pub fn check(&mut self, sequence: u16) -> SequenceCheck {
+        let Some(highest) = self.highest else {
+            self.start(sequence);
+            return SequenceCheck::InOrder { lost: 0 };
+        };
+
+        let delta = sequence.wrapping_sub(highest as u16) as i16 as i64;
+        match delta {
+            0 => SequenceCheck::Duplicate,
+            _ if (1..Self::MAX_DROPOUT).contains(&delta) => {
+                let delta = delta as u64;
+                self.highest = Some(highest + delta);
+                self.received = self.received.checked_shl(delta as u32).unwrap_or(0) | 1;
+                self.restart_candidate = None;
+
+                SequenceCheck::InOrder { lost: delta - 1 }
+            }
+            _ if (-Self::MAX_MISORDER..0).contains(&delta) => {
+                if self.mark_received(sequence) {
+                    SequenceCheck::Reordered
+                } else {
+                    SequenceCheck::Duplicate
+                }
+            }
+            _ if self.restart_candidate == Some(sequence) => {
+                self.start(sequence);
+                SequenceCheck::Restart
+            }
+            _ => {
+                self.restart_candidate = Some(sequence.wrapping_add(1));
+                SequenceCheck::Ignored
+            }
+        }
+    }
+
+    /// Marks an older packet as received, e.g. because it was recovered from redundant data in a
+    /// later packet. Returns false if it was received before.
+    pub fn mark_received(&mut self, sequence: u16) -> bool {
+        let Some(highest) = self.highest else {
+            return false;
+        };
+
+        let age = (highest as u16).wrapping_sub(sequence) as u64;
+        if age >= Self::WINDOW {
+            // We don't remember that far back, so it can't be marked.
+            return true;
+        }
+
+        let bit = 1 << age;
+        let new = self.received & bit == 0;
+        self.received |= bit;
+        new
+    }
+
+    fn start(&mut self, sequence: u16) {
+        self.highest = Some(sequence as u64);
+        self.received = 1;
+        self.restart_candidate = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SequenceCheck, SequenceTracker};
+
+    #[test]
+    fn detects_gaps_across_wraparound() {
+        let mut tracker = SequenceTracker::default();
+
+        assert_eq!(tracker.check(65534), SequenceCheck::InOrder { lost: 0 });
+        assert_eq!(tracker.check(65535), SequenceCheck::InOrder { lost: 0 });
+        assert_eq!(tracker.check(0), SequenceCheck::InOrder { lost: 0 });
+        assert_eq!(tracker.check(3), SequenceCheck::InOrder { lost: 2 });
+    }
+
+    #[test]
+    fn detects_duplicates_and_reordering() {
+        let mut tracker = SequenceTracker::default();
+
+        tracker.check(10);
+        assert_eq!(tracker.check(13), SequenceCheck::InOrder { lost: 2 });
+        assert_eq!(tracker.check(13), SequenceCheck::Duplicate);
+        assert_eq!(tracker.check(11), SequenceCheck::Reordered);
+        assert_eq!(tracker.check(11), SequenceCheck::Duplicate);
+        assert_eq!(tracker.check(10), SequenceCheck::Duplicate);
+
+        assert!(tracker.mark_received(12));
+        assert_eq!(tracker.check(12), SequenceCheck::Duplicate);
+    }
+
+    #[test]
+    fn restarts_after_two_consecutive_packets() {
+        let mut tracker = SequenceTracker::default();
+
+        tracker.check(100);
+        assert_eq!(tracker.check(40000), SequenceCheck::Ignored);
+        assert_eq!(tracker.check(101), SequenceCheck::InOrder { lost: 0 });
+
+        assert_eq!(tracker.check(40000), SequenceCheck::Ignored);
+        assert_eq!(tracker.check(40001), SequenceCheck::Restart);
+        assert_eq!(tracker.check(40002), SequenceCheck::InOrder { lost: 0 });
+        assert_eq!(tracker.check(102), SequenceCheck::Ignored);
+    }
+}
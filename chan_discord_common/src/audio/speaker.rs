@@ -0,0 +1,190 @@
+use std::time::{Duration, Instant};
+
+use twilight_model::id::{marker::UserMarker, Id};
+
+/// How loud a participant currently is, according to the audio levels in their packets or the
+/// decoded audio.
+#[derive(Debug, Clone, Copy)]
+pub struct ParticipantLevel {
+    pub user: Id<UserMarker>,
+    pub ssrc: u32,
+    /// In dBov, as metered by the mixer.
+    pub level_db: f32,
+    /// Whether the sender flagged their recent packets as speech.
+    pub voice: bool,
+}
+
+impl ParticipantLevel {
+    // Participants without speech flags count as speaking above this level.
+    const ACTIVE_LEVEL_DB: f32 = -50.0;
+
+    /// Whether the participant is flagged as speaking or loud enough.
+    pub fn is_active(&self) -> bool {
+        self.voice || self.level_db > Self::ACTIVE_LEVEL_DB
+    }
+}
+
+/// Works out who is currently the dominant speaker in a call.
+///
+/// A participant counts as speaking if their packets are flagged as speech or loud enough. To
+/// avoid flickering between people talking over each other, someone else only takes over after
+/// being clearly louder than the current speaker for a while, and the current speaker is only
+/// cleared after a longer pause.
+pub struct ActiveSpeakerDetector {
+    current: Option<Id<UserMarker>>,
+    // When the current speaker was last heard.
+    last_active: Option<Instant>,
+    // A participant louder than the current speaker, and since when.
+    challenger: Option<(Id<UserMarker>, Instant)>,
+}
+
+impl ActiveSpeakerDetector {
+    // How much louder than the current speaker someone needs to be to take over.
+    const HYSTERESIS_DB: f32 = 6.0;
+    const SWITCH_AFTER: Duration = Duration::from_millis(300);
+    const RELEASE_AFTER: Duration = Duration::from_secs(2);
+
+    pub fn new() -> Self {
+        Self {
+            current: None,
+            last_active: None,
+            challenger: None,
+        }
+    }
+
+    pub fn current(&self) -> Option<Id<UserMarker>> {
+        self.current
+    }
+
+    /// Updates the detector with the current [levels], returning whether the active speaker
+    /// changed.
+    pub fn update(&mut self, levels: &[ParticipantLevel], now: Instant) -> bool {
+        let current_level = levels
+            .iter()
+            .filter(|level| level.is_active())
+            .find(|level| Some(level.user) == self.current)
+            .map(|level| level.level_db);
+        if current_level.is_some() {
+            self.last_active = Some(now);
+        }
+
+        let dominant = levels
+            .iter()
+            .filter(|level| level.is_active())
+            .filter(|level| Some(level.user) != self.current)
+            .max_by(|a, b| a.level_db.total_cmp(&b.level_db))
+            .filter(|level| {
+                current_level.is_none_or(|current| level.level_db > current + Self::HYSTERESIS_DB)
+            });
+
+        match (dominant, self.challenger) {
+            (Some(dominant), Some((challenger, since))) if dominant.user == challenger => {
+                if now.saturating_duration_since(since) >= Self::SWITCH_AFTER {
+                    self.current = Some(dominant.user);
+                    self.last_active = Some(now);
+                    self.challenger = None;
+                    return true;
+                }
+            }
+            (Some(dominant), _) => self.challenger = Some((dominant.user, now)),
+            (None, _) => self.challenger = None,
+        }
+
+        let released = self.last_active.is_some_and(|last_active| {
+            now.saturating_duration_since(last_active) >= Self::RELEASE_AFTER
+        });
+        if self.current.is_some() && released {
+            self.current = None;
+            self.last_active = None;
+            return true;
+        }
+
+        false
+    }
+}
+
+impl Default for ActiveSpeakerDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use twilight_model::id::Id;
+
+    use super::{ActiveSpeakerDetector, ParticipantLevel};
+
+    fn level(user: u64, level_db: f32) -> ParticipantLevel {
+        ParticipantLevel {
+            user: Id::new(user),
+            ssrc: user as u32,
+            level_db,
+            voice: false,
+        }
+    }
+
+    // Feeds the same levels every 100ms for [millis], returning the speaker changes.
+    fn run(
+        detector: &mut ActiveSpeakerDetector,
+        now: &mut Instant,
+        levels: &[ParticipantLevel],
+        millis: u64,
+    ) -> Vec<Option<u64>> {
+        let end = *now + Duration::from_millis(millis);
+        let mut changes = vec![];
+        while *now < end {
+            if detector.update(levels, *now) {
+                changes.push(detector.current().map(|user| user.get()));
+            }
+            *now += Duration::from_millis(100);
+        }
+
+        changes
+    }
+
+    #[test]
+    fn switches_after_sustained_speech() {
+        let mut detector = ActiveSpeakerDetector::new();
+        let mut now = Instant::now();
+
+        // A short noise doesn't make anyone the active speaker.
+        let changes = run(&mut detector, &mut now, &[level(1, -20.0)], 200);
+        assert_eq!(changes, vec![]);
+        let changes = run(&mut detector, &mut now, &[level(1, -80.0)], 1000);
+        assert_eq!(changes, vec![]);
+
+        let changes = run(&mut detector, &mut now, &[level(1, -20.0)], 1000);
+        assert_eq!(changes, vec![Some(1)]);
+    }
+
+    #[test]
+    fn keeps_speaker_when_others_are_not_clearly_louder() {
+        let mut detector = ActiveSpeakerDetector::new();
+        let mut now = Instant::now();
+        run(&mut detector, &mut now, &[level(1, -30.0)], 1000);
+
+        let talking_over = [level(1, -30.0), level(2, -27.0)];
+        let changes = run(&mut detector, &mut now, &talking_over, 2000);
+        assert_eq!(changes, vec![]);
+
+        let louder = [level(1, -30.0), level(2, -20.0)];
+        let changes = run(&mut detector, &mut now, &louder, 1000);
+        assert_eq!(changes, vec![Some(2)]);
+    }
+
+    #[test]
+    fn releases_speaker_after_silence() {
+        let mut detector = ActiveSpeakerDetector::new();
+        let mut now = Instant::now();
+        let mut speech = level(1, -80.0);
+        speech.voice = true;
+        run(&mut detector, &mut now, &[speech], 1000);
+        assert_eq!(detector.current(), Some(Id::new(1)));
+
+        let changes = run(&mut detector, &mut now, &[level(1, -80.0)], 3000);
+        assert_eq!(changes, vec![None]);
+    }
+}
@@ -0,0 +1,113 @@
+/// Energy-based voice activity detection for outgoing audio.
+///
+/// A frame counts as speech if it's noticeably louder than the tracked background noise. To avoid
+/// cutting off the ends of words, frames following speech are still reported as speech for a
+/// short hangover period.
+pub struct VoiceActivityDetector {
+    noise_floor_db: f32,
+    hangover_frames: usize,
+}
+
+impl VoiceActivityDetector {
+    // Frames quieter than this are never speech, no matter how quiet the background is.
+    const MIN_SPEECH_DB: f32 = -55.0;
+    // How much louder than the background noise a frame needs to be to count as speech.
+    const SPEECH_MARGIN_DB: f32 = 9.0;
+    // The noise floor follows quieter input immediately, but only rises slowly (2.5dB per second
+    // with 20ms frames) so that speech doesn't raise it.
+    const NOISE_FLOOR_RISE_DB: f32 = 0.05;
+    // 300ms with 20ms frames.
+    const HANGOVER_FRAMES: usize = 15;
+
+    pub fn new() -> Self {
+        Self {
+            noise_floor_db: Self::MIN_SPEECH_DB - Self::SPEECH_MARGIN_DB,
+            hangover_frames: 0,
+        }
+    }
+
+    /// Processes the next frame, returning whether it should be transmitted as speech.
+    pub fn process(&mut self, frame: &[i16]) -> bool {
+        let level = level_db(frame);
+
+        if level < self.noise_floor_db {
+            self.noise_floor_db = level;
+        } else {
+            self.noise_floor_db += Self::NOISE_FLOOR_RISE_DB;
+        }
+
+        let is_speech =
+            level > Self::MIN_SPEECH_DB && level > self.noise_floor_db + Self::SPEECH_MARGIN_DB;
+        if is_speech {
+            self.hangover_frames = Self::HANGOVER_FRAMES;
+            true
+        } else if self.hangover_frames > 0 {
+            self.hangover_frames -= 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Default for VoiceActivityDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The RMS level of [frame] in dBFS, with digital silence being reported as -96dB.
+pub fn level_db(frame: &[i16]) -> f32 {
+    if frame.is_empty() {
+        return -96.0;
+    }
+
+    let sum_of_squares: f64 = frame.iter().map(|s| (*s as f64).powi(2)).sum();
+    let rms = (sum_of_squares / frame.len() as f64).sqrt() / 32768.0;
+
+    (20.0 * rms.log10()).max(-96.0) as f32
+}
+
+#[cfg(test)]
+mod test {
+    use super::{level_db, VoiceActivityDetector};
+
+    fn tone(amplitude: f32) -> Vec<i16> {
+        (0..960)
+            .map(|i| (amplitude * (i as f32 * 0.06).sin()) as i16)
+            .collect()
+    }
+
+    #[test]
+    fn levels() {
+        assert_eq!(level_db(&[0; 960]), -96.0);
+        assert!((level_db(&[16384; 960]) + 6.02).abs() < 0.01);
+    }
+
+    #[test]
+    fn detects_speech_with_hangover() {
+        let mut vad = VoiceActivityDetector::new();
+        for _ in 0..50 {
+            assert!(!vad.process(&[0; 960]));
+        }
+
+        assert!(vad.process(&tone(8000.0)));
+        for _ in 0..15 {
+            assert!(vad.process(&[0; 960]));
+        }
+        assert!(!vad.process(&[0; 960]));
+    }
+
+    #[test]
+    fn ignores_constant_background_noise() {
+        let mut vad = VoiceActivityDetector::new();
+        let noise = tone(300.0);
+
+        // The noise floor needs to catch up first, after that the noise is no longer speech.
+        let detected = (0..1000).filter(|_| vad.process(&noise)).count();
+        assert!(detected < 1000);
+        assert!(!vad.process(&noise));
+
+        assert!(vad.process(&tone(8000.0)));
+    }
+}
@@ -14,3 +14,8 @@ pub const RTP_PROFILE_TYPE: RtpType = RtpType::Dynamic(0x78);
 pub const MAX_RTP_PACKET_SIZE: usize = 1450;
 pub const MAX_OPUS_PAYLOAD_SIZE: usize =
     MAX_RTP_PACKET_SIZE - RtpPacket::minimum_packet_size() - TAG_SIZE - NONCE_SIZE;
+
+// Discord expects five frames of Opus silence before a client stops transmitting, to avoid
+// unintended interpolation on the receiving side.
+pub const OPUS_SILENCE_FRAME: [u8; 3] = [0xF8, 0xFF, 0xFE];
+pub const SILENCE_FRAMES_BEFORE_PAUSE: usize = 5;
@@ -0,0 +1,212 @@
+use std::time::Duration;
+
+use twilight_model::id::{
+    marker::{ChannelMarker, GuildMarker},
+    Id,
+};
+
+/// Where to call, dialed as `Discord/<guild>/<channel>[,<options>]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Destination {
+    pub guild: Id<GuildMarker>,
+    pub channel: Id<ChannelMarker>,
+    pub options: DialOptions,
+}
+
+/// Options given after the destination. Each is a letter, optionally followed by an argument in
+/// parentheses, and they can be separated by commas: `m,n(Front desk),e(30)`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DialOptions {
+    /// `e(<seconds>)`, `t(<seconds>)` and `i(<seconds>)`, overriding the configured timeouts.
+    pub timeouts: CallTimeouts,
+    /// `m`: Only listen to Discord, the bot is muted there.
+    pub listen_only: bool,
+    /// `d`: Only speak into Discord, the bot is deafened there.
+    pub speak_only: bool,
+    /// `n(<nickname>)`: The nickname of the bot in the guild for the duration of the call.
+    pub nickname: Option<String>,
+    /// `p(<profile>)`: A section of discord.conf whose access lists apply to the call as well.
+    pub profile: Option<String>,
+}
+
+/// Limits after which a call is hung up. A zero duration turns a limit off.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallTimeouts {
+    /// How long the call continues without other (human) participants in the voice channel.
+    pub empty: Option<Duration>,
+    /// How long the call may last after being answered.
+    pub max_duration: Option<Duration>,
+    /// How long the call continues without speech in either direction.
+    pub idle: Option<Duration>,
+}
+
+impl Destination {
+    pub fn parse(str: &str) -> Option<Self> {
+        let (address, options) = match str.split_once(',') {
+            Some((address, options)) => (address, options),
+            None => (str, ""),
+        };
+
+        let (guild, channel) = Self::parse_address(address)?;
+        Some(Self {
+            guild,
+            channel,
+            options: DialOptions::parse(options)?,
+        })
+    }
+
+    /// Parses a voice channel without options, `<guild>/<channel>`.
+    pub fn parse_address(str: &str) -> Option<(Id<GuildMarker>, Id<ChannelMarker>)> {
+        let mut split = str.split('/');
+        let guild = Id::new_checked(split.next()?.trim().parse::<u64>().ok()?)?;
+        let channel = Id::new_checked(split.next()?.trim().parse::<u64>().ok()?)?;
+
+        if split.next().is_some() {
+            // We only want two elements
+            return None;
+        }
+
+        Some((guild, channel))
+    }
+}
+
+impl DialOptions {
+    pub fn parse(mut str: &str) -> Option<Self> {
+        let mut options = Self::default();
+
+        loop {
+            str = str.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+            let mut chars = str.chars();
+            let Some(letter) = chars.next() else {
+                break;
+            };
+            str = chars.as_str();
+
+            let argument = match str.strip_prefix('(') {
+                Some(rest) => {
+                    let (argument, rest) = rest.split_once(')')?;
+                    str = rest;
+                    Some(argument)
+                }
+                None => None,
+            };
+
+            match (letter, argument) {
+                ('e', Some(seconds)) => options.timeouts.empty = Some(parse_seconds(seconds)?),
+                ('t', Some(seconds)) => {
+                    options.timeouts.max_duration = Some(parse_seconds(seconds)?)
+                }
+                ('i', Some(seconds)) => options.timeouts.idle = Some(parse_seconds(seconds)?),
+                ('m', None) => options.listen_only = true,
+                ('d', None) => options.speak_only = true,
+                ('n', Some(nickname)) if !nickname.trim().is_empty() => {
+                    options.nickname = Some(nickname.trim().to_string())
+                }
+                ('p', Some(profile)) if !profile.trim().is_empty() => {
+                    options.profile = Some(profile.trim().to_string())
+                }
+                _ => return None,
+            }
+        }
+
+        Some(options)
+    }
+}
+
+impl CallTimeouts {
+    /// These timeouts, with the ones set in [overrides] replacing them.
+    pub fn with_overrides(self, overrides: &CallTimeouts) -> Self {
+        Self {
+            empty: overrides.empty.or(self.empty),
+            max_duration: overrides.max_duration.or(self.max_duration),
+            idle: overrides.idle.or(self.idle),
+        }
+    }
+}
+
+pub fn parse_seconds(str: &str) -> Option<Duration> {
+    Some(Duration::from_secs(str.trim().parse().ok()?))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use twilight_model::id::Id;
+
+    use super::{CallTimeouts, Destination, DialOptions};
+
+    #[test]
+    fn parses_plain_destination() {
+        let destination = Destination::parse("1234/5678").unwrap();
+        assert_eq!(destination.guild, Id::new(1234));
+        assert_eq!(destination.channel, Id::new(5678));
+        assert_eq!(destination.options, DialOptions::default());
+
+        assert_eq!(Destination::parse("1234"), None);
+        assert_eq!(Destination::parse("1234/5678/9"), None);
+        assert_eq!(Destination::parse("0/5678"), None);
+
+        assert_eq!(
+            Destination::parse_address("1234/5678"),
+            Some((Id::new(1234), Id::new(5678)))
+        );
+        assert_eq!(Destination::parse_address("1234/5678,m"), None);
+    }
+
+    #[test]
+    fn parses_timeouts() {
+        let destination = Destination::parse("1234/5678,e(30),t(3600)i(0)").unwrap();
+        assert_eq!(
+            destination.options.timeouts,
+            CallTimeouts {
+                empty: Some(Duration::from_secs(30)),
+                max_duration: Some(Duration::from_secs(3600)),
+                idle: Some(Duration::ZERO),
+            }
+        );
+
+        assert_eq!(Destination::parse("1234/5678,e"), None);
+        assert_eq!(Destination::parse("1234/5678,e(soon)"), None);
+        assert_eq!(Destination::parse("1234/5678,x(1)"), None);
+    }
+
+    #[test]
+    fn parses_voice_options() {
+        let destination = Destination::parse("1234/5678,m,n(Front desk, Berlin)").unwrap();
+        assert!(destination.options.listen_only);
+        assert!(!destination.options.speak_only);
+        assert_eq!(
+            destination.options.nickname.as_deref(),
+            Some("Front desk, Berlin")
+        );
+
+        let destination = Destination::parse("1234/5678, d").unwrap();
+        assert!(destination.options.speak_only);
+
+        let destination = Destination::parse("1234/5678,p(support)").unwrap();
+        assert_eq!(destination.options.profile.as_deref(), Some("support"));
+
+        assert_eq!(Destination::parse("1234/5678,m(1)"), None);
+        assert_eq!(Destination::parse("1234/5678,n()"), None);
+        assert_eq!(Destination::parse("1234/5678,n(unterminated"), None);
+    }
+
+    #[test]
+    fn dial_options_override_configured_timeouts() {
+        let configured = CallTimeouts {
+            empty: Some(Duration::from_secs(60)),
+            max_duration: None,
+            idle: Some(Duration::from_secs(300)),
+        };
+        let dialed = CallTimeouts {
+            idle: Some(Duration::ZERO),
+            ..Default::default()
+        };
+
+        let timeouts = configured.with_overrides(&dialed);
+        assert_eq!(timeouts.empty, Some(Duration::from_secs(60)));
+        assert_eq!(timeouts.max_duration, None);
+        assert_eq!(timeouts.idle, Some(Duration::ZERO));
+    }
+}
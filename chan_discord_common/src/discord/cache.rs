@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use twilight_cache_inmemory::InMemoryCache;
+use twilight_model::{
+    channel::ChannelType,
+    guild::Permissions,
+    id::{
+        marker::{ChannelMarker, GuildMarker, UserMarker},
+        Id,
+    },
+};
+
+use crate::{
+    discord::permissions::{check_join, MemberPermissions},
+    error::ChanRes,
+};
+
+/// Read access to what the global gateway told us about guilds, channels and the users in them.
+///
+/// Lookups don't block on the gateway, but the cache is only as current as the events received so
+/// far.
+#[derive(Clone)]
+pub struct DiscordCache {
+    cache: Arc<InMemoryCache>,
+    bot_user: Id<UserMarker>,
+}
+
+impl DiscordCache {
+    pub(crate) fn new(cache: Arc<InMemoryCache>, bot_user: Id<UserMarker>) -> Self {
+        Self { cache, bot_user }
+    }
+
+    pub fn guild_name(&self, guild: Id<GuildMarker>) -> Option<String> {
+        Some(self.cache.guild(guild)?.name().to_owned())
+    }
+
+    pub fn channel_name(&self, channel: Id<ChannelMarker>) -> Option<String> {
+        self.cache.channel(channel)?.name.clone()
+    }
+
+    /// Whether [channel] is a stage channel, where only speakers can be heard.
+    pub fn is_stage_channel(&self, channel: Id<ChannelMarker>) -> bool {
+        self.cache
+            .channel(channel)
+            .is_some_and(|channel| channel.kind == ChannelType::GuildStageVoice)
+    }
+
+    /// Whether our bot is suppressed in the voice [channel] of [guild], i.e. it's in the audience
+    /// of a stage. `None` if the bot isn't known to be in that channel.
+    pub fn is_suppressed(
+        &self,
+        guild: Id<GuildMarker>,
+        channel: Id<ChannelMarker>,
+    ) -> Option<bool> {
+        let state = self.cache.voice_state(self.bot_user, guild)?;
+        (state.channel_id() == channel).then(|| state.suppress())
+    }
+
+    /// The permissions of our bot in [channel], computed from the roles of the bot and the
+    /// overwrites of the channel. `None` if we don't know the channel or the bot's roles.
+    pub fn permissions(&self, channel: Id<ChannelMarker>) -> Option<Permissions> {
+        let channel = self.cache.channel(channel)?;
+        let guild = channel.guild_id?;
+        let member = self.cache.member(guild, self.bot_user)?;
+        let roles = member
+            .roles()
+            .iter()
+            .map(|role| Some((*role, self.cache.role(*role)?.permissions)))
+            .collect::<Option<Vec<_>>>()?;
+
+        let member = MemberPermissions {
+            guild,
+            user: self.bot_user,
+            everyone: self.cache.role(guild.cast())?.permissions,
+            roles: &roles,
+            is_owner: self.cache.guild(guild)?.owner_id() == self.bot_user,
+        };
+        Some(member.in_channel(channel.permission_overwrites.as_deref().unwrap_or_default()))
+    }
+
+    /// Checks that our bot may join the voice [channel] and that there's room for it, and that it
+    /// may speak there if it [speaks]. Whatever we don't know about yet doesn't stop the bot from
+    /// trying.
+    pub fn check_join(&self, channel: Id<ChannelMarker>, speaks: bool) -> ChanRes<()> {
+        let Some(permissions) = self.permissions(channel) else {
+            return Ok(());
+        };
+        let user_limit = self.cache.channel(channel).and_then(|c| c.user_limit);
+
+        check_join(
+            permissions,
+            speaks,
+            self.is_stage_channel(channel),
+            user_limit,
+            self.voice_channel_users(channel).len(),
+        )
+    }
+
+    /// The nickname of [user] in [guild], if they have one.
+    pub fn nickname(&self, guild: Id<GuildMarker>, user: Id<UserMarker>) -> Option<String> {
+        Some(self.cache.member(guild, user)?.nick()?.to_owned())
+    }
+
+    /// The name [user] is shown with in [guild]: their nickname there, or their global display
+    /// name, or their user name.
+    pub fn display_name(&self, guild: Id<GuildMarker>, user: Id<UserMarker>) -> Option<String> {
+        if let Some(nick) = self
+            .cache
+            .member(guild, user)
+            .and_then(|member| member.nick().map(str::to_owned))
+        {
+            return Some(nick);
+        }
+
+        let user = self.cache.user(user)?;
+        Some(
+            user.global_name
+                .clone()
+                .unwrap_or_else(|| user.name.clone()),
+        )
+    }
+
+    /// Whether [user] is a bot account. Users we don't know are assumed to be human.
+    pub fn is_bot(&self, user: Id<UserMarker>) -> bool {
+        self.cache.user(user).is_some_and(|user| user.bot)
+    }
+
+    /// The users currently connected to the voice [channel], not counting our bot.
+    pub fn voice_channel_users(&self, channel: Id<ChannelMarker>) -> Vec<Id<UserMarker>> {
+        let Some(states) = self.cache.voice_channel_states(channel) else {
+            return vec![];
+        };
+
+        let mut users: Vec<_> = states
+            .map(|state| state.user_id())
+            .filter(|user| *user != self.bot_user)
+            .collect();
+        users.sort();
+        users
+    }
+}
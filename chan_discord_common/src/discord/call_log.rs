@@ -0,0 +1,237 @@
+use std::time::Duration;
+
+use twilight_model::{
+    channel::message::embed::{Embed, EmbedField, EmbedFooter},
+    id::{
+        marker::{ChannelMarker, UserMarker},
+        Id,
+    },
+};
+
+use crate::stats::CallStats;
+
+/// A message about a call for the log channel of a guild, so that moderators can see who bridged
+/// phone calls into their voice channels.
+pub struct CallLogEntry<'a> {
+    pub event: CallLogEvent<'a>,
+    /// The caller id of the calling side, if Asterisk knows it.
+    pub caller: Option<&'a str>,
+    pub channel: Id<ChannelMarker>,
+    /// The name of the Asterisk channel, to find the call in Asterisk logs.
+    pub asterisk_channel: &'a str,
+}
+
+pub enum CallLogEvent<'a> {
+    Started,
+    Connected,
+    Ended {
+        /// How long the call has been answered, `None` if it never was.
+        duration: Option<Duration>,
+        /// Everyone who has been in the voice channel during the call.
+        participants: &'a [Id<UserMarker>],
+        stats: &'a CallStats,
+    },
+}
+
+impl CallLogEntry<'_> {
+    const COLOR_STARTED: u32 = 0x5865f2;
+    const COLOR_CONNECTED: u32 = 0x57f287;
+    const COLOR_ENDED: u32 = 0x99aab5;
+
+    pub fn embed(&self) -> Embed {
+        let (title, color) = match self.event {
+            CallLogEvent::Started => ("Call started", Self::COLOR_STARTED),
+            CallLogEvent::Connected => ("Call connected", Self::COLOR_CONNECTED),
+            CallLogEvent::Ended { .. } => ("Call ended", Self::COLOR_ENDED),
+        };
+
+        // Mentions in embeds show names without notifying anyone.
+        let mut fields = vec![
+            field("Caller", self.caller.unwrap_or("Unknown"), true),
+            field("Voice channel", &format!("<#{}>", self.channel), true),
+        ];
+        if let CallLogEvent::Ended {
+            duration,
+            participants,
+            stats,
+        } = self.event
+        {
+            let duration = duration.map_or("Not answered".to_string(), format_duration);
+            let participants = match participants {
+                [] => "Nobody".to_string(),
+                _ => mentions(participants),
+            };
+
+            fields.push(field("Duration", &duration, true));
+            fields.push(field("Participants", &participants, false));
+            fields.push(field("Quality", &quality(stats), false));
+        }
+
+        Embed {
+            author: None,
+            color: Some(color),
+            description: None,
+            fields,
+            footer: Some(EmbedFooter {
+                icon_url: None,
+                proxy_icon_url: None,
+                text: self.asterisk_channel.to_string(),
+            }),
+            image: None,
+            kind: "rich".to_string(),
+            provider: None,
+            thumbnail: None,
+            timestamp: None,
+            title: Some(title.to_string()),
+            url: None,
+            video: None,
+        }
+    }
+}
+
+fn field(name: &str, value: &str, inline: bool) -> EmbedField {
+    EmbedField {
+        inline,
+        name: name.to_string(),
+        value: value.to_string(),
+    }
+}
+
+/// Mentions of [users], leaving out as many as needed to fit into an embed field.
+fn mentions(users: &[Id<UserMarker>]) -> String {
+    // The limit of Discord for the value of an embed field.
+    const MAX_LENGTH: usize = 1024;
+
+    let mentions: Vec<String> = users.iter().map(|user| format!("<@{user}>")).collect();
+    let mut shown = mentions.len();
+    loop {
+        let hidden = mentions.len() - shown;
+        let mut text = mentions[..shown].join(", ");
+        if hidden > 0 {
+            text += &format!(" and {hidden} more");
+        }
+
+        if text.len() <= MAX_LENGTH || shown == 1 {
+            return text;
+        }
+        shown -= 1;
+    }
+}
+
+/// `m:ss`, or `h:mm:ss` for calls of an hour or more.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    match hours {
+        0 => format!("{minutes}:{seconds:02}"),
+        _ => format!("{hours}:{minutes:02}:{seconds:02}"),
+    }
+}
+
+/// The packets sent and received in total, with the loss and worst jitter among participants.
+fn quality(stats: &CallStats) -> String {
+    let received = stats.participants.iter().map(|p| &p.received);
+    let packets = received.clone().map(|r| r.packets).sum::<u64>();
+    let lost = received.clone().map(|r| r.lost).sum::<u64>();
+    let late = received.clone().map(|r| r.late).sum::<u64>();
+    let max_jitter = received.map(|r| r.jitter).max().unwrap_or_default();
+    let loss = match packets + lost {
+        0 => 0.0,
+        expected => lost as f64 * 100.0 / expected as f64,
+    };
+
+    format!(
+        "{} packets sent, {packets} received, {lost} lost ({loss:.1}%), {late} late, \
+        max. jitter {} ms",
+        stats.sent.packets,
+        max_jitter.as_millis()
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use twilight_model::id::Id;
+
+    use crate::stats::{CallStats, ParticipantStats, ReceiveStats, SendStats};
+
+    use super::{format_duration, mentions, CallLogEntry, CallLogEvent};
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(format_duration(Duration::from_secs(0)), "0:00");
+        assert_eq!(format_duration(Duration::from_millis(62_900)), "1:02");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1:02:03");
+    }
+
+    #[test]
+    fn describes_ended_calls() {
+        let stats = CallStats {
+            sent: SendStats {
+                packets: 500,
+                bytes: 40_000,
+            },
+            participants: vec![ParticipantStats {
+                user: Id::new(7),
+                ssrc: 1,
+                received: ReceiveStats {
+                    packets: 990,
+                    lost: 10,
+                    late: 2,
+                    decode_errors: 0,
+                    jitter: Duration::from_millis(12),
+                },
+            }],
+        };
+        let participants = [Id::new(7), Id::new(8)];
+        let entry = CallLogEntry {
+            event: CallLogEvent::Ended {
+                duration: Some(Duration::from_secs(75)),
+                participants: &participants,
+                stats: &stats,
+            },
+            caller: Some("Alice <1234>"),
+            channel: Id::new(42),
+            asterisk_channel: "Discord/1/42-00000001",
+        };
+
+        let embed = entry.embed();
+        assert_eq!(embed.title.as_deref(), Some("Call ended"));
+        let fields = embed
+            .fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.value.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            [
+                ("Caller", "Alice <1234>"),
+                ("Voice channel", "<#42>"),
+                ("Duration", "1:15"),
+                ("Participants", "<@7>, <@8>"),
+                (
+                    "Quality",
+                    "500 packets sent, 990 received, 10 lost (1.0%), 2 late, max. jitter 12 ms"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn fits_many_participants_into_a_field() {
+        let users: Vec<_> = (0..100)
+            .map(|i| Id::new(100_000_000_000_000_000 + i))
+            .collect();
+        let text = mentions(&users);
+        assert!(text.len() <= 1024, "{} characters", text.len());
+
+        assert!(text.starts_with("<@100000000000000000>, <@100000000000000001>, "));
+        assert!(text.ends_with("<@100000000000000043> and 56 more"));
+
+        assert_eq!(
+            mentions(&users[..2]),
+            "<@100000000000000000>, <@100000000000000001>"
+        );
+    }
+}
@@ -11,27 +11,38 @@ use twilight_http::Client;
 use twilight_model::id::marker::{GuildMarker, UserMarker};
 use twilight_model::id::Id;
 
+use crate::discord::cache::DiscordCache;
+use crate::discord::presence::{Presence, PresenceTemplates};
+use crate::discord::rest::RestClient;
 use crate::error::DiscordError;
 
+pub mod cache;
+pub mod call_log;
 pub mod crypto;
+pub mod permissions;
+pub mod presence;
+pub mod rest;
 pub mod rtp;
 mod voice_gateway;
 pub mod voice_task;
 
 struct DiscordInner {
-    cache: InMemoryCache,
+    cache: Arc<InMemoryCache>,
+    http: Arc<Client>,
     sender: MessageSender,
     user: Id<UserMarker>,
     channels: Mutex<HashMap<Id<GuildMarker>, mpsc::Sender<Event>>>,
+    presence: Presence,
 }
 
+#[derive(Clone)]
 pub struct Discord {
     inner: Arc<DiscordInner>,
     cancel: CancellationToken,
 }
 
 impl Discord {
-    pub async fn start(token: String) -> Result<Self, DiscordError> {
+    pub async fn start(token: String, presence: PresenceTemplates) -> Result<Self, DiscordError> {
         let client = Client::new(token.clone());
         let bot_user = client
             .current_user()
@@ -46,21 +57,34 @@ impl Discord {
         );
         let bot_user = bot_user.id;
 
+        // Guilds, channels and voice states give calls their names and participants. Members of
+        // voice channels come with their voice states, which also covers their nicknames. Roles
+        // and our own member tell us what the bot may do in a channel.
         let cache = InMemoryCache::builder()
-            .resource_types(ResourceType::MESSAGE)
+            .resource_types(
+                ResourceType::MESSAGE
+                    | ResourceType::GUILD
+                    | ResourceType::CHANNEL
+                    | ResourceType::VOICE_STATE
+                    | ResourceType::MEMBER
+                    | ResourceType::ROLE
+                    | ResourceType::USER,
+            )
             .build();
         let mut shard = Shard::new(
             ShardId::ONE,
             token,
-            Intents::GUILD_MESSAGES | Intents::GUILD_VOICE_STATES,
+            Intents::GUILDS | Intents::GUILD_MESSAGES | Intents::GUILD_VOICE_STATES,
         );
 
         let token = CancellationToken::new();
         let inner = Arc::new(DiscordInner {
-            cache,
+            cache: Arc::new(cache),
+            http: Arc::new(client),
             sender: shard.sender(),
             user: bot_user,
             channels: Default::default(),
+            presence: Presence::new(shard.sender(), presence),
         });
         {
             let token = token.clone();
@@ -100,6 +124,18 @@ impl Discord {
         self.inner.sender.clone()
     }
 
+    pub fn presence(&self) -> Presence {
+        self.inner.presence.clone()
+    }
+
+    pub fn cache(&self) -> DiscordCache {
+        DiscordCache::new(self.inner.cache.clone(), self.inner.user)
+    }
+
+    pub fn rest(&self) -> RestClient {
+        RestClient::new(self.inner.http.clone())
+    }
+
     /// Returns a channel receiving events on the [server] id if no other channel is listening on
     /// that server yet.
     pub async fn exclusive_server_events(
@@ -135,6 +171,10 @@ impl DiscordInner {
         trace!("Event on global gateway: {event:?}");
 
         self.cache.update(&event);
+        if let Event::Ready(_) = event {
+            // The presence doesn't survive reconnecting with a new session.
+            self.presence.resend();
+        }
         if let Some(guild) = event.guild_id() {
             let mut lock = self.channels.lock().await;
             {
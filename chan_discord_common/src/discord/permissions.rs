@@ -0,0 +1,233 @@
+use twilight_model::{
+    channel::permission_overwrite::{PermissionOverwrite, PermissionOverwriteType},
+    guild::Permissions,
+    id::{
+        marker::{GuildMarker, RoleMarker, UserMarker},
+        Id,
+    },
+};
+
+use crate::error::{ChanRes, DiscordError};
+
+/// What we know about a member of a guild to compute their permissions in a channel.
+pub struct MemberPermissions<'a> {
+    pub guild: Id<GuildMarker>,
+    pub user: Id<UserMarker>,
+    /// The permissions of the `@everyone` role, which has the id of the guild.
+    pub everyone: Permissions,
+    /// The roles of the member, along with their permissions.
+    pub roles: &'a [(Id<RoleMarker>, Permissions)],
+    pub is_owner: bool,
+}
+
+impl MemberPermissions<'_> {
+    /// The permissions of the member in a channel with [overwrites], following
+    /// https://discord.com/developers/docs/topics/permissions#permission-overwrites
+    pub fn in_channel(&self, overwrites: &[PermissionOverwrite]) -> Permissions {
+        let base = self
+            .roles
+            .iter()
+            .fold(self.everyone, |permissions, (_, role)| permissions | *role);
+        if self.is_owner || base.contains(Permissions::ADMINISTRATOR) {
+            return Permissions::all();
+        }
+
+        let everyone = self.guild.cast::<RoleMarker>();
+        let apply = |permissions: Permissions, (allow, deny): (Permissions, Permissions)| {
+            (permissions - deny) | allow
+        };
+
+        // The overwrite of @everyone applies first, then those of all roles of the member
+        // together and finally the one of the member.
+        let mut permissions = base;
+        if let Some(overwrite) = overwrites
+            .iter()
+            .find(|o| o.kind == PermissionOverwriteType::Role && o.id == everyone.cast())
+        {
+            permissions = apply(permissions, (overwrite.allow, overwrite.deny));
+        }
+
+        let roles = overwrites
+            .iter()
+            .filter(|o| {
+                o.kind == PermissionOverwriteType::Role
+                    && self.roles.iter().any(|(role, _)| o.id == role.cast())
+            })
+            .fold(
+                (Permissions::empty(), Permissions::empty()),
+                |(allow, deny), o| (allow | o.allow, deny | o.deny),
+            );
+        permissions = apply(permissions, roles);
+
+        if let Some(overwrite) = overwrites
+            .iter()
+            .find(|o| o.kind == PermissionOverwriteType::Member && o.id == self.user.cast())
+        {
+            permissions = apply(permissions, (overwrite.allow, overwrite.deny));
+        }
+
+        permissions
+    }
+}
+
+/// Checks that a member with [permissions] can join a voice channel where [users] others are
+/// connected already, as Discord silently ignores joins it doesn't allow.
+///
+/// The permission to speak is only needed if the member [speaks], and not for joining the
+/// audience of a stage. A [user_limit] of zero means there is none, and members who may move
+/// others can join full channels.
+pub fn check_join(
+    permissions: Permissions,
+    speaks: bool,
+    is_stage: bool,
+    user_limit: Option<u32>,
+    users: usize,
+) -> ChanRes<()> {
+    let mut required = Permissions::VIEW_CHANNEL | Permissions::CONNECT;
+    if speaks && !is_stage {
+        required |= Permissions::SPEAK;
+    }
+    if !permissions.contains(required) {
+        return Err(DiscordError::MissingPermissions {
+            missing: required - permissions,
+        });
+    }
+
+    let limit = user_limit.unwrap_or(0) as usize;
+    if limit > 0 && users >= limit && !permissions.contains(Permissions::MOVE_MEMBERS) {
+        return Err(DiscordError::ChannelFull);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use twilight_model::{
+        channel::permission_overwrite::{PermissionOverwrite, PermissionOverwriteType},
+        guild::Permissions,
+        id::Id,
+    };
+
+    use crate::error::DiscordError;
+
+    use super::{check_join, MemberPermissions};
+
+    fn overwrite(
+        id: u64,
+        kind: PermissionOverwriteType,
+        allow: Permissions,
+        deny: Permissions,
+    ) -> PermissionOverwrite {
+        PermissionOverwrite {
+            allow,
+            deny,
+            id: Id::new(id),
+            kind,
+        }
+    }
+
+    #[test]
+    fn applies_overwrites_in_order() {
+        let roles = [(Id::new(10), Permissions::SPEAK)];
+        let member = MemberPermissions {
+            guild: Id::new(1),
+            user: Id::new(100),
+            everyone: Permissions::CONNECT | Permissions::SPEAK,
+            roles: &roles,
+            is_owner: false,
+        };
+
+        assert_eq!(
+            member.in_channel(&[]),
+            Permissions::CONNECT | Permissions::SPEAK
+        );
+
+        // @everyone can't connect, but the role of the member may.
+        let overwrites = [
+            overwrite(
+                1,
+                PermissionOverwriteType::Role,
+                Permissions::empty(),
+                Permissions::CONNECT | Permissions::SPEAK,
+            ),
+            overwrite(
+                10,
+                PermissionOverwriteType::Role,
+                Permissions::CONNECT,
+                Permissions::empty(),
+            ),
+        ];
+        assert_eq!(member.in_channel(&overwrites), Permissions::CONNECT);
+
+        // The member's own overwrite wins.
+        let overwrites = [
+            overwrite(
+                10,
+                PermissionOverwriteType::Role,
+                Permissions::MUTE_MEMBERS,
+                Permissions::empty(),
+            ),
+            overwrite(
+                100,
+                PermissionOverwriteType::Member,
+                Permissions::empty(),
+                Permissions::MUTE_MEMBERS | Permissions::SPEAK,
+            ),
+        ];
+        assert_eq!(member.in_channel(&overwrites), Permissions::CONNECT);
+    }
+
+    #[test]
+    fn administrators_and_owners_have_all_permissions() {
+        let roles = [(Id::new(10), Permissions::ADMINISTRATOR)];
+        let deny_all = [overwrite(
+            1,
+            PermissionOverwriteType::Role,
+            Permissions::empty(),
+            Permissions::all(),
+        )];
+
+        let admin = MemberPermissions {
+            guild: Id::new(1),
+            user: Id::new(100),
+            everyone: Permissions::empty(),
+            roles: &roles,
+            is_owner: false,
+        };
+        assert_eq!(admin.in_channel(&deny_all), Permissions::all());
+
+        let owner = MemberPermissions {
+            roles: &[],
+            is_owner: true,
+            ..admin
+        };
+        assert_eq!(owner.in_channel(&deny_all), Permissions::all());
+    }
+
+    #[test]
+    fn checks_permissions_and_user_limit_before_joining() {
+        let member = Permissions::VIEW_CHANNEL | Permissions::CONNECT | Permissions::SPEAK;
+        assert!(check_join(member, true, false, None, 10).is_ok());
+        assert!(check_join(member, true, false, Some(0), 10).is_ok());
+        assert!(check_join(member, true, false, Some(5), 4).is_ok());
+        assert!(matches!(
+            check_join(member, true, false, Some(5), 5),
+            Err(DiscordError::ChannelFull)
+        ));
+        assert!(check_join(member | Permissions::MOVE_MEMBERS, true, false, Some(5), 5).is_ok());
+
+        let listener = Permissions::VIEW_CHANNEL | Permissions::CONNECT;
+        assert!(check_join(listener, true, true, None, 0).is_ok());
+        assert!(matches!(
+            check_join(listener, true, false, None, 0),
+            Err(DiscordError::MissingPermissions { missing }) if missing == Permissions::SPEAK
+        ));
+        // Listen-only calls never speak.
+        assert!(check_join(listener, false, false, None, 0).is_ok());
+        assert!(matches!(
+            check_join(Permissions::VIEW_CHANNEL, false, false, None, 0),
+            Err(DiscordError::MissingPermissions { missing }) if missing == Permissions::CONNECT
+        ));
+    }
+}
@@ -0,0 +1,232 @@
+use std::sync::{Arc, Mutex};
+
+use log::{trace, warn};
+use twilight_gateway::MessageSender;
+use twilight_model::gateway::{
+    payload::outgoing::{update_presence::UpdatePresencePayload, UpdatePresence},
+    presence::{Activity, ActivityType, Status},
+    OpCode,
+};
+
+/// The activity of the bot, depending on how many calls are connected. `{count}` is replaced with
+/// the number of calls, `{guild}` and `{channel}` with the names of the voice channel of a single
+/// call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PresenceTemplates {
+    pub call: String,
+    pub calls: String,
+    /// Without any calls, where an empty template shows no activity at all.
+    pub idle: String,
+}
+
+/// Where a connected call is, for the presence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallPlace {
+    pub guild: String,
+    pub channel: String,
+}
+
+/// Shows the calls that are currently connected in the presence of the bot, and the bot as idle
+/// without them.
+#[derive(Clone)]
+pub struct Presence {
+    state: Arc<Mutex<PresenceState>>,
+    sender: MessageSender,
+}
+
+struct PresenceState {
+    templates: PresenceTemplates,
+    calls: Vec<(u64, CallPlace)>,
+    next_call: u64,
+    // The presence we sent last, to not send it again.
+    current: Option<(Status, Option<String>)>,
+}
+
+/// A call counted in the presence until this is dropped.
+pub struct PresenceCall {
+    id: u64,
+    presence: Presence,
+}
+
+impl Default for PresenceTemplates {
+    fn default() -> Self {
+        Self {
+            call: "On call in #{channel}".to_string(),
+            calls: "In {count} calls".to_string(),
+            idle: String::new(),
+        }
+    }
+}
+
+impl PresenceTemplates {
+    /// The status and the text of the activity with [calls] connected.
+    pub fn render(&self, calls: &[CallPlace]) -> (Status, Option<String>) {
+        let (status, template) = match calls {
+            [] => (Status::Idle, &self.idle),
+            [_] => (Status::Online, &self.call),
+            _ => (Status::Online, &self.calls),
+        };
+
+        let mut text = template.replace("{count}", &calls.len().to_string());
+        if let [call] = calls {
+            text = text
+                .replace("{guild}", &call.guild)
+                .replace("{channel}", &call.channel);
+        }
+
+        (status, Some(text).filter(|text| !text.is_empty()))
+    }
+}
+
+impl Presence {
+    /// Starts out idle, as there are no calls yet.
+    pub fn new(sender: MessageSender, templates: PresenceTemplates) -> Self {
+        let presence = Self {
+            state: Arc::new(Mutex::new(PresenceState {
+                templates,
+                calls: Vec::new(),
+                next_call: 0,
+                current: None,
+            })),
+            sender,
+        };
+        presence.update(|_| {});
+
+        presence
+    }
+
+    /// Counts a call connected to the voice channel at [place].
+    pub fn add_call(&self, place: CallPlace) -> PresenceCall {
+        let mut id = 0;
+        self.update(|state| {
+            id = state.next_call;
+            state.next_call += 1;
+            state.calls.push((id, place));
+        });
+
+        PresenceCall {
+            id,
+            presence: self.clone(),
+        }
+    }
+
+    /// Sends the presence again, as new gateway sessions start without one.
+    pub fn resend(&self) {
+        self.update(|state| state.current = None);
+    }
+
+    /// Changes the calls with [change] and sends the resulting presence if it's different.
+    fn update(&self, change: impl FnOnce(&mut PresenceState)) {
+        let mut state = self.state.lock().unwrap();
+        change(&mut state);
+
+        let calls = state.calls.iter().map(|(_, place)| place.clone());
+        let presence = state.templates.render(&calls.collect::<Vec<_>>());
+        if state.current.as_ref() == Some(&presence) {
+            return;
+        }
+
+        trace!("Presence is now {presence:?}");
+        let (status, text) = presence.clone();
+        let command = UpdatePresence {
+            d: UpdatePresencePayload {
+                activities: text.into_iter().map(custom_activity).collect(),
+                afk: status == Status::Idle,
+                since: None,
+                status,
+            },
+            op: OpCode::PresenceUpdate,
+        };
+        match self.sender.command(&command) {
+            Ok(()) => state.current = Some(presence),
+            Err(e) => warn!("Could not update presence: {e}"),
+        }
+    }
+}
+
+impl PresenceCall {
+    /// Follows the call into the voice channel at [place].
+    pub fn moved(&self, place: CallPlace) {
+        self.presence.update(|state| {
+            if let Some((_, call)) = state.calls.iter_mut().find(|(id, _)| *id == self.id) {
+                *call = place;
+            }
+        });
+    }
+}
+
+impl Drop for PresenceCall {
+    fn drop(&mut self) {
+        self.presence
+            .update(|state| state.calls.retain(|(id, _)| *id != self.id));
+    }
+}
+
+/// A custom status, which bots set through the state of the activity.
+fn custom_activity(text: String) -> Activity {
+    Activity {
+        application_id: None,
+        assets: None,
+        buttons: Vec::new(),
+        created_at: None,
+        details: None,
+        emoji: None,
+        flags: None,
+        id: None,
+        instance: None,
+        kind: ActivityType::Custom,
+        name: "Custom Status".to_string(),
+        party: None,
+        secrets: None,
+        state: Some(text),
+        timestamps: None,
+        url: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use twilight_model::gateway::presence::Status;
+
+    use super::{CallPlace, PresenceTemplates};
+
+    fn place(channel: &str) -> CallPlace {
+        CallPlace {
+            guild: "Office".to_string(),
+            channel: channel.to_string(),
+        }
+    }
+
+    #[test]
+    fn renders_templates_by_number_of_calls() {
+        let templates = PresenceTemplates::default();
+        assert_eq!(templates.render(&[]), (Status::Idle, None));
+        assert_eq!(
+            templates.render(&[place("general")]),
+            (Status::Online, Some("On call in #general".to_string()))
+        );
+        assert_eq!(
+            templates.render(&[place("general"), place("lobby")]),
+            (Status::Online, Some("In 2 calls".to_string()))
+        );
+
+        let templates = PresenceTemplates {
+            call: "{guild}: {channel} ({count})".to_string(),
+            calls: "{channel}".to_string(),
+            idle: "Waiting for calls".to_string(),
+        };
+        assert_eq!(
+            templates.render(&[]),
+            (Status::Idle, Some("Waiting for calls".to_string()))
+        );
+        assert_eq!(
+            templates.render(&[place("general")]),
+            (Status::Online, Some("Office: general (1)".to_string()))
+        );
+        // Only single calls have a voice channel.
+        assert_eq!(
+            templates.render(&[place("general"), place("lobby")]),
+            (Status::Online, Some("{channel}".to_string()))
+        );
+    }
+}
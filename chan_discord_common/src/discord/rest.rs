@@ -0,0 +1,150 @@
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use twilight_http::{request::Request, response::marker::EmptyBody, routing::Route, Client};
+use twilight_model::{
+    channel::message::embed::Embed,
+    id::{
+        marker::{ChannelMarker, GuildMarker},
+        Id,
+    },
+    util::Timestamp,
+};
+
+use crate::error::DiscordError;
+
+/// Changes made through the HTTP API while a call is running.
+#[derive(Clone)]
+pub struct RestClient {
+    http: Arc<Client>,
+}
+
+impl RestClient {
+    pub(crate) fn new(http: Arc<Client>) -> Self {
+        Self { http }
+    }
+
+    /// Posts [content] into [channel]. Voice channels have a text chat of their own, so this
+    /// works for them as well.
+    pub async fn post_message(
+        &self,
+        channel: Id<ChannelMarker>,
+        content: &str,
+    ) -> Result<(), DiscordError> {
+        self.http
+            .create_message(channel)
+            .content(content)
+            .map_err(|e| DiscordError::InternalError { source: e.into() })?
+            .await
+            .map_err(|e| DiscordError::InternalError { source: e.into() })?;
+
+        Ok(())
+    }
+
+    /// Posts [embed] into [channel].
+    pub async fn post_embed(
+        &self,
+        channel: Id<ChannelMarker>,
+        embed: &Embed,
+    ) -> Result<(), DiscordError> {
+        self.http
+            .create_message(channel)
+            .embeds(std::slice::from_ref(embed))
+            .map_err(|e| DiscordError::InternalError { source: e.into() })?
+            .await
+            .map_err(|e| DiscordError::InternalError { source: e.into() })?;
+
+        Ok(())
+    }
+
+    /// Changes the nickname of the bot in [guild], or resets it to the user name with `None`.
+    pub async fn set_nickname(
+        &self,
+        guild: Id<GuildMarker>,
+        nickname: Option<&str>,
+    ) -> Result<(), DiscordError> {
+        self.http
+            .update_current_member(guild)
+            .nick(nickname)
+            .map_err(|e| DiscordError::InternalError { source: e.into() })?
+            .await
+            .map_err(|e| DiscordError::InternalError { source: e.into() })?;
+
+        Ok(())
+    }
+
+    /// Makes the bot a speaker on the stage [channel], which needs the "Mute Members" permission.
+    pub async fn become_speaker(
+        &self,
+        guild: Id<GuildMarker>,
+        channel: Id<ChannelMarker>,
+    ) -> Result<(), DiscordError> {
+        self.update_voice_state(
+            guild,
+            &VoiceStateFields {
+                channel_id: channel,
+                suppress: Some(false),
+                request_to_speak_timestamp: None,
+            },
+        )
+        .await
+    }
+
+    /// Raises the hand of the bot on the stage [channel], so that a moderator can invite it to
+    /// speak. This needs the "Request to Speak" permission.
+    pub async fn request_to_speak(
+        &self,
+        guild: Id<GuildMarker>,
+        channel: Id<ChannelMarker>,
+    ) -> Result<(), DiscordError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| DiscordError::InternalError { source: e.into() })?;
+        let now = Timestamp::from_secs(now.as_secs() as i64)
+            .map_err(|e| DiscordError::InternalError { source: e.into() })?;
+
+        self.update_voice_state(
+            guild,
+            &VoiceStateFields {
+                channel_id: channel,
+                suppress: None,
+                request_to_speak_timestamp: Some(now.iso_8601().to_string()),
+            },
+        )
+        .await
+    }
+
+    // Built by hand to send exactly the fields we set, `suppress: false` included.
+    async fn update_voice_state(
+        &self,
+        guild: Id<GuildMarker>,
+        fields: &VoiceStateFields,
+    ) -> Result<(), DiscordError> {
+        let request = Request::builder(&Route::UpdateCurrentUserVoiceState {
+            guild_id: guild.get(),
+        })
+        .json(fields)
+        .build()
+        .map_err(|e| DiscordError::InternalError { source: e.into() })?;
+
+        self.http
+            .request::<EmptyBody>(request)
+            .await
+            .map_err(|e| DiscordError::InternalError { source: e.into() })?;
+
+        Ok(())
+    }
+}
+
+/// The body of `PATCH /guilds/{guild}/voice-states/@me`.
+#[derive(Serialize)]
+struct VoiceStateFields {
+    channel_id: Id<ChannelMarker>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suppress: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_to_speak_timestamp: Option<String>,
+}
@@ -4,14 +4,20 @@ use discortp::demux::{demux_mut, DemuxedMut};
 use discortp::discord::{IpDiscoveryPacket, IpDiscoveryType, MutableIpDiscoveryPacket};
 use discortp::rtp::MutableRtpPacket;
 use discortp::{MutablePacket, Packet};
-use log::debug;
+use log::{debug, warn};
 use rand::{thread_rng, RngCore};
-use std::net::IpAddr;
+use std::fs::File;
+use std::io::BufWriter;
+use std::net::{IpAddr, SocketAddr};
 use std::ops::Range;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::SystemTime;
 use tokio::net::{ToSocketAddrs, UdpSocket};
 
 use crate::constants::{RTP_PROFILE_TYPE, RTP_VERSION};
+use crate::stats::SendCounters;
+use crate::utils::pcap::PcapWriter;
 
 use super::crypto::{EncryptionMode, VoiceDecryption, VoiceEncryption};
 
@@ -23,6 +29,14 @@ pub struct VoiceDataChannel {
     socket: UdpSocket,
     crypto: Option<(VoiceEncryption, VoiceDecryption)>,
     send_buf: Box<[u8; Self::VOICE_PACKET_MAX]>,
+    capture: Option<PacketCapture>,
+    counters: Arc<SendCounters>,
+}
+
+struct PacketCapture {
+    writer: PcapWriter<BufWriter<File>>,
+    local: SocketAddr,
+    remote: SocketAddr,
 }
 
 pub struct ReceivedRtpPacket {
@@ -42,6 +56,35 @@ pub enum VoicePacket {
     Rtcp(ReceivedRtcpPacket),
 }
 
+impl VoicePacket {
+    /// Parses a packet that has already been decrypted, like the ones in packet captures.
+    pub fn parse_decrypted(mut buffer: Vec<u8>) -> Option<Self> {
+        let packet = match demux_mut(&mut buffer) {
+            DemuxedMut::Rtp(packet) => {
+                // Only the fixed header, header extensions are skipped when decoding.
+                let header_size = 12 + 4 * packet.get_csrc_count() as usize;
+                let sequence_number = packet.get_sequence().into();
+                let timestamp = packet.get_timestamp().into();
+                let ssrc = packet.get_ssrc().into();
+
+                VoicePacket::Rtp(ReceivedRtpPacket {
+                    sequence_number,
+                    timestamp,
+                    ssrc,
+                    data_range: header_size.min(buffer.len())..buffer.len(),
+                    buffer,
+                })
+            }
+            DemuxedMut::Rtcp(_) => VoicePacket::Rtcp(ReceivedRtcpPacket {
+                decrypted_buffer: buffer,
+            }),
+            _ => return None,
+        };
+
+        Some(packet)
+    }
+}
+
 impl VoiceDataChannel {
     const VOICE_PACKET_MAX: usize = 1460;
 
@@ -54,7 +97,11 @@ impl VoiceDataChannel {
         ));
     }
 
-    pub async fn connect<A: ToSocketAddrs>(addr: A, ssrc: u32) -> anyhow::Result<Self> {
+    pub async fn connect<A: ToSocketAddrs>(
+        addr: A,
+        ssrc: u32,
+        counters: Arc<SendCounters>,
+    ) -> anyhow::Result<Self> {
         // todo: ipv6?
         let udp = UdpSocket::bind("0.0.0.0:0").await?;
         udp.connect(addr).await?;
@@ -110,9 +157,38 @@ impl VoiceDataChannel {
             sequence_no: thread_rng().next_u32() as u16,
             crypto: None,
             send_buf: Box::new([0; Self::VOICE_PACKET_MAX]),
+            capture: None,
+            counters,
         })
     }
 
+    /// Starts writing decrypted packets in both directions into [capture], or stops capturing
+    /// packets if it's `None`.
+    pub fn set_capture(
+        &mut self,
+        capture: Option<PcapWriter<BufWriter<File>>>,
+    ) -> anyhow::Result<()> {
+        let capture = match capture {
+            Some(writer) => Some(PacketCapture {
+                writer,
+                // The addresses are only used to make the packets look plausible in Wireshark.
+                local: SocketAddr::new(self.public_addr, self.public_port),
+                remote: self.socket.peer_addr()?,
+            }),
+            None => None,
+        };
+
+        if let Some(mut previous) = std::mem::replace(&mut self.capture, capture) {
+            previous.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Stops capturing packets and returns the writer, to go on capturing on another connection.
+    pub fn take_capture(&mut self) -> Option<PcapWriter<BufWriter<File>>> {
+        self.capture.take().map(|capture| capture.writer)
+    }
+
     pub async fn send_voice(&mut self, timestamp: u32, voice: &[u8]) -> anyhow::Result<()> {
         let seq_no = self.sequence_no;
         self.sequence_no = seq_no.wrapping_add(1);
@@ -136,10 +212,17 @@ impl VoiceDataChannel {
                 .copy_from_slice(&voice);
         }
 
+        if self.capture.is_some() {
+            let mut clear = bytes[..VoiceEncryption::RTP_HEADER_LEN].to_vec();
+            clear.extend_from_slice(voice);
+            capture_packet(&mut self.capture, SystemTime::now(), true, &clear);
+        }
+
         let Ok(size) = encrypt.encrypt_packet(bytes, payload_len) else {
             return Err(anyhow!("Could not encrypt"));
         };
         self.socket.send(&bytes[..size]).await?;
+        self.counters.record(payload_len);
 
         Ok(())
     }
@@ -147,19 +230,28 @@ impl VoiceDataChannel {
     pub async fn receive_packet(&mut self) -> anyhow::Result<VoicePacket> {
         let mut buffer = vec![0; Self::VOICE_PACKET_MAX];
         let len = self.socket.recv(&mut buffer).await?;
+        let received_at = SystemTime::now();
         buffer.truncate(len);
 
         let Some((_, ref decrypt)) = self.crypto else {
             bail!("Received packet, but crypto was not set up");
         };
 
-        Ok(match demux_mut(&mut buffer) {
+        let packet = match demux_mut(&mut buffer) {
             DemuxedMut::Rtp(mut packet) => {
                 let range = decrypt.decrypt_packet(&mut packet)?;
+                let header_size = packet.packet().len() - packet.payload().len();
 
                 let sequence = packet.get_sequence().into();
                 let timestamp = packet.get_timestamp().into();
                 let ssrc = packet.get_ssrc().into();
+
+                if self.capture.is_some() {
+                    let mut clear = buffer[..header_size].to_vec();
+                    clear.extend_from_slice(&buffer[range.clone()]);
+                    capture_packet(&mut self.capture, received_at, false, &clear);
+                }
+
                 VoicePacket::Rtp(ReceivedRtpPacket {
                     sequence_number: sequence,
                     timestamp,
@@ -174,6 +266,7 @@ impl VoiceDataChannel {
 
                 buffer.drain(range.end..); // Remove suffix, if any
                 buffer.drain(header_size..range.start); // Remove tag
+                capture_packet(&mut self.capture, received_at, false, &buffer);
 
                 VoicePacket::Rtcp(ReceivedRtcpPacket {
                     decrypted_buffer: buffer,
@@ -185,7 +278,31 @@ impl VoiceDataChannel {
             DemuxedMut::TooSmall => {
                 bail!("Illegal UDP packet from voice server.");
             }
-        })
+        };
+
+        Ok(packet)
+    }
+}
+
+fn capture_packet(
+    capture: &mut Option<PacketCapture>,
+    time: SystemTime,
+    outgoing: bool,
+    packet: &[u8],
+) {
+    let Some(active) = capture else {
+        return;
+    };
+
+    let (source, destination) = if outgoing {
+        (active.local, active.remote)
+    } else {
+        (active.remote, active.local)
+    };
+
+    if let Err(e) = active.writer.write_udp(time, source, destination, packet) {
+        warn!("Could not write packet capture, stopping it: {e}");
+        *capture = None;
     }
 }
 
@@ -1,8 +1,12 @@
 use std::fmt::Debug;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
 
-use anyhow::{anyhow, bail};
-use log::{info, trace, warn};
+use anyhow::{bail, Context};
+use log::{debug, info, trace, warn};
 use serde::Serialize;
 use serenity_voice_model::id::{GuildId, UserId};
 use serenity_voice_model::payload::Speaking;
@@ -16,6 +20,8 @@ use twilight_model::id::Id;
 
 use crate::discord::crypto::EncryptionMode;
 use crate::error::{ChanRes, DiscordError};
+use crate::stats::SendCounters;
+use crate::utils::pcap::PcapWriter;
 use crate::utils::{request_channel, RequestReceiver, RequestSender};
 
 use super::rtp::{VoiceDataChannel, VoicePacket};
@@ -24,6 +30,10 @@ use super::voice_gateway::GatewayConnection;
 
 enum VoiceTaskRequest {
     Write(OutgoingVoicePacket),
+    SetSpeaking(bool),
+    SetSelfMute(bool),
+    SetCapture { path: Option<PathBuf>, append: bool },
+    MoveTo(Id<ChannelMarker>),
     Close,
 }
 
@@ -34,12 +44,33 @@ pub struct OutgoingVoicePacket {
 
 type VoiceTaskResponse = ChanRes<()>;
 
+/// How the bot shows up in the voice channel.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VoiceStateFlags {
+    pub self_mute: bool,
+    pub self_deaf: bool,
+}
+
 #[derive(Debug)]
 pub enum VoiceEvent {
     Packet(VoicePacket),
-    UserJoined { ssrc: u32, user: Id<UserMarker> },
-    Speaking { user: Id<UserMarker>, ssrc: u32 },
-    UserLeft { user: Id<UserMarker> },
+    /// A user connected, [ssrc] is missing if they can't send audio. We never receive their
+    /// video, but its packets might still reach us.
+    UserJoined {
+        user: Id<UserMarker>,
+        ssrc: Option<u32>,
+        video_ssrcs: Vec<u32>,
+    },
+    Speaking {
+        user: Id<UserMarker>,
+        ssrc: u32,
+    },
+    UserLeft {
+        user: Id<UserMarker>,
+    },
+    /// Someone in the guild joined, left or changed their voice state. The cache has already
+    /// been updated when this is received.
+    VoiceStatesChanged,
     FullyConnected,
     Closed,
 }
@@ -104,6 +135,13 @@ struct VoiceTaskRunner {
     requests: RequestReceiver<VoiceTaskRequest, VoiceTaskResponse>,
     events: Sender<VoiceEvent>,
     gateway_events: mpsc::Receiver<Event>,
+    // A packet capture requested before the data channel was connected.
+    pending_capture: Option<PcapWriter<BufWriter<File>>>,
+    send_counters: Arc<SendCounters>,
+    // Sent along with every update of our voice state.
+    flags: VoiceStateFlags,
+    // The speaking state requested last, applied once the session is up if it came earlier.
+    speaking: bool,
     close_requested: bool,
 }
 
@@ -114,6 +152,8 @@ impl VoiceTaskHandle {
         user: Id<UserMarker>,
         guild: Id<GuildMarker>,
         channel: Id<ChannelMarker>,
+        send_counters: Arc<SendCounters>,
+        flags: VoiceStateFlags,
     ) -> Self {
         let (event_sender, event_receiver) = mpsc::channel(32);
         let (send, receive) = request_channel();
@@ -128,6 +168,10 @@ impl VoiceTaskHandle {
                 state: VoiceTaskState::default(),
                 requests: receive,
                 gateway_events,
+                pending_capture: None,
+                send_counters,
+                flags,
+                speaking: true,
                 close_requested: false,
             };
             runner.run().await;
@@ -147,6 +191,54 @@ impl VoiceTaskHandle {
             .map_err(|e| DiscordError::InternalError { source: e.into() })?
     }
 
+    /// Updates the speaking indicator shown for the bot in Discord.
+    pub async fn set_speaking(&self, speaking: bool) -> ChanRes<()> {
+        self.sender
+            .request(VoiceTaskRequest::SetSpeaking(speaking))
+            .await
+            .map_err(|e| DiscordError::InternalError { source: e.into() })?
+    }
+
+    /// Shows the bot as muted in Discord, or not.
+    pub async fn set_self_mute(&self, muted: bool) -> ChanRes<()> {
+        self.sender
+            .request(VoiceTaskRequest::SetSelfMute(muted))
+            .await
+            .map_err(|e| DiscordError::InternalError { source: e.into() })?
+    }
+
+    /// Starts writing the packets of this call into a pcap file at [path], or stops an active
+    /// capture if it's `None`.
+    pub async fn set_capture(&self, path: Option<PathBuf>) -> ChanRes<()> {
+        self.sender
+            .request(VoiceTaskRequest::SetCapture {
+                path,
+                append: false,
+            })
+            .await
+            .map_err(|e| DiscordError::InternalError { source: e.into() })?
+    }
+
+    /// Goes on with the capture at [path] that another voice task of the call has written to.
+    pub async fn continue_capture(&self, path: PathBuf) -> ChanRes<()> {
+        self.sender
+            .request(VoiceTaskRequest::SetCapture {
+                path: Some(path),
+                append: true,
+            })
+            .await
+            .map_err(|e| DiscordError::InternalError { source: e.into() })?
+    }
+
+    /// Moves the bot to another voice [channel] in the same guild. The voice session is set up
+    /// again, [VoiceEvent::FullyConnected] is sent once it's ready.
+    pub async fn move_to(&self, channel: Id<ChannelMarker>) -> ChanRes<()> {
+        self.sender
+            .request(VoiceTaskRequest::MoveTo(channel))
+            .await
+            .map_err(|e| DiscordError::InternalError { source: e.into() })?
+    }
+
     pub async fn leave_and_close(self) {
         let _ = self.sender.request(VoiceTaskRequest::Close).await;
         let _ = self.task.await;
@@ -185,21 +277,75 @@ impl VoiceTaskRunner {
                             .send_voice(write.timestamp, &write.opus_payload)
                             .await
                             .map_err(|e| DiscordError::InternalError { source: e }),
-                        _ => Err(DiscordError::InternalError {
-                            source: anyhow!("Voice not set up yet."),
-                        }),
+                        // Asterisk may write early media before we're connected, there's nobody
+                        // to send it to yet.
+                        _ => {
+                            trace!("Dropping audio written before the voice session started");
+                            Ok(())
+                        }
+                    };
+
+                    let _ = response.send(res);
+                }
+                VoiceTaskRequest::SetSpeaking(speaking) => {
+                    self.speaking = speaking;
+                    let res = match &self.state {
+                        VoiceTaskState::Connected {
+                            gateway,
+                            voice,
+                            has_session: true,
+                        } => gateway
+                            .send(serenity_voice_model::Event::Speaking(Speaking {
+                                delay: Some(0),
+                                speaking: if speaking {
+                                    SpeakingState::MICROPHONE
+                                } else {
+                                    SpeakingState::empty()
+                                },
+                                ssrc: voice.ssrc,
+                                user_id: None,
+                            }))
+                            .await
+                            .map_err(|e| DiscordError::InternalError { source: e }),
+                        // Sent once the session starts.
+                        _ => Ok(()),
                     };
 
                     let _ = response.send(res);
                 }
+                VoiceTaskRequest::SetSelfMute(muted) => {
+                    self.flags.self_mute = muted;
+                    // Repeating the join updates our voice state without moving anywhere.
+                    let res = self
+                        .register_join_intent()
+                        .map_err(|e| DiscordError::InternalError { source: e });
+                    let _ = response.send(res);
+                }
+                VoiceTaskRequest::SetCapture { path, append } => {
+                    let res = self
+                        .set_capture(path, append)
+                        .map_err(|e| DiscordError::InternalError { source: e });
+                    let _ = response.send(res);
+                }
+                VoiceTaskRequest::MoveTo(channel) => {
+                    let res = self
+                        .move_to(channel)
+                        .await
+                        .map_err(|e| DiscordError::InternalError { source: e });
+                    let _ = response.send(res);
+                }
                 VoiceTaskRequest::Close => {
                     let _ = response.send(Ok(()));
                     self.close_requested = true;
                 }
             },
             VoiceTaskEvent::GlobalEvent { event } => {
+                if let Event::VoiceStateUpdate(_) = &event {
+                    let _ = self.events.send(VoiceEvent::VoiceStatesChanged).await;
+                }
+
                 if let VoiceTaskState::WaitingForEvents(waiting) = &mut self.state {
-                    if waiting.apply(&event) {
+                    if waiting.apply(&event, self.user) {
                         let gateway = waiting.start_gateway(&self.user).await;
                         self.state = VoiceTaskState::WaitingForReady {
                             gateway: Some(gateway),
@@ -220,11 +366,18 @@ impl VoiceTaskRunner {
                                 .max()
                                 .ok_or(anyhow::anyhow!("Did not find an encryption mode"))?;
 
-                            let Ok(voice) =
-                                VoiceDataChannel::connect((event.ip, event.port), event.ssrc).await
+                            let Ok(mut voice) = VoiceDataChannel::connect(
+                                (event.ip, event.port),
+                                event.ssrc,
+                                self.send_counters.clone(),
+                            )
+                            .await
                             else {
                                 bail!("Could not connect to voice channel");
                             };
+                            if let Err(e) = voice.set_capture(self.pending_capture.take()) {
+                                warn!("Could not start packet capture: {e:?}");
+                            }
 
                             let gateway = gateway.take().unwrap();
                             let _ = gateway
@@ -280,6 +433,18 @@ impl VoiceTaskRunner {
                                         user_id: None,
                                     }))
                                     .await?;
+
+                                // The call may have gone silent before we were connected.
+                                if !self.speaking {
+                                    gateway
+                                        .send(serenity_voice_model::Event::Speaking(Speaking {
+                                            delay: Some(0),
+                                            speaking: SpeakingState::empty(),
+                                            ssrc: voice.ssrc,
+                                            user_id: None,
+                                        }))
+                                        .await?;
+                                }
                             }
 
                             *has_session = true;
@@ -287,11 +452,18 @@ impl VoiceTaskRunner {
                         }
                     }
                     voice_gateway::VoiceEvent::ClientConnect(connect) => {
+                        // Discord sends retransmissions of a video stream with the next SSRC.
+                        let video_ssrcs = match connect.video_ssrc {
+                            0 => vec![],
+                            ssrc => vec![ssrc, ssrc.wrapping_add(1)],
+                        };
+
                         let _ = self
                             .events
                             .send(VoiceEvent::UserJoined {
-                                ssrc: connect.audio_ssrc,
                                 user: Id::new(connect.user_id.0),
+                                ssrc: Some(connect.audio_ssrc).filter(|ssrc| *ssrc != 0),
+                                video_ssrcs,
                             })
                             .await;
                     }
@@ -326,6 +498,27 @@ impl VoiceTaskRunner {
         Ok(())
     }
 
+    fn set_capture(&mut self, path: Option<PathBuf>, append: bool) -> anyhow::Result<()> {
+        let capture = match path {
+            Some(path) => {
+                let writer = match append {
+                    true => PcapWriter::append(&path),
+                    false => PcapWriter::create(&path),
+                };
+                Some(writer.with_context(|| format!("Could not create packet capture {path:?}"))?)
+            }
+            None => None,
+        };
+
+        match &mut self.state {
+            VoiceTaskState::Connected { voice, .. } => voice.set_capture(capture),
+            _ => {
+                self.pending_capture = capture;
+                Ok(())
+            }
+        }
+    }
+
     async fn wait_for_event(&mut self) -> VoiceTaskEvent {
         let (gateway, rtp) = self.state.sockets_mut();
         let events = &mut self.events;
@@ -366,6 +559,27 @@ impl VoiceTaskRunner {
         }
     }
 
+    /// Closes the session with the current voice server and asks to join [channel] instead. Both
+    /// the voice server and our session change, so we wait for them like when first joining.
+    async fn move_to(&mut self, channel: Id<ChannelMarker>) -> anyhow::Result<()> {
+        debug!("Moving from voice channel {} to {channel}", self.channel);
+        let (gateway, _) = self.state.sockets_mut();
+        if let Some(gateway) = gateway {
+            let _ = gateway.close().await;
+        }
+
+        // The capture goes on once we're connected to the next voice server.
+        if let VoiceTaskState::Connected { voice, .. } = &mut self.state {
+            if let Some(capture) = voice.take_capture() {
+                self.pending_capture = Some(capture);
+            }
+        }
+
+        self.channel = channel;
+        self.state = VoiceTaskState::default();
+        self.register_join_intent()
+    }
+
     async fn close(&mut self) {
         trace!("Closing voice task runner");
         let (gateway, _) = self.state.sockets_mut();
@@ -406,8 +620,8 @@ impl VoiceTaskRunner {
             d: UpdateRequest {
                 guild_id: self.guild,
                 channel_id: Some(self.channel),
-                self_deaf: false,
-                self_mute: false,
+                self_deaf: self.flags.self_deaf,
+                self_mute: self.flags.self_mute,
             },
         })?)?;
 
@@ -451,9 +665,11 @@ impl Default for VoiceTaskState {
 }
 
 impl WaitForConnectInfo {
-    fn apply(&mut self, event: &Event) -> bool {
+    /// Takes the connection details from [event], voice states of other users than our [user]
+    /// are ignored.
+    fn apply(&mut self, event: &Event, user: Id<UserMarker>) -> bool {
         match event {
-            Event::VoiceStateUpdate(update) => {
+            Event::VoiceStateUpdate(update) if update.user_id == user => {
                 self.guild_id = update.guild_id;
                 self.channel_id = update.channel_id;
                 self.session_id = Some(update.session_id.clone());
@@ -1,4 +1,5 @@
 use thiserror::Error;
+use twilight_model::guild::Permissions;
 
 pub type ChanRes<T> = Result<T, DiscordError>;
 
@@ -15,4 +16,10 @@ pub enum DiscordError {
     AlreadyInChannelOnServer,
     #[error("Could not encode data to opus")]
     EncodeError,
+    #[error("The bot is missing permissions in the voice channel: {missing:?}")]
+    MissingPermissions { missing: Permissions },
+    #[error("The voice channel is full")]
+    ChannelFull,
+    #[error("Calls to the voice channel are not allowed: {reason}")]
+    NotAllowed { reason: String },
 }
@@ -1,4 +1,9 @@
+pub mod access;
+pub mod audio;
 pub mod constants;
+pub mod dial;
 pub mod discord;
 pub mod error;
+pub mod stats;
+pub mod timeouts;
 pub mod utils;
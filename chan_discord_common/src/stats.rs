@@ -0,0 +1,234 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use twilight_model::id::{marker::UserMarker, Id};
+
+use crate::constants::SAMPLE_RATE;
+
+/// Counters for the packets we sent, shared between the voice data channel and the call.
+#[derive(Debug, Default)]
+pub struct SendCounters {
+    packets: AtomicU64,
+    bytes: AtomicU64,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SendStats {
+    pub packets: u64,
+    /// Opus payload bytes, without RTP headers and encryption overhead.
+    pub bytes: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReceiveStats {
+    pub packets: u64,
+    /// Packets missing from the sequence numbers we've seen, unless they arrived out of order.
+    pub lost: u64,
+    /// Packets that arrived too late and were dropped by the jitter buffer.
+    pub late: u64,
+    pub decode_errors: u64,
+    /// Interarrival jitter as in RFC 3550, section 6.4.1.
+    pub jitter: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParticipantStats {
+    pub user: Id<UserMarker>,
+    pub ssrc: u32,
+    pub received: ReceiveStats,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CallStats {
+    pub sent: SendStats,
+    pub participants: Vec<ParticipantStats>,
+}
+
+/// Counts the packets received from a single SSRC.
+#[derive(Default)]
+pub struct ReceiveCounter {
+    packets: u64,
+    decode_errors: u64,
+    lost: u64,
+    epoch: Option<Instant>,
+    // Arrival time (in samples since the epoch) and RTP timestamp of the last packet.
+    last: Option<(i64, u32)>,
+    // In samples
+    jitter: f64,
+}
+
+impl SendCounters {
+    pub fn record(&self, payload_len: usize) {
+        self.packets.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(payload_len as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> SendStats {
+        SendStats {
+            packets: self.packets.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl ReceiveCounter {
+    pub fn record_packet(&mut self, timestamp: u32, now: Instant) {
+        self.packets += 1;
+
+        let epoch = *self.epoch.get_or_insert(now);
+        let arrival = (now - epoch).as_micros() as i64 * SAMPLE_RATE as i64 / 1_000_000;
+        if let Some((last_arrival, last_timestamp)) = self.last {
+            let transit_difference =
+                (arrival - last_arrival) - timestamp.wrapping_sub(last_timestamp) as i32 as i64;
+            self.jitter += (transit_difference.abs() as f64 - self.jitter) / 16.0;
+        }
+        self.last = Some((arrival, timestamp));
+    }
+
+    pub fn record_decode_error(&mut self) {
+        self.decode_errors += 1;
+    }
+
+    /// Counts packets missing from a gap in the sequence numbers.
+    pub fn record_lost(&mut self, count: u64) {
+        self.lost += count;
+    }
+
+    /// Counts a packet that arrived out of order, filling a gap counted as lost before.
+    pub fn record_reordered(&mut self) {
+        self.lost = self.lost.saturating_sub(1);
+    }
+
+    /// The counters so far, [late] is taken from the jitter buffer.
+    pub fn stats(&self, late: u64) -> ReceiveStats {
+        ReceiveStats {
+            packets: self.packets,
+            lost: self.lost,
+            late,
+            decode_errors: self.decode_errors,
+            jitter: Duration::from_secs_f64(self.jitter / SAMPLE_RATE as f64),
+        }
+    }
+}
+
+impl ParticipantStats {
+    /// The counters in the style of `RTPAUDIOQOS`.
+    pub fn qos(&self) -> String {
+        let received = &self.received;
+        format!(
+            "user={};ssrc={};rxcount={};lp={};late={};decodeerrors={};rxjitter={:.6}",
+            self.user,
+            self.ssrc,
+            received.packets,
+            received.lost,
+            received.late,
+            received.decode_errors,
+            received.jitter.as_secs_f64()
+        )
+    }
+}
+
+impl CallStats {
+    /// Totals over all participants in the style of `RTPAUDIOQOS`.
+    pub fn qos(&self) -> String {
+        let received = self.participants.iter().map(|p| &p.received);
+        let total = |counter: fn(&ReceiveStats) -> u64| received.clone().map(counter).sum::<u64>();
+        let max_jitter = received.clone().map(|r| r.jitter).max().unwrap_or_default();
+
+        format!(
+            "txcount={};txbytes={};rxcount={};lp={};late={};decodeerrors={};maxrxjitter={:.6}",
+            self.sent.packets,
+            self.sent.bytes,
+            total(|r| r.packets),
+            total(|r| r.lost),
+            total(|r| r.late),
+            total(|r| r.decode_errors),
+            max_jitter.as_secs_f64()
+        )
+    }
+
+    /// The counters of each participant, separated by commas.
+    pub fn participants_qos(&self) -> String {
+        self.participants
+            .iter()
+            .map(ParticipantStats::qos)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use twilight_model::id::Id;
+
+    use super::{CallStats, ParticipantStats, ReceiveCounter, SendStats};
+
+    #[test]
+    fn reordered_packets_are_not_lost() {
+        let now = Instant::now();
+        let mut counter = ReceiveCounter::default();
+
+        counter.record_packet(0, now);
+        counter.record_lost(3);
+        counter.record_packet(960 * 4, now);
+        counter.record_reordered();
+        counter.record_packet(960 * 2, now);
+
+        let stats = counter.stats(0);
+        assert_eq!(stats.packets, 3);
+        assert_eq!(stats.lost, 2);
+    }
+
+    #[test]
+    fn jitter_follows_arrival_times() {
+        let start = Instant::now();
+        let mut regular = ReceiveCounter::default();
+        let mut irregular = ReceiveCounter::default();
+
+        for i in 0..50u16 {
+            let timestamp = i as u32 * 960;
+            let arrival = start + Duration::from_millis(20 * i as u64);
+            let delay = Duration::from_millis(if i % 2 == 0 { 0 } else { 10 });
+
+            regular.record_packet(timestamp, arrival);
+            irregular.record_packet(timestamp, arrival + delay);
+        }
+
+        assert_eq!(regular.stats(0).jitter, Duration::ZERO);
+        let jitter = irregular.stats(0).jitter.as_secs_f64();
+        assert!((0.009..=0.010).contains(&jitter), "{jitter}");
+    }
+
+    #[test]
+    fn formats_qos() {
+        let now = Instant::now();
+        let mut counter = ReceiveCounter::default();
+        counter.record_packet(0, now);
+        counter.record_lost(1);
+        counter.record_packet(0, now);
+        counter.record_decode_error();
+
+        let stats = CallStats {
+            sent: SendStats {
+                packets: 10,
+                bytes: 800,
+            },
+            participants: vec![ParticipantStats {
+                user: Id::new(42),
+                ssrc: 7,
+                received: counter.stats(1),
+            }],
+        };
+
+        assert_eq!(
+            stats.qos(),
+            "txcount=10;txbytes=800;rxcount=2;lp=1;late=1;decodeerrors=1;maxrxjitter=0.000000"
+        );
+        assert_eq!(
+            stats.participants_qos(),
+            "user=42;ssrc=7;rxcount=2;lp=1;late=1;decodeerrors=1;rxjitter=0.000000"
+        );
+    }
+}
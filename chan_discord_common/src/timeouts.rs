@@ -0,0 +1,163 @@
+use std::time::{Duration, Instant};
+
+use crate::dial::CallTimeouts;
+
+/// Why we hung up a call ourselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HangupReason {
+    Empty,
+    MaxDuration,
+    Idle,
+}
+
+/// Follows when the [CallTimeouts] of a call started running. The current time is passed in by
+/// callers.
+#[derive(Debug)]
+pub struct CallTimers {
+    timeouts: CallTimeouts,
+    // Since when there are no humans in the voice channel.
+    empty_since: Option<Instant>,
+    answered_at: Option<Instant>,
+    // When voiced audio was last sent or received, once connected.
+    last_audio: Option<Instant>,
+}
+
+impl HangupReason {
+    /// The value of `DISCORD_HANGUP_REASON`.
+    pub fn name(self) -> &'static str {
+        match self {
+            HangupReason::Empty => "empty",
+            HangupReason::MaxDuration => "maxduration",
+            HangupReason::Idle => "idle",
+        }
+    }
+}
+
+impl CallTimers {
+    pub fn new(timeouts: CallTimeouts) -> Self {
+        Self {
+            timeouts,
+            empty_since: None,
+            answered_at: None,
+            last_audio: None,
+        }
+    }
+
+    /// Starts the idle timeout, which doesn't run before we're connected.
+    pub fn connected(&mut self, now: Instant) {
+        self.last_audio = Some(now);
+    }
+
+    /// Starts the maximum duration.
+    pub fn answered(&mut self, now: Instant) {
+        self.answered_at = Some(now);
+    }
+
+    pub fn answered_at(&self) -> Option<Instant> {
+        self.answered_at
+    }
+
+    /// Starts or stops the timeout for calls without participants.
+    pub fn set_empty(&mut self, empty: bool, now: Instant) {
+        if !empty {
+            self.empty_since = None;
+        } else if self.empty_since.is_none() {
+            self.empty_since = Some(now);
+        }
+    }
+
+    /// Restarts the idle timeout if [voiced] audio was sent or received. Silence keeps the
+    /// timeout running, as a bridged phone sends it just as steadily as speech.
+    pub fn note_audio(&mut self, voiced: bool, now: Instant) {
+        if voiced && self.last_audio.is_some() {
+            self.last_audio = Some(now);
+        }
+    }
+
+    /// The earliest timeout that would hang up the call, and why.
+    pub fn next_timeout(&self) -> Option<(Instant, HangupReason)> {
+        let deadline = |since: Option<Instant>, timeout: Option<Duration>, reason| {
+            let timeout = timeout.filter(|timeout| !timeout.is_zero())?;
+            Some((since? + timeout, reason))
+        };
+
+        [
+            deadline(self.empty_since, self.timeouts.empty, HangupReason::Empty),
+            deadline(
+                self.answered_at,
+                self.timeouts.max_duration,
+                HangupReason::MaxDuration,
+            ),
+            deadline(self.last_audio, self.timeouts.idle, HangupReason::Idle),
+        ]
+        .into_iter()
+        .flatten()
+        .min_by_key(|(deadline, _)| *deadline)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::dial::CallTimeouts;
+
+    use super::{CallTimers, HangupReason};
+
+    #[test]
+    fn silent_frames_dont_keep_idle_calls_up() {
+        let start = Instant::now();
+        let mut timers = CallTimers::new(CallTimeouts {
+            idle: Some(Duration::from_secs(10)),
+            ..Default::default()
+        });
+        assert_eq!(timers.next_timeout(), None);
+        timers.connected(start);
+
+        // A bridged phone sends a silent frame every 20ms in both directions.
+        for frame in 1..=1000 {
+            timers.note_audio(false, start + Duration::from_millis(20 * frame));
+        }
+        assert_eq!(
+            timers.next_timeout(),
+            Some((start + Duration::from_secs(10), HangupReason::Idle))
+        );
+
+        let speech = start + Duration::from_secs(5);
+        timers.note_audio(true, speech);
+        assert_eq!(
+            timers.next_timeout(),
+            Some((speech + Duration::from_secs(10), HangupReason::Idle))
+        );
+    }
+
+    #[test]
+    fn picks_the_earliest_timeout() {
+        let start = Instant::now();
+        let mut timers = CallTimers::new(CallTimeouts {
+            empty: Some(Duration::from_secs(30)),
+            max_duration: Some(Duration::from_secs(60)),
+            idle: Some(Duration::ZERO),
+        });
+        timers.connected(start);
+        timers.answered(start);
+        assert_eq!(
+            timers.next_timeout(),
+            Some((start + Duration::from_secs(60), HangupReason::MaxDuration))
+        );
+
+        timers.set_empty(true, start + Duration::from_secs(10));
+        // Still empty, which doesn't restart the timeout.
+        timers.set_empty(true, start + Duration::from_secs(20));
+        assert_eq!(
+            timers.next_timeout(),
+            Some((start + Duration::from_secs(40), HangupReason::Empty))
+        );
+
+        timers.set_empty(false, start + Duration::from_secs(25));
+        assert_eq!(
+            timers.next_timeout(),
+            Some((start + Duration::from_secs(60), HangupReason::MaxDuration))
+        );
+    }
+}
@@ -1,6 +1,5 @@
 mod request_channel;
+pub mod pcap;
 pub mod rtp;
-#[cfg(feature = "rtplog")]
-pub mod rtp_log;
 
 pub use request_channel::{request_channel, RequestError, RequestReceiver, RequestSender};
@@ -0,0 +1,381 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Writes UDP datagrams into a pcap file that can be opened in Wireshark.
+///
+/// We only see the UDP payloads, so each packet gets made-up IP and UDP headers for the addresses
+/// it was sent between.
+pub struct PcapWriter<W: Write> {
+    writer: W,
+}
+
+/// Reads UDP datagrams from a pcap file, skipping over all other packets.
+pub struct PcapReader<R: Read> {
+    reader: R,
+    big_endian: bool,
+    nanoseconds: bool,
+    link_type: u32,
+}
+
+pub struct CapturedDatagram {
+    pub time: SystemTime,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub payload: Vec<u8>,
+}
+
+const LINKTYPE_ETHERNET: u32 = 1;
+// Raw IP packets, the IP version is taken from the header of each packet.
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const SNAPLEN: u32 = 65535;
+
+const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const UDP_HEADER_LEN: usize = 8;
+const PROTOCOL_UDP: u8 = 17;
+const TTL: u8 = 64;
+
+impl PcapWriter<BufWriter<File>> {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+
+    /// Continues a capture that was written to [path] before, or creates it if it doesn't exist.
+    pub fn append(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        let writer = BufWriter::new(file);
+        match writer.get_ref().metadata()?.len() {
+            0 => Self::new(writer),
+            _ => Ok(Self { writer }),
+        }
+    }
+}
+
+impl<W: Write> PcapWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(&0xa1b2c3d4u32.to_le_bytes())?; // magic, microsecond timestamps
+        writer.write_all(&2u16.to_le_bytes())?; // major version
+        writer.write_all(&4u16.to_le_bytes())?; // minor version
+        writer.write_all(&0i32.to_le_bytes())?; // timezone offset
+        writer.write_all(&0u32.to_le_bytes())?; // timestamp accuracy
+        writer.write_all(&SNAPLEN.to_le_bytes())?;
+        writer.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+
+        Ok(Self { writer })
+    }
+
+    /// Writes a UDP datagram with [payload] sent from [source] to [destination] at [time].
+    pub fn write_udp(
+        &mut self,
+        time: SystemTime,
+        source: SocketAddr,
+        destination: SocketAddr,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let udp_len = UDP_HEADER_LEN + payload.len();
+        let mut packet = Vec::with_capacity(IPV6_HEADER_LEN + udp_len);
+
+        match (source.ip(), destination.ip()) {
+            (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                let total_len = (IPV4_HEADER_LEN + udp_len) as u16;
+                packet.extend_from_slice(&[0x45, 0]); // version, header length, DSCP
+                packet.extend_from_slice(&total_len.to_be_bytes());
+                packet.extend_from_slice(&[0, 0, 0x40, 0]); // identification, don't fragment
+                packet.extend_from_slice(&[TTL, PROTOCOL_UDP, 0, 0]); // checksum set below
+                packet.extend_from_slice(&source.octets());
+                packet.extend_from_slice(&destination.octets());
+
+                let checksum = ipv4_checksum(&packet);
+                packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+            }
+            (source, destination) => {
+                packet.extend_from_slice(&[0x60, 0, 0, 0]); // version, traffic class, flow label
+                packet.extend_from_slice(&(udp_len as u16).to_be_bytes());
+                packet.extend_from_slice(&[PROTOCOL_UDP, TTL]);
+                packet.extend_from_slice(&to_ipv6(source).octets());
+                packet.extend_from_slice(&to_ipv6(destination).octets());
+            }
+        }
+
+        packet.extend_from_slice(&source.port().to_be_bytes());
+        packet.extend_from_slice(&destination.port().to_be_bytes());
+        packet.extend_from_slice(&(udp_len as u16).to_be_bytes());
+        // The UDP checksum is optional for IPv4, and Wireshark doesn't verify it by default.
+        packet.extend_from_slice(&0u16.to_be_bytes());
+        packet.extend_from_slice(payload);
+
+        let time = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let captured_len = packet.len().min(SNAPLEN as usize);
+        self.writer
+            .write_all(&(time.as_secs() as u32).to_le_bytes())?;
+        self.writer.write_all(&time.subsec_micros().to_le_bytes())?;
+        self.writer
+            .write_all(&(captured_len as u32).to_le_bytes())?;
+        self.writer
+            .write_all(&(packet.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&packet[..captured_len])
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<R: Read> PcapReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 24];
+        reader.read_exact(&mut header)?;
+
+        let (big_endian, nanoseconds) = match header[..4] {
+            [0xd4, 0xc3, 0xb2, 0xa1] => (false, false),
+            [0x4d, 0x3c, 0xb2, 0xa1] => (false, true),
+            [0xa1, 0xb2, 0xc3, 0xd4] => (true, false),
+            [0xa1, 0xb2, 0x3c, 0x4d] => (true, true),
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "Not a pcap file (pcapng is not supported)",
+                ))
+            }
+        };
+
+        let mut reader = Self {
+            reader,
+            big_endian,
+            nanoseconds,
+            link_type: 0,
+        };
+        reader.link_type = reader.u32(&header[20..24]);
+        if ![
+            LINKTYPE_ETHERNET,
+            LINKTYPE_RAW,
+            LINKTYPE_IPV4,
+            LINKTYPE_IPV6,
+        ]
+        .contains(&reader.link_type)
+        {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Unsupported link type {}", reader.link_type),
+            ));
+        }
+
+        Ok(reader)
+    }
+
+    /// Reads the next UDP datagram, or returns `None` at the end of the file.
+    pub fn next_datagram(&mut self) -> io::Result<Option<CapturedDatagram>> {
+        loop {
+            let mut header = [0; 16];
+            match self.reader.read_exact(&mut header) {
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                res => res?,
+            };
+
+            let seconds = self.u32(&header[0..4]) as u64;
+            let fraction = self.u32(&header[4..8]) as u64;
+            let captured_len = self.u32(&header[8..12]) as usize;
+
+            let mut data = vec![0; captured_len];
+            self.reader.read_exact(&mut data)?;
+
+            let since_epoch = Duration::from_secs(seconds)
+                + if self.nanoseconds {
+                    Duration::from_nanos(fraction)
+                } else {
+                    Duration::from_micros(fraction)
+                };
+
+            let ip_packet = match self.link_type {
+                LINKTYPE_ETHERNET => data.get(14..).unwrap_or_default(),
+                _ => &data[..],
+            };
+            if let Some((source, destination, payload)) = parse_udp(ip_packet) {
+                return Ok(Some(CapturedDatagram {
+                    time: UNIX_EPOCH + since_epoch,
+                    source,
+                    destination,
+                    payload: payload.to_vec(),
+                }));
+            }
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes.try_into().unwrap();
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+}
+
+/// Extracts addresses and payload of a UDP datagram in an IPv4 or IPv6 packet.
+fn parse_udp(packet: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let (source, destination, udp): (IpAddr, IpAddr, _) = match packet.first()? >> 4 {
+        4 => {
+            let header_len = (packet[0] & 0x0f) as usize * 4;
+            if *packet.get(9)? != PROTOCOL_UDP {
+                return None;
+            }
+
+            let source: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let destination: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            (
+                Ipv4Addr::from(source).into(),
+                Ipv4Addr::from(destination).into(),
+                packet.get(header_len..)?,
+            )
+        }
+        6 => {
+            // Extension headers aren't supported, we don't write them.
+            if *packet.get(6)? != PROTOCOL_UDP {
+                return None;
+            }
+
+            let source: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let destination: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            (
+                Ipv6Addr::from(source).into(),
+                Ipv6Addr::from(destination).into(),
+                packet.get(IPV6_HEADER_LEN..)?,
+            )
+        }
+        _ => return None,
+    };
+
+    let source_port = u16::from_be_bytes(udp.get(0..2)?.try_into().ok()?);
+    let destination_port = u16::from_be_bytes(udp.get(2..4)?.try_into().ok()?);
+    let udp_len = u16::from_be_bytes(udp.get(4..6)?.try_into().ok()?) as usize;
+    let payload = udp.get(UDP_HEADER_LEN..udp_len.clamp(UDP_HEADER_LEN, udp.len()))?;
+
+    Some((
+        SocketAddr::new(source, source_port),
+        SocketAddr::new(destination, destination_port),
+        payload,
+    ))
+}
+
+fn to_ipv6(addr: IpAddr) -> Ipv6Addr {
+    match addr {
+        IpAddr::V4(addr) => addr.to_ipv6_mapped(),
+        IpAddr::V6(addr) => addr,
+    }
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum: u32 = header
+        .chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], word[1]]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{ipv4_checksum, PcapReader, PcapWriter};
+
+    #[test]
+    fn ipv4_header_checksum() {
+        // Example from https://en.wikipedia.org/wiki/Internet_checksum
+        let header = hex::decode("45000073000040004011b861c0a80001c0a800c7").unwrap();
+        assert_eq!(ipv4_checksum(&header), 0);
+
+        let mut header = header;
+        header[10..12].fill(0);
+        assert_eq!(ipv4_checksum(&header), 0xb861);
+    }
+
+    #[test]
+    fn writes_udp_packets() {
+        let mut writer = PcapWriter::new(vec![]).unwrap();
+        writer
+            .write_udp(
+                UNIX_EPOCH + Duration::from_micros(1_500_000),
+                "192.168.0.1:1234".parse().unwrap(),
+                "192.168.0.2:5678".parse().unwrap(),
+                &[1, 2, 3],
+            )
+            .unwrap();
+        let data = writer.into_inner();
+
+        assert_eq!(data.len(), 24 + 16 + 20 + 8 + 3);
+        assert_eq!(data[20..24], 101u32.to_le_bytes());
+
+        let (record, packet) = data[24..].split_at(16);
+        assert_eq!(record[..4], 1u32.to_le_bytes());
+        assert_eq!(record[4..8], 500_000u32.to_le_bytes());
+        assert_eq!(record[8..12], 31u32.to_le_bytes());
+
+        assert_eq!(packet[0], 0x45);
+        assert_eq!(ipv4_checksum(&packet[..20]), 0);
+        assert_eq!(packet[12..16], [192, 168, 0, 1]);
+        assert_eq!(packet[20..22], 1234u16.to_be_bytes());
+        assert_eq!(packet[22..24], 5678u16.to_be_bytes());
+        assert_eq!(packet[24..26], 11u16.to_be_bytes());
+        assert_eq!(packet[28..], [1, 2, 3]);
+    }
+
+    #[test]
+    fn reads_written_packets() {
+        let mut writer = PcapWriter::new(vec![]).unwrap();
+        let time = UNIX_EPOCH + Duration::from_micros(1_000_020);
+        let v4 = ("10.0.0.1:1".parse().unwrap(), "10.0.0.2:2".parse().unwrap());
+        let v6 = ("[::1]:3".parse().unwrap(), "10.0.0.2:4".parse().unwrap());
+        writer.write_udp(time, v4.0, v4.1, &[1, 2]).unwrap();
+        writer.write_udp(time, v6.0, v6.1, &[3]).unwrap();
+
+        let mut reader = PcapReader::new(Cursor::new(writer.into_inner())).unwrap();
+
+        let first = reader.next_datagram().unwrap().unwrap();
+        assert_eq!(first.time, time);
+        assert_eq!((first.source, first.destination), v4);
+        assert_eq!(first.payload, [1, 2]);
+
+        let second = reader.next_datagram().unwrap().unwrap();
+        assert_eq!(second.source, v6.0);
+        assert_eq!(second.destination, "[::ffff:10.0.0.2]:4".parse().unwrap());
+        assert_eq!(second.payload, [3]);
+
+        assert!(reader.next_datagram().unwrap().is_none());
+    }
+
+    #[test]
+    fn appends_to_captures() {
+        let path = std::env::temp_dir().join(format!("append-{}.pcap", std::process::id()));
+        let time = UNIX_EPOCH + Duration::from_secs(1);
+        let addresses = ("10.0.0.1:1".parse().unwrap(), "10.0.0.2:2".parse().unwrap());
+
+        for payload in [[1], [2]] {
+            let mut writer = PcapWriter::append(&path).unwrap();
+            writer
+                .write_udp(time, addresses.0, addresses.1, &payload)
+                .unwrap();
+            writer.flush().unwrap();
+        }
+
+        let mut reader = PcapReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.next_datagram().unwrap().unwrap().payload, [1]);
+        assert_eq!(reader.next_datagram().unwrap().unwrap().payload, [2]);
+        assert!(reader.next_datagram().unwrap().is_none());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -29,6 +29,15 @@ pub fn request_channel<Req, Res>() -> (RequestSender<Req, Res>, RequestReceiver<
     )
 }
 
+// Not derived, which would require the requests and responses to be Clone.
+impl<Req, Res> Clone for RequestSender<Req, Res> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
 impl<Req, Res> RequestSender<Req, Res> {
     pub async fn request(&self, request: Req) -> Result<Res, RequestError> {
         let (tx, rx) = oneshot::channel();
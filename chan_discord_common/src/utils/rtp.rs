@@ -1,38 +1,200 @@
 use std::ops::Range;
 
-pub fn skip_over_extensions(packet: &Vec<u8>, payload: Range<usize>) -> Option<Range<usize>> {
-    let (start, end) = (payload.start, payload.end);
-    let mut original_payload = packet[payload].iter();
+/// The one-byte RTP header extensions of a received packet.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HeaderExtensions<'a> {
+    elements: &'a [u8],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExtensionElement<'a> {
+    pub id: u8,
+    pub data: &'a [u8],
+}
+
+/// The level of the audio in a packet as measured by the sender, see RFC 6464.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioLevel {
+    /// Attenuation below the loudest possible signal, from 0 to 127 dB.
+    pub attenuation: u8,
+    /// Whether the sender thinks that the packet contains speech.
+    pub voice: bool,
+}
+
+struct ExtensionElements<'a> {
+    remaining: &'a [u8],
+}
+
+/// Splits the payload of a received packet into its header extensions and the Opus data.
+pub fn parse_extensions(
+    packet: &[u8],
+    payload: Range<usize>,
+) -> Option<(HeaderExtensions<'_>, Range<usize>)> {
+    let data = &packet[payload.clone()];
 
     // Not documented anywhere, taken from https://github.com/discord-jda/JDA/blob/ca1da012650c9be33cfef47681a2076767dbc58d/src/main/java/net/dv8tion/jda/internal/audio/AudioPacket.java#L110
-    // This is explicitly not rfc8285 even though it may kind of look like it.
-    if *original_payload.next()? != 0xBE || *original_payload.next()? != 0xDE {
-        return Some(start..end);
-    }
-    let entries = {
-        let hi = *original_payload.next()?;
-        let lo = *original_payload.next()?;
-        (hi as usize) << 8 | (lo as usize)
+    // The extension header is sent unencrypted along with the RTP header, but the elements are
+    // encrypted, so they end up at the start of the decrypted payload.
+    let [0xBE, 0xDE, hi, lo, ..] = *data else {
+        return Some((HeaderExtensions::default(), payload));
     };
+    let length = 4 * ((hi as usize) << 8 | (lo as usize));
+
+    let elements = data.get(4..4 + length)?;
+    Some((
+        HeaderExtensions { elements },
+        payload.start + 4 + length..payload.end,
+    ))
+}
+
+impl<'a> HeaderExtensions<'a> {
+    // As negotiated in the SDP of Discord's clients.
+    pub const AUDIO_LEVEL_ID: u8 = 1;
+    pub const TRANSPORT_CC_ID: u8 = 5;
 
-    for _ in 0..entries * 4 {
-        original_payload.next()?;
+    pub fn iter(&self) -> impl Iterator<Item = ExtensionElement<'a>> {
+        ExtensionElements {
+            remaining: self.elements,
+        }
     }
 
-    let skipped_bytes = end - start - original_payload.len();
-    let start = start + skipped_bytes;
+    pub fn get(&self, id: u8) -> Option<&'a [u8]> {
+        self.iter()
+            .find(|element| element.id == id)
+            .map(|element| element.data)
+    }
+
+    pub fn audio_level(&self) -> Option<AudioLevel> {
+        let [level] = *self.get(Self::AUDIO_LEVEL_ID)? else {
+            return None;
+        };
+
+        Some(AudioLevel {
+            attenuation: level & 0x7F,
+            voice: level & 0x80 != 0,
+        })
+    }
+
+    /// The transport-wide sequence number used for congestion control.
+    pub fn transport_sequence_number(&self) -> Option<u16> {
+        let [hi, lo] = *self.get(Self::TRANSPORT_CC_ID)? else {
+            return None;
+        };
+
+        Some(u16::from_be_bytes([hi, lo]))
+    }
+}
+
+impl AudioLevel {
+    pub fn db(&self) -> f32 {
+        -(self.attenuation as f32)
+    }
+}
+
+impl<'a> Iterator for ExtensionElements<'a> {
+    type Item = ExtensionElement<'a>;
 
-    Some((start)..end)
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (&header, rest) = self.remaining.split_first()?;
+
+            // Padding between elements
+            if header == 0 {
+                self.remaining = rest;
+                continue;
+            }
+
+            let id = header >> 4;
+            let length = (header & 0x0F) as usize + 1;
+            // Id 15 ends the extension block, and elements running past its end are malformed.
+            if id == 15 || rest.len() < length {
+                self.remaining = &[];
+                return None;
+            }
+
+            let (data, rest) = rest.split_at(length);
+            self.remaining = rest;
+            return Some(ExtensionElement { id, data });
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::skip_over_extensions;
+    use super::{parse_extensions, AudioLevel, ExtensionElement, HeaderExtensions};
+
+    type Elements = Vec<(u8, Vec<u8>)>;
+
+    // The elements and the start of the Opus data
+    fn parse(hex: &str) -> Option<(Elements, usize)> {
+        let data = hex::decode(hex).unwrap();
+        parse_extensions(&data, 0..data.len()).map(|(extensions, range)| {
+            assert_eq!(range.end, data.len());
+            let elements = extensions
+                .iter()
+                .map(|ExtensionElement { id, data }| (id, data.to_vec()))
+                .collect();
+            (elements, range.start)
+        })
+    }
+
+    #[test]
+    fn parses_elements_and_payload() {
+        let parsed = parse("BEDE000232DF690410FF9000F8FFFE");
+
+        let (elements, start) = parsed.unwrap();
+        assert_eq!(start, 12);
+        assert_eq!(
+            elements,
+            vec![
+                (3, vec![0xDF, 0x69, 0x04]),
+                (1, vec![0xFF]),
+                (9, vec![0x00]),
+            ]
+        );
+    }
 
     #[test]
-    fn skip_over_extensions_valid() {
-        let data = hex::decode("BEDE000232DF690410FF9000F8FFFE").unwrap();
-        let range = skip_over_extensions(&data, 0..data.len());
-        assert_eq!(range, Some(12..data.len()));
+    fn payload_without_extensions() {
+        let parsed = parse("F8FFFE");
+        assert_eq!(parsed, Some((vec![], 0)));
+
+        let parsed = parse("");
+        assert_eq!(parsed, Some((vec![], 0)));
+    }
+
+    #[test]
+    fn truncated_extension_block() {
+        let parsed = parse("BEDE000232DF6904");
+        assert_eq!(parsed, None);
+    }
+
+    #[test]
+    fn stops_at_malformed_elements() {
+        // The second element claims four bytes, but only one is left.
+        let parsed = parse("BEDE000210AA0000000033BBF8");
+        assert_eq!(parsed, Some((vec![(1, vec![0xAA])], 12)));
+
+        // Id 15 ends the block even if more elements follow.
+        let parsed = parse("BEDE000110AAF0BBF8");
+        assert_eq!(parsed, Some((vec![(1, vec![0xAA])], 8)));
+    }
+
+    #[test]
+    fn reads_audio_level_and_transport_sequence_number() {
+        let data = hex::decode("BEDE0002109E5101F4000000F8").unwrap();
+        let (extensions, range) = parse_extensions(&data, 0..data.len()).unwrap();
+
+        assert_eq!(range, 12..13);
+        assert_eq!(
+            extensions.audio_level(),
+            Some(AudioLevel {
+                attenuation: 30,
+                voice: true,
+            })
+        );
+        assert_eq!(extensions.audio_level().unwrap().db(), -30.0);
+        assert_eq!(extensions.transport_sequence_number(), Some(0x01F4));
+        assert_eq!(HeaderExtensions::default().audio_level(), None);
     }
 }
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Context;
+use chan_discord_common::constants::SAMPLE_RATE;
+use chan_discord_common::discord::rtp::{ReceivedRtpPacket, VoicePacket};
+use chan_discord_common::utils::pcap::PcapReader;
+use rusqlite::{Connection, OpenFlags};
+
+pub struct CapturedPacket {
+    /// Time since the first packet of the capture.
+    pub arrival: Duration,
+    pub packet: VoicePacket,
+}
+
+/// Loads the packets received from Discord, either from a pcap file or from an `rtp_packets`
+/// SQLite table.
+pub fn load(path: &Path, local: Option<SocketAddr>) -> anyhow::Result<Vec<CapturedPacket>> {
+    let is_pcap = path
+        .extension()
+        .is_some_and(|extension| extension == "pcap" || extension == "cap");
+
+    let mut packets = if is_pcap {
+        load_pcap(path, local)?
+    } else {
+        load_sqlite(path)?
+    };
+    packets.sort_by_key(|packet| packet.arrival);
+
+    Ok(packets)
+}
+
+fn load_pcap(path: &Path, local: Option<SocketAddr>) -> anyhow::Result<Vec<CapturedPacket>> {
+    let file = File::open(path).with_context(|| format!("Could not open {path:?}"))?;
+    let mut reader = PcapReader::new(BufReader::new(file))?;
+
+    let mut datagrams = vec![];
+    while let Some(datagram) = reader.next_datagram()? {
+        datagrams.push(datagram);
+    }
+
+    // Captures contain the packets we sent as well. We never send RTCP, so the destination of the
+    // first RTCP packet tells us which address is ours.
+    let local = local.or_else(|| {
+        datagrams
+            .iter()
+            .find(|datagram| {
+                matches!(
+                    VoicePacket::parse_decrypted(datagram.payload.clone()),
+                    Some(VoicePacket::Rtcp(_))
+                )
+            })
+            .map(|datagram| datagram.destination)
+    });
+    if local.is_none() {
+        eprintln!("Could not tell which packets were sent by Discord, replaying all of them");
+    }
+
+    let Some(start) = datagrams.first().map(|datagram| datagram.time) else {
+        return Ok(vec![]);
+    };
+
+    Ok(datagrams
+        .into_iter()
+        .filter(|datagram| Some(datagram.source) != local)
+        .filter_map(|datagram| {
+            Some(CapturedPacket {
+                arrival: datagram.time.duration_since(start).unwrap_or_default(),
+                packet: VoicePacket::parse_decrypted(datagram.payload)?,
+            })
+        })
+        .collect())
+}
+
+fn load_sqlite(path: &Path) -> anyhow::Result<Vec<CapturedPacket>> {
+    let database = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Could not open {path:?}"))?;
+    let mut statement =
+        database.prepare("SELECT ssrc, timestamp, seq_no, data FROM rtp_packets ORDER BY rowid")?;
+    let rows = statement.query_map((), |row| {
+        Ok((
+            row.get::<_, u32>(0)?,
+            row.get::<_, u32>(1)?,
+            row.get::<_, u16>(2)?,
+            row.get::<_, Vec<u8>>(3)?,
+        ))
+    })?;
+
+    // The table doesn't record arrival times. Rows were inserted as packets arrived, so every SSRC
+    // starts when its first row was logged and then follows its RTP timestamps.
+    let mut clock = Duration::ZERO;
+    let mut streams = HashMap::new();
+    let mut packets = vec![];
+    for row in rows {
+        let (ssrc, timestamp, sequence_number, data) = row?;
+
+        let (first_timestamp, first_arrival) = *streams.entry(ssrc).or_insert((timestamp, clock));
+        let offset = timestamp.wrapping_sub(first_timestamp) as i32;
+        let expected = first_arrival
+            + Duration::from_micros(offset.max(0) as u64 * 1_000_000 / SAMPLE_RATE as u64);
+        clock = clock.max(expected);
+
+        packets.push(CapturedPacket {
+            arrival: clock,
+            packet: VoicePacket::Rtp(ReceivedRtpPacket {
+                sequence_number,
+                timestamp,
+                ssrc,
+                data_range: 0..data.len(),
+                buffer: data,
+            }),
+        });
+    }
+
+    Ok(packets)
+}
@@ -0,0 +1,174 @@
+//! Replays a captured Discord call through the receive pipeline of chan_discord, without Asterisk
+//! or Discord, to reproduce problems with received audio.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context};
+use chan_discord_common::audio::adaptive_jitter::{AdaptiveConfig, AdaptiveJitterBuffer};
+use chan_discord_common::audio::jitter::{FixedDelayBuffer, JitterBuffer};
+use chan_discord_common::audio::receiver::{FetchResult, ReceivePipeline};
+use chan_discord_common::audio::recording::CallRecording;
+use chan_discord_common::constants::SAMPLE_RATE;
+use chan_discord_common::discord::rtp::VoicePacket;
+use twilight_model::id::Id;
+
+use crate::input::CapturedPacket;
+
+mod input;
+
+const USAGE: &str = "Usage: rtp_replay [options] <capture> <output directory>
+
+Replays the RTP packets in <capture> through the decoding, adaptive jitter buffer and mixing
+of chan_discord and writes replay-mixed.wav and one WAV file per SSRC into <output directory>.
+
+<capture> is a pcap file written by `discord capture start` or DISCORD_PCAP, or a SQLite
+database with an rtp_packets table.
+
+Options:
+    --local <ip:port>  Our address in the capture, packets sent from it are ignored
+    --delay <ms>       Use a jitter buffer with this fixed delay instead of the adaptive one";
+
+struct Options {
+    capture: PathBuf,
+    output: PathBuf,
+    local: Option<SocketAddr>,
+    // The delay of a fixed jitter buffer, the adaptive one is used without it.
+    delay: Option<Duration>,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match run(options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> anyhow::Result<Options> {
+    let mut local = None;
+    let mut delay = None;
+    let mut positional = vec![];
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--local" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| anyhow!("Missing value for --local"))?;
+                local = Some(value.parse().context("Invalid address for --local")?);
+            }
+            "--delay" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| anyhow!("Missing value for --delay"))?;
+                let millis = value.parse().context("Invalid value for --delay")?;
+                delay = Some(Duration::from_millis(millis));
+            }
+            _ if arg.starts_with("--") => bail!("Unknown option {arg}"),
+            _ => positional.push(PathBuf::from(arg)),
+        }
+    }
+
+    let [capture, output] = <[PathBuf; 2]>::try_from(positional)
+        .map_err(|_| anyhow!("Expected a capture and an output directory"))?;
+
+    Ok(Options {
+        capture,
+        output,
+        local,
+        delay,
+    })
+}
+
+fn run(options: Options) -> anyhow::Result<()> {
+    let packets = input::load(&options.capture, options.local)?;
+    println!("Replaying {} packets", packets.len());
+
+    let mut recording = CallRecording::new(&options.output, "replay")?;
+    let mut mixed = recording.mixed_track();
+    // Simulated time, starting with the first packet of the capture.
+    let start = recording.start();
+
+    let delay = options.delay;
+    let mut pipeline = ReceivePipeline::new(Box::new(move |_| -> Box<dyn JitterBuffer> {
+        match delay {
+            Some(delay) => Box::new(FixedDelayBuffer::new(delay)),
+            None => Box::new(AdaptiveJitterBuffer::new(AdaptiveConfig::default())),
+        }
+    }));
+    pipeline.set_recording(Some(recording));
+
+    let mut packets = packets.into_iter().peekable();
+    let mut now = start;
+    let mut mixed_position = 0;
+    let mut stalled = false;
+    loop {
+        let arrival = packets.peek().map(|packet| start + packet.arrival);
+        let due = pipeline.next_frame_time().filter(|_| !stalled);
+
+        match (arrival, due) {
+            (Some(arrival), due) if due.map_or(true, |due| arrival <= due) => {
+                let CapturedPacket { packet, .. } = packets.next().unwrap();
+                now = now.max(arrival);
+
+                if let VoicePacket::Rtp(rtp) = &packet {
+                    if !pipeline.is_mapped(rtp.ssrc) {
+                        // Captures don't say which user sent which SSRC, so the SSRC doubles as
+                        // the user id.
+                        pipeline.map_user_id(Id::new(u64::from(rtp.ssrc).max(1)), rtp.ssrc)?;
+                    }
+                }
+                pipeline.handle_packet(packet, now);
+                stalled = false;
+            }
+            (_, Some(due)) => {
+                now = now.max(due);
+                match pipeline.fetch(now) {
+                    FetchResult::Block(block) => {
+                        // Blocks fetched at the same time are played one after the other.
+                        let position = duration_to_samples(now - start).max(mixed_position);
+                        mixed.write(position as u32, now, &block);
+                        mixed_position = position + block.len() as u64;
+                    }
+                    FetchResult::CheckBackLater { .. } => {}
+                    FetchResult::NoneQueued => {
+                        // Nothing was due after all, wait for the next packet instead of asking
+                        // again for the same time.
+                        stalled = pipeline.next_frame_time() == Some(due);
+                    }
+                }
+            }
+            (_, None) => break,
+        }
+    }
+
+    mixed.finish();
+    pipeline.finish_capture();
+    for participant in pipeline.stats() {
+        println!("{}", participant.qos());
+    }
+    println!(
+        "Wrote {:.1}s of mixed audio into {}",
+        mixed_position as f64 / SAMPLE_RATE as f64,
+        options.output.display()
+    );
+
+    Ok(())
+}
+
+fn duration_to_samples(duration: Duration) -> u64 {
+    (duration.as_micros() * SAMPLE_RATE as u128 / 1_000_000) as u64
+}